@@ -0,0 +1,746 @@
+//! Assembler for the classic Cowgod/Chipper mnemonic syntax, the same syntax the
+//! disassembler writes:
+//!
+//! ```text
+//! ; Comments run to the end of the line
+//! SPEED   EQU 4               ; Constants, also written SPEED = 4
+//!         INCLUDE "sprites.c8s"
+//! start:  LD I, ball          ; Labels end with a colon
+//!         DRW V0, V1, 4
+//!         ADD V0, SPEED
+//!         JP start
+//! ball:   DB 0x60, 0xF0, 0xF0, 0x60
+//! table:  DW start, ball + 2
+//! ```
+//!
+//! Numbers are decimal, hex (`0x1F`, `$1F`, `#1F`) or binary (`0b101`, `%101`), and can be
+//! combined with `+ - * / % & | ^ << >> ~` and parentheses. Instructions are encoded from
+//! the same opcode table the CPU decodes with.
+
+use std::{collections::HashMap, fmt, fs, path::Path, rc::Rc};
+
+use crate::{chip8::PROGRAM_START, opcodes::{parse_register, Opcode, Operand, OPCODES}};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 64;
+const MAX_NESTING: usize = 64; // Brackets and unary operators within one value
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError{
+
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError{}
+
+#[derive(Debug, Clone)]
+struct Location{
+
+    file: Rc<str>,
+    line: usize,
+}
+
+impl Location{
+
+    fn error(&self, message: impl Into<String>) -> AsmError{
+
+        AsmError{ file: self.file.to_string(), line: self.line, message: message.into() }
+    }
+}
+
+enum Symbol{
+
+    Label(u16),
+    Constant(String, Location), // Evaluated when used, so it may refer to later labels
+}
+
+enum Body{
+
+    Instruction(&'static Opcode, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Statement{
+
+    location: Location,
+    body: Body,
+}
+
+/// Assembles `source`. Includes are looked up relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError>{
+
+    let mut assembler = Assembler::new();
+    assembler.read_source("<source>", source, Path::new("."), 0)?;
+    assembler.finish()
+}
+
+/// Assembles the file at `path`. Includes are looked up relative to the including file.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError>{
+
+    let mut assembler = Assembler::new();
+    let source = fs::read_to_string(path).map_err(|e| AsmError{ file: path.display().to_string(), line: 0, message: e.to_string() })?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    assembler.read_source(&path.display().to_string(), &source, &dir, 0)?;
+    assembler.finish()
+}
+
+struct Assembler{
+
+    symbols: HashMap<String, Symbol>,
+    statements: Vec<Statement>,
+    addr: u16,
+}
+
+impl Assembler{
+
+    fn new() -> Assembler{
+
+        Assembler{ symbols: HashMap::new(), statements: Vec::new(), addr: PROGRAM_START }
+    }
+
+    // First pass: defines the symbols and works out where every statement goes
+    fn read_source(&mut self, name: &str, source: &str, dir: &Path, depth: usize) -> Result<(), AsmError>{
+
+        let file: Rc<str> = Rc::from(name);
+        for (index, text) in source.lines().enumerate(){
+
+            let location = Location{ file: Rc::clone(&file), line: index + 1 };
+            self.read_line(text, location, dir, depth)?;
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self, text: &str, location: Location, dir: &Path, depth: usize) -> Result<(), AsmError>{
+
+        let mut rest = strip_comment(text).trim();
+
+        // Label, unless the colon is inside an operand
+        if let Some((label, after)) = rest.split_once(':')
+            && is_identifier(label.trim()){
+
+            self.define(label.trim(), Symbol::Label(self.addr), &location)?;
+            rest = after.trim();
+        }
+        if rest.is_empty(){
+
+            return Ok(());
+        }
+
+        let (word, operands) = split_word(rest);
+
+        // NAME EQU value / NAME = value
+        let (second, value) = split_word(operands);
+        if is_identifier(word) && (second.eq_ignore_ascii_case("EQU") || second == "="){
+
+            if value.is_empty(){
+
+                return Err(location.error(format!("constant '{}' needs a value", word)));
+            }
+            return self.define(word, Symbol::Constant(value.to_string(), location.clone()), &location);
+        }
+        if let Some(value) = operands.strip_prefix('=')
+            && is_identifier(word){
+
+            return self.define(word, Symbol::Constant(value.trim().to_string(), location.clone()), &location);
+        }
+
+        let mnemonic = word.to_ascii_uppercase();
+        let operands = split_operands(operands);
+        let body = match mnemonic.as_str(){
+
+            "INCLUDE" => {
+
+                let [name] = operands.as_slice() else{
+
+                    return Err(location.error("INCLUDE needs one file name"));
+                };
+                let name = parse_string(name).ok_or(location.error("INCLUDE needs a quoted file name"))?;
+                if depth >= MAX_INCLUDE_DEPTH{
+
+                    return Err(location.error("includes are nested too deeply, is a file including itself?"));
+                }
+                let path = dir.join(&name);
+                let source = fs::read_to_string(&path).map_err(|e| location.error(format!("could not include {}: {}", path.display(), e)))?;
+                let include_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                return self.read_source(&path.display().to_string(), &source, &include_dir, depth + 1);
+            },
+            "DB" => {
+
+                let len: usize = operands.iter().map(|operand| parse_string(operand).map_or(1, |text| text.len())).sum();
+                self.advance(len, &location)?;
+                Body::Bytes(operands)
+            },
+            "DW" => {
+
+                self.advance(operands.len() * 2, &location)?;
+                Body::Words(operands)
+            },
+            _ => {
+
+                let (entry, operands) = find_opcode(&mnemonic, operands).map_err(|message| location.error(message))?;
+                self.advance(entry.length() as usize, &location)?;
+                Body::Instruction(entry, operands)
+            }
+        };
+        self.statements.push(Statement{ location, body });
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<(), AsmError>{
+
+        if is_reserved(name){
+
+            return Err(location.error(format!("'{}' is a reserved word and can't be a label or constant", name)));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some(){
+
+            return Err(location.error(format!("'{}' is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, len: usize, location: &Location) -> Result<(), AsmError>{
+
+        self.addr = u16::try_from(self.addr as usize + len).map_err(|_| location.error("program doesn't fit in 64KB of memory"))?;
+        Ok(())
+    }
+
+    // Second pass: every symbol is known now, so the operands can be evaluated
+    fn finish(self) -> Result<Vec<u8>, AsmError>{
+
+        let mut rom = Vec::new();
+        for statement in &self.statements{
+
+            let location = &statement.location;
+            let value = |text: &str, min: i64, max: i64| -> Result<i64, AsmError>{
+
+                let value = self.evaluate(text, 0).map_err(|message| location.error(message))?;
+                if value < min || value > max{
+
+                    let shown = if text.trim() == value.to_string() { text.to_string() } else { format!("{} = {}", text, value) };
+                    return Err(location.error(format!("{} is out of range ({}..={})", shown, min, max)));
+                }
+                Ok(value)
+            };
+
+            match &statement.body{
+
+                Body::Instruction(entry, operands) => {
+
+                    let mut opcode = entry.pattern;
+                    let mut long = None;
+                    for (operand, text) in entry.operands.iter().zip(operands){
+
+                        match operand{
+
+                            Operand::Vx => opcode |= (parse_register(text).unwrap_or(0) as u16) << 8,
+                            Operand::Vy => opcode |= (parse_register(text).unwrap_or(0) as u16) << 4,
+                            Operand::Range => {
+
+                                let (x, y) = parse_range(text).unwrap_or((0, 0));
+                                opcode |= (x as u16) << 8 | (y as u16) << 4;
+                            },
+                            Operand::Byte => opcode |= (value(text, -128, 0xFF)? as u16) & 0xFF,
+                            Operand::Addr => opcode |= value(text, 0, 0xFFF)? as u16,
+                            Operand::Nibble => opcode |= value(text, 0, 0xF)? as u16,
+                            Operand::Planes => opcode |= (value(text, 0, 0xF)? as u16) << 8,
+                            Operand::Long => long = Some(value(strip_long(text).unwrap_or(text), 0, 0xFFFF)? as u16),
+                            Operand::Keyword(_) => {},
+                        }
+                    }
+                    rom.extend_from_slice(&opcode.to_be_bytes());
+                    if let Some(address) = long{
+
+                        rom.extend_from_slice(&address.to_be_bytes());
+                    }
+                },
+                Body::Bytes(items) => {
+
+                    for item in items{
+
+                        match parse_string(item){
+
+                            Some(text) => rom.extend_from_slice(text.as_bytes()),
+                            None => rom.push(value(item, -128, 0xFF)? as u8),
+                        }
+                    }
+                },
+                Body::Words(items) => {
+
+                    for item in items{
+
+                        rom.extend_from_slice(&(value(item, -0x8000, 0xFFFF)? as u16).to_be_bytes());
+                    }
+                },
+            }
+        }
+        Ok(rom)
+    }
+
+    fn evaluate(&self, text: &str, depth: usize) -> Result<i64, String>{
+
+        let tokens = tokenize(text)?;
+        let mut parser = ExprParser{ tokens: &tokens, position: 0, assembler: self, depth, nesting: 0 };
+        let value = parser.binary(0)?;
+        if parser.position < tokens.len(){
+
+            return Err(format!("unexpected '{}' in '{}'", tokens[parser.position], text));
+        }
+        Ok(value)
+    }
+
+    fn lookup(&self, name: &str, depth: usize) -> Result<i64, String>{
+
+        match self.symbols.get(name){
+
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant(text, location)) => {
+
+                if depth >= MAX_CONSTANT_DEPTH{
+
+                    return Err(format!("constant '{}' refers to itself", name));
+                }
+                self.evaluate(text, depth + 1).map_err(|message| format!("{} (in '{}' at {}:{})", message, name, location.file, location.line))
+            },
+            None => Err(format!("undefined symbol '{}'", name)),
+        }
+    }
+}
+
+// Picks the table entry for a mnemonic from the shape of its operands; values are
+// checked in the second pass once labels are known
+fn find_opcode(mnemonic: &str, mut operands: Vec<String>) -> Result<(&'static Opcode, Vec<String>), String>{
+
+    // SHR Vx and SHL Vx shift Vx in place
+    if (mnemonic == "SHR" || mnemonic == "SHL") && operands.len() == 1{
+
+        operands.push(operands[0].clone());
+    }
+
+    let mut candidates = OPCODES.iter().filter(|entry| entry.mnemonic == mnemonic).peekable();
+    if candidates.peek().is_none(){
+
+        return Err(format!("unknown instruction '{}'", mnemonic));
+    }
+    candidates
+        .find(|entry| entry.operands.len() == operands.len() && entry.operands.iter().zip(&operands).all(|(operand, text)| fits(*operand, text)))
+        .map(|entry| (entry, operands.clone()))
+        .ok_or(format!("invalid operands for {}: '{}'", mnemonic, operands.join(", ")))
+}
+
+fn fits(operand: Operand, text: &str) -> bool{
+
+    match operand{
+
+        Operand::Vx | Operand::Vy => parse_register(text).is_some(),
+        Operand::Range => parse_range(text).is_some(),
+        Operand::Keyword(keyword) => text.eq_ignore_ascii_case(keyword),
+        Operand::Long => strip_long(text).is_some(),
+        Operand::Byte | Operand::Addr | Operand::Nibble | Operand::Planes => {
+
+            !text.is_empty() && parse_register(text).is_none() && parse_range(text).is_none() && strip_long(text).is_none() && !is_reserved(text)
+        },
+    }
+}
+
+fn is_reserved(word: &str) -> bool{
+
+    const RESERVED: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+    RESERVED.iter().any(|reserved| word.eq_ignore_ascii_case(reserved)) || parse_register(word).is_some()
+}
+
+fn parse_range(text: &str) -> Option<(usize, usize)>{
+
+    let (x, y) = text.split_once('-')?;
+    Some((parse_register(x.trim())?, parse_register(y.trim())?))
+}
+
+fn strip_long(text: &str) -> Option<&str>{
+
+    let (word, rest) = split_word(text);
+    (word.eq_ignore_ascii_case("LONG") && !rest.is_empty()).then_some(rest)
+}
+
+fn parse_string(text: &str) -> Option<String>{
+
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.to_string())
+}
+
+fn is_identifier(text: &str) -> bool{
+
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn split_word(text: &str) -> (&str, &str){
+
+    match text.split_once(char::is_whitespace){
+
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+// A ';' inside a string literal doesn't start a comment
+fn strip_comment(text: &str) -> &str{
+
+    let mut in_string = false;
+    for (index, c) in text.char_indices(){
+
+        match c{
+
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..index],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn split_operands(text: &str) -> Vec<String>{
+
+    if text.trim().is_empty(){
+
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    for c in text.chars(){
+
+        match c{
+
+            '"' => {
+
+                in_string = !in_string;
+                current.push(c);
+            },
+            ',' if !in_string => operands.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    operands.push(current.trim().to_string());
+    operands
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token{
+
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 13] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String>{
+
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty(){
+
+        let c = rest.chars().next().unwrap_or_default();
+        // % is modulo after a value and a binary prefix anywhere else
+        let after_value = matches!(tokens.last(), Some(Token::Number(_) | Token::Name(_) | Token::Symbol(")")));
+        let (prefix, radix) = if let Some(hex) = rest.strip_prefix("0x").or(rest.strip_prefix("0X")){
+
+            (hex, 16)
+        }else if let Some(binary) = rest.strip_prefix("0b").or(rest.strip_prefix("0B")){
+
+            (binary, 2)
+        }else if let Some(hex) = rest.strip_prefix('$').or(rest.strip_prefix('#')){
+
+            (hex, 16)
+        }else if let Some(binary) = rest.strip_prefix('%').filter(|_| !after_value){
+
+            (binary, 2)
+        }else if c.is_ascii_digit(){
+
+            (rest, 10)
+        }else{
+
+            ("", 0)
+        };
+
+        if radix != 0{
+
+            let end = prefix.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(prefix.len());
+            let digits = &prefix[..end];
+            let value = i64::from_str_radix(digits, radix).map_err(|_| format!("'{}' is not a number", &rest[..rest.len() - prefix.len() + end]))?;
+            tokens.push(Token::Number(value));
+            rest = &prefix[end..];
+        }else if is_identifier(&c.to_string()){
+
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        }else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)){
+
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }else{
+
+            return Err(format!("unexpected '{}' in '{}'", c, text));
+        }
+        rest = rest.trim_start();
+    }
+    if tokens.is_empty(){
+
+        return Err("missing value".to_string());
+    }
+    Ok(tokens)
+}
+
+// Precedence climbing over C's binary operators
+struct ExprParser<'a>{
+
+    tokens: &'a [Token],
+    position: usize,
+    assembler: &'a Assembler,
+    depth: usize,
+    nesting: usize,
+}
+
+fn precedence(symbol: &str) -> Option<u8>{
+
+    match symbol{
+
+        "|" => Some(1),
+        "^" => Some(2),
+        "&" => Some(3),
+        "<<" | ">>" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+impl ExprParser<'_>{
+
+    fn binary(&mut self, min_precedence: u8) -> Result<i64, String>{
+
+        let mut left = self.unary()?;
+        while let Some(Token::Symbol(symbol)) = self.tokens.get(self.position)
+            && let Some(precedence) = precedence(symbol)
+            && precedence > min_precedence{
+
+            self.position += 1;
+            let right = self.binary(precedence)?;
+            left = match *symbol{
+
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.checked_shl(right as u32).ok_or("shift is too large")?,
+                ">>" => left.checked_shr(right as u32).ok_or("shift is too large")?,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if right == 0 => return Err("division by zero".to_string()),
+                "/" => left.checked_div(right).ok_or("division overflows")?,
+                _ => left.checked_rem(right).ok_or("division overflows")?,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, String>{
+
+        if self.nesting >= MAX_NESTING{
+
+            return Err(format!("value is nested more than {} deep", MAX_NESTING));
+        }
+        self.nesting += 1;
+        let value = self.operand();
+        self.nesting -= 1;
+        value
+    }
+
+    fn operand(&mut self) -> Result<i64, String>{
+
+        let token = self.tokens.get(self.position).cloned().ok_or("value ends too early")?;
+        self.position += 1;
+        match token{
+
+            Token::Number(value) => Ok(value),
+            Token::Name(name) => self.assembler.lookup(&name, self.depth),
+            Token::Symbol("-") => Ok(self.unary()?.wrapping_neg()),
+            Token::Symbol("+") => self.unary(),
+            Token::Symbol("~") => Ok(!self.unary()?),
+            Token::Symbol("(") => {
+
+                let value = self.binary(0)?;
+                match self.tokens.get(self.position){
+
+                    Some(Token::Symbol(")")) => {
+
+                        self.position += 1;
+                        Ok(value)
+                    },
+                    _ => Err("missing ')'".to_string()),
+                }
+            },
+            token => Err(format!("unexpected '{}'", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn error(source: &str) -> String{
+
+        assemble(source).unwrap_err().to_string()
+    }
+
+    // How each operand is written in the round trip, the way the disassembler writes it back
+    fn sample(operand: Operand) -> &'static str{
+
+        match operand{
+
+            Operand::Vx => "VA",
+            Operand::Vy => "V5",
+            Operand::Range => "V2-V7",
+            Operand::Byte => "0x12",
+            Operand::Addr => "0x345",
+            Operand::Nibble => "6",
+            Operand::Planes => "2",
+            Operand::Long => "LONG 0x1234",
+            Operand::Keyword(keyword) => keyword,
+        }
+    }
+
+    #[test]
+    fn every_opcode_round_trips_through_the_decoder(){
+
+        for entry in OPCODES{
+
+            let operands: Vec<&str> = entry.operands.iter().map(|&operand| sample(operand)).collect();
+            let source = if operands.is_empty() { entry.mnemonic.to_string() } else { format!("{} {}", entry.mnemonic, operands.join(", ")) };
+            let rom = assemble(&source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+            assert_eq!(rom.len(), entry.length() as usize, "{}", source);
+
+            let opcode = u16::from_be_bytes([rom[0], rom[1]]);
+            let next = rom.get(2..4).map_or(0, |word| u16::from_be_bytes([word[0], word[1]]));
+            assert!(entry.matches(opcode), "{} assembled to {:04X}", source, opcode);
+            assert_eq!(Cpu::decode_instruction_pair(opcode, next).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn labels_and_shorthands(){
+
+        let rom = assemble("start: CLS\n  SHR V1\nloop: JP loop\n  CALL start\n  LD I, data\ndata: DB 0xAA").unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0x81, 0x16, 0x12, 0x04, 0x22, 0x00, 0xA2, 0x0A, 0xAA]);
+    }
+
+    #[test]
+    fn constants(){
+
+        let source = "\
+SPEED EQU 4
+DOUBLE = SPEED * 2
+        LD V0, DOUBLE     ; 8
+        LD V1, END - START
+START:  ADD V0, SPEED
+END:";
+        assert_eq!(assemble(source).unwrap(), vec![0x60, 0x08, 0x61, 0x02, 0x70, 0x04]);
+        let cycle = error("ONE = TWO\nTWO = ONE\nLD V0, ONE");
+        assert!(cycle.starts_with("<source>:3: constant '") && cycle.contains("' refers to itself (in '"), "{}", cycle);
+    }
+
+    #[test]
+    fn data(){
+
+        let rom = assemble("DB 1, 0x02, \"AB;C\", -1\nDW 0x1234, end, -2\nend:").unwrap();
+        assert_eq!(rom, vec![0x01, 0x02, b'A', b'B', b';', b'C', 0xFF, 0x12, 0x34, 0x02, 0x0D, 0xFF, 0xFE]);
+        assert_eq!(error("DB 256"), "<source>:1: 256 is out of range (-128..=255)");
+        assert_eq!(error("DW 0x10000"), "<source>:1: 0x10000 = 65536 is out of range (-32768..=65535)");
+    }
+
+    #[test]
+    fn expressions(){
+
+        let rom = assemble("DB 1 + 2 * 3, (1 + 2) * 3, 1 << 2 + 1, 0xF0 | 0x0F & 0x3C, ~0 & 0xFF, 10 % 3, %101, $1F - #0F, 0b11 ^ 1, -(2 - 5), 7 / 2").unwrap();
+        assert_eq!(rom, vec![7, 9, 8, 0xFC, 0xFF, 1, 5, 0x10, 2, 3, 3]);
+        assert_eq!(error("DB 1 / 0"), "<source>:1: division by zero");
+        assert_eq!(error("DB 1 % 0"), "<source>:1: division by zero");
+        assert_eq!(error("DB (-0x7FFFFFFFFFFFFFFF - 1) / -1"), "<source>:1: division overflows");
+        assert_eq!(error("DB (-0x7FFFFFFFFFFFFFFF - 1) % -1"), "<source>:1: division overflows");
+        assert_eq!(assemble("DB -(~0x7FFFFFFFFFFFFFFF) & 0xFF").unwrap(), [0]);
+        assert_eq!(assemble(&format!("DB {}1{}", "(".repeat(63), ")".repeat(63))).unwrap(), [1]);
+        assert_eq!(error(&format!("DB {}1{}", "(".repeat(200_000), ")".repeat(200_000))), "<source>:1: value is nested more than 64 deep");
+        assert_eq!(error(&format!("DB {}1", "-".repeat(100))), "<source>:1: value is nested more than 64 deep");
+        assert_eq!(error("DB (1 + 2"), "<source>:1: missing ')'");
+        assert_eq!(error("DB 1 2"), "<source>:1: unexpected '2' in '1 2'");
+        assert_eq!(error("DB 0xZZ"), "<source>:1: '0xZZ' is not a number");
+    }
+
+    #[test]
+    fn errors_name_the_line(){
+
+        assert_eq!(error("CLS\nJP nowhere"), "<source>:2: undefined symbol 'nowhere'");
+        assert_eq!(error("CLS\n\nFOO V1"), "<source>:3: unknown instruction 'FOO'");
+        assert_eq!(error("LD V0, DT, 3"), "<source>:1: invalid operands for LD: 'V0, DT, 3'");
+        assert_eq!(error("JP 0x1000"), "<source>:1: 0x1000 = 4096 is out of range (0..=4095)");
+        assert_eq!(error("a: CLS\na: CLS"), "<source>:2: 'a' is defined more than once");
+        assert_eq!(error("I = 3"), "<source>:1: 'I' is a reserved word and can't be a label or constant");
+    }
+
+    #[test]
+    fn includes(){
+
+        let dir = std::env::temp_dir().join(format!("rustchip-asm-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.c8s"), "INCLUDE \"lib/sprites.c8s\"\nLD I, ball").unwrap();
+        fs::write(dir.join("lib/sprites.c8s"), "ball: DB 0x60\nINCLUDE \"more.c8s\"").unwrap();
+        fs::write(dir.join("lib/more.c8s"), "DB 0xF0").unwrap();
+        fs::write(dir.join("loop.c8s"), "CLS\nINCLUDE \"loop.c8s\"").unwrap();
+        fs::write(dir.join("bad.c8s"), "INCLUDE \"lib/broken.c8s\"").unwrap();
+        fs::write(dir.join("lib/broken.c8s"), "CLS\nLD V0, nowhere").unwrap();
+
+        let result = (
+            assemble_file(&dir.join("main.c8s")),
+            assemble_file(&dir.join("loop.c8s")),
+            assemble_file(&dir.join("bad.c8s")),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.0.unwrap(), vec![0x60, 0xF0, 0xA2, 0x00]);
+        let nested = result.1.unwrap_err();
+        assert_eq!((nested.line, nested.message.as_str()), (2, "includes are nested too deeply, is a file including itself?"));
+        assert!(nested.file.ends_with("loop.c8s"));
+        let broken = result.2.unwrap_err();
+        assert_eq!((broken.line, broken.message.as_str()), (2, "undefined symbol 'nowhere'"));
+        assert!(broken.file.ends_with("broken.c8s"), "{}", broken.file);
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::{Arc, Mutex};
+
+// Frequency of the plain Chip-8 buzzer
+pub const BEEP_FREQUENCY: f32 = 440.0;
+
+// XO-CHIP pattern playback: 128 1-bit samples played at 4000 Hz when the pitch is 64
+pub const PATTERN_BITS: usize = 128;
+pub const DEFAULT_PITCH: u8 = 64;
+pub const PATTERN_AMPLITUDE: f32 = 0.25;
+
+// A pattern together with the pitch it was set to play at
+pub type PatternEvent = ([u8; 16], u8);
+
+/// Receives sound events from the machine. `frame` is the number of 60 Hz
+/// timer ticks since power-on, so events can be lined up with emulated time.
+pub trait AudioSink: Send{
+
+    fn set_beep(&mut self, on: bool, frame: u64);
+
+    fn set_tone(&mut self, _frequency: f32){}
+
+    // XO-CHIP: from now on beeps play `pattern` at the rate given by `pitch` instead of the tone
+    fn set_pattern(&mut self, _pattern: [u8; 16], _pitch: u8){}
+}
+
+/// Rate in bits per second at which an XO-CHIP pattern is played back for a given pitch.
+pub fn pattern_playback_rate(pitch: u8) -> f32{
+
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// Endless stream of samples for an XO-CHIP audio pattern at a host sample rate.
+/// Set bits play as `+PATTERN_AMPLITUDE` and clear bits as `-PATTERN_AMPLITUDE`.
+#[derive(Debug, Clone)]
+pub struct PatternSynth{
+
+    pattern: [u8; 16],
+    step: f32,     // Pattern bits advanced per output sample
+    position: f32, // Current bit, fractional
+}
+
+impl PatternSynth{
+
+    pub fn new(pattern: [u8; 16], pitch: u8, sample_rate: u32) -> PatternSynth{
+
+        let step = pattern_playback_rate(pitch) / sample_rate as f32;
+        PatternSynth{ pattern, step, position: 0.0 }
+    }
+
+    fn bit(&self, index: usize) -> bool{
+
+        self.pattern[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
+
+impl Iterator for PatternSynth{
+
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32>{
+
+        let sample = if self.bit(self.position as usize){
+
+            PATTERN_AMPLITUDE
+        }else{
+
+            -PATTERN_AMPLITUDE
+        };
+
+        self.position = (self.position + self.step) % PATTERN_BITS as f32;
+        Some(sample)
+    }
+}
+
+/// Discards every event; the default for machines without sound.
+#[derive(Default)]
+pub struct NullAudio;
+
+impl AudioSink for NullAudio{
+
+    fn set_beep(&mut self, _on: bool, _frame: u64){}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeepInterval{
+
+    pub start: u64,
+    pub end: Option<u64>, // None while the beep is still sounding
+}
+
+/// Captures beep intervals so tests can assert on them. Clones share the same log,
+/// so keep one clone and hand the other to the machine.
+#[derive(Clone, Default)]
+pub struct RecordingAudio{
+
+    intervals: Arc<Mutex<Vec<BeepInterval>>>,
+    tones: Arc<Mutex<Vec<f32>>>,
+    patterns: Arc<Mutex<Vec<PatternEvent>>>,
+}
+
+impl RecordingAudio{
+
+    pub fn new() -> RecordingAudio{
+
+        RecordingAudio::default()
+    }
+
+    pub fn intervals(&self) -> Vec<BeepInterval>{
+
+        self.intervals.lock().unwrap().clone()
+    }
+
+    pub fn tones(&self) -> Vec<f32>{
+
+        self.tones.lock().unwrap().clone()
+    }
+
+    pub fn patterns(&self) -> Vec<PatternEvent>{
+
+        self.patterns.lock().unwrap().clone()
+    }
+}
+
+impl AudioSink for RecordingAudio{
+
+    fn set_beep(&mut self, on: bool, frame: u64){
+
+        let mut intervals = self.intervals.lock().unwrap();
+        let sounding = intervals.last().is_some_and(|interval| interval.end.is_none());
+        if on && !sounding{
+
+            intervals.push(BeepInterval{ start: frame, end: None });
+        }else if !on && sounding
+            && let Some(interval) = intervals.last_mut(){
+
+            interval.end = Some(frame);
+        }
+    }
+
+    fn set_tone(&mut self, frequency: f32){
+
+        self.tones.lock().unwrap().push(frequency);
+    }
+
+    fn set_pattern(&mut self, pattern: [u8; 16], pitch: u8){
+
+        self.patterns.lock().unwrap().push((pattern, pitch));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    const HIGH: f32 = PATTERN_AMPLITUDE;
+    const LOW: f32 = -PATTERN_AMPLITUDE;
+
+    #[test]
+    fn playback_rate_follows_pitch(){
+
+        assert_eq!(pattern_playback_rate(64), 4000.0);
+        assert!((pattern_playback_rate(112) - 8000.0).abs() < 0.01);
+        assert!((pattern_playback_rate(16) - 2000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn default_pitch_at_4000_hz_plays_one_bit_per_sample(){
+
+        let mut pattern = [0x00; 16];
+        pattern[0] = 0b1010_0000;
+        let samples: Vec<f32> = PatternSynth::new(pattern, DEFAULT_PITCH, 4000).take(4).collect();
+
+        assert_eq!(samples, vec![HIGH, LOW, HIGH, LOW]);
+    }
+
+    #[test]
+    fn higher_sample_rate_holds_each_bit(){
+
+        let mut pattern = [0x00; 16];
+        pattern[0] = 0b1100_0000;
+        let samples: Vec<f32> = PatternSynth::new(pattern, DEFAULT_PITCH, 8000).take(6).collect();
+
+        assert_eq!(samples, vec![HIGH, HIGH, HIGH, HIGH, LOW, LOW]);
+    }
+
+    #[test]
+    fn pattern_loops_after_128_bits(){
+
+        let mut pattern = [0x00; 16];
+        pattern[0] = 0x80;
+        let samples: Vec<f32> = PatternSynth::new(pattern, DEFAULT_PITCH, 4000).take(PATTERN_BITS + 2).collect();
+
+        assert_eq!(samples[0], HIGH);
+        assert!(samples[1..PATTERN_BITS].iter().all(|&sample| sample == LOW));
+        assert_eq!(samples[PATTERN_BITS], HIGH);
+        assert_eq!(samples[PATTERN_BITS + 1], LOW);
+    }
+
+    #[test]
+    fn square_pattern_frequency_scales_with_pitch(){
+
+        // 0xF0 repeated is a square wave with an 8 bit period: 500 Hz at pitch 64, 1000 Hz at pitch 112
+        let pattern = [0xF0; 16];
+        let rising_edges = |pitch| {
+
+            let samples: Vec<f32> = PatternSynth::new(pattern, pitch, 48000).take(48000).collect();
+            samples.windows(2).filter(|pair| pair[0] == LOW && pair[1] == HIGH).count()
+        };
+
+        assert!((rising_edges(64) as i32 - 500).abs() <= 1);
+        assert!((rising_edges(112) as i32 - 1000).abs() <= 1);
+    }
+
+    #[test]
+    fn recording_audio_captures_beep_intervals(){
+
+        let recording = RecordingAudio::new();
+        let mut sink = recording.clone();
+        sink.set_beep(true, 3);
+        sink.set_beep(true, 4);
+        sink.set_beep(false, 10);
+        sink.set_beep(true, 12);
+
+        assert_eq!(recording.intervals(), vec![
+            BeepInterval{ start: 3, end: Some(10) },
+            BeepInterval{ start: 12, end: None },
+        ]);
+    }
+}
@@ -0,0 +1,365 @@
+//! Octo cartridges: GIF images that carry a program together with the settings it needs.
+//!
+//! The payload is a 4 byte big-endian length followed by JSON of the form
+//! `{"program": "<Octo source>", "options": {"tickrate": 20, "fillColor": "#FFCC00", ...}}`.
+//! It's stored two bits per pixel, most significant first, in the low bits of the palette
+//! indices, over as many frames as it needs. Every colour appears four times in the palette
+//! so the data doesn't show in the picture.
+
+use std::{fmt, io::{Read, Write}};
+
+use serde_json::{Map, Value, json};
+
+use crate::{disasm::Platform, octo::{self, OctoError}, quirks::{LoadStore, Quirks}};
+
+pub const WIDTH: u16 = 160;
+pub const HEIGHT: u16 = 128;
+
+const BYTES_PER_FRAME: usize = WIDTH as usize * HEIGHT as usize / 4;
+
+#[derive(Debug)]
+pub enum CartridgeError{
+
+    Decoding(gif::DecodingError),
+    Encoding(gif::EncodingError),
+    Payload(String),
+}
+
+impl fmt::Display for CartridgeError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            CartridgeError::Decoding(e) => write!(f, "could not read the cartridge image: {}", e),
+            CartridgeError::Encoding(e) => write!(f, "could not write the cartridge image: {}", e),
+            CartridgeError::Payload(what) => write!(f, "not an Octo cartridge: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError{}
+
+impl From<gif::DecodingError> for CartridgeError{
+
+    fn from(e: gif::DecodingError) -> Self{
+
+        CartridgeError::Decoding(e)
+    }
+}
+
+impl From<gif::EncodingError> for CartridgeError{
+
+    fn from(e: gif::EncodingError) -> Self{
+
+        CartridgeError::Encoding(e)
+    }
+}
+
+/// The settings Octo stores next to the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeOptions{
+
+    pub tickrate: u32,          // Instructions per 60 Hz frame
+    pub colours: [[u8; 3]; 4],  // Background, plane 1, plane 2 and both planes, as RGB
+    pub quirks: Quirks,
+}
+
+impl Default for CartridgeOptions{
+
+    // Octo's defaults
+    fn default() -> Self{
+
+        CartridgeOptions{
+            tickrate: 20,
+            colours: [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]],
+            quirks: Quirks::xochip(),
+        }
+    }
+}
+
+const COLOUR_KEYS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+
+impl CartridgeOptions{
+
+    // Missing or malformed options keep their defaults, as in Octo
+    fn from_json(options: &Map<String, Value>) -> CartridgeOptions{
+
+        let mut result = CartridgeOptions::default();
+        if let Some(tickrate) = options.get("tickrate").and_then(Value::as_u64){
+
+            result.tickrate = tickrate.clamp(1, 100_000) as u32;
+        }
+        for (colour, key) in result.colours.iter_mut().zip(COLOUR_KEYS){
+
+            if let Some(rgb) = options.get(key).and_then(Value::as_str).and_then(parse_colour){
+
+                *colour = rgb;
+            }
+        }
+
+        // Octo's quirk flags are all off for the COSMAC VIP behaviour that XO-CHIP keeps
+        let flag = |key: &str| options.get(key).and_then(Value::as_bool);
+        let quirks = &mut result.quirks;
+        if let Some(shift) = flag("shiftQuirks"){
+
+            quirks.shift = shift;
+        }
+        if let Some(unchanged) = flag("loadStoreQuirks"){
+
+            quirks.load_store = if unchanged { LoadStore::Unchanged } else { LoadStore::IncrementByXPlusOne };
+        }
+        if let Some(vf_reset) = flag("logicQuirks"){
+
+            quirks.vf_reset = vf_reset;
+        }
+        if let Some(clip) = flag("clipQuirks"){
+
+            quirks.clip = clip;
+        }
+        if let Some(jump) = flag("jumpQuirks"){
+
+            quirks.jump = jump;
+        }
+        if let Some(display_wait) = flag("vBlankQuirks"){
+
+            quirks.display_wait = display_wait;
+        }
+        result
+    }
+
+    fn to_json(self) -> Value{
+
+        let mut options = json!({
+            "tickrate": self.tickrate,
+            "shiftQuirks": self.quirks.shift,
+            "loadStoreQuirks": self.quirks.load_store == LoadStore::Unchanged,
+            "logicQuirks": self.quirks.vf_reset,
+            "clipQuirks": self.quirks.clip,
+            "jumpQuirks": self.quirks.jump,
+            "vBlankQuirks": self.quirks.display_wait,
+            "vfOrderQuirks": false,
+        });
+        for (colour, key) in self.colours.iter().zip(COLOUR_KEYS){
+
+            options[key] = json!(format!("#{:02X}{:02X}{:02X}", colour[0], colour[1], colour[2]));
+        }
+        options
+    }
+}
+
+/// Parses `#RRGGBB` (the `#` is optional).
+pub fn parse_colour(text: &str) -> Option<[u8; 3]>{
+
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6{
+
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge{
+
+    pub program: String, // Octo source
+    pub options: CartridgeOptions,
+}
+
+impl Cartridge{
+
+    /// Wraps a binary ROM, written out as Octo data bytes starting at 0x200.
+    pub fn from_rom(rom: &[u8], options: CartridgeOptions) -> Cartridge{
+
+        let mut program = String::from(":org 0x200\n: main\n");
+        for line in rom.chunks(16){
+
+            let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            program.push_str(&bytes.join(" "));
+            program.push('\n');
+        }
+        Cartridge{ program, options }
+    }
+
+    /// Compiles the program to ROM bytes.
+    pub fn rom(&self) -> Result<Vec<u8>, OctoError>{
+
+        octo::compile(&self.program, Platform::XoChip)
+    }
+
+    pub fn read(reader: impl Read) -> Result<Cartridge, CartridgeError>{
+
+        let mut decode_options = gif::DecodeOptions::new();
+        decode_options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decode_options.read_info(reader)?;
+
+        let mut payload = Vec::new();
+        while let Some(frame) = decoder.read_next_frame()?{
+
+            for pixels in frame.buffer.chunks_exact(4){
+
+                payload.push(pixels.iter().fold(0, |byte, pixel| byte << 2 | (pixel & 3)));
+            }
+        }
+
+        let invalid = |what: &str| CartridgeError::Payload(what.to_string());
+        let (length, rest) = payload.split_first_chunk::<4>().ok_or(invalid("the image is too small"))?;
+        let json = rest.get(..u32::from_be_bytes(*length) as usize).ok_or(invalid("the payload is truncated"))?;
+        let json: Value = serde_json::from_slice(json).map_err(|e| CartridgeError::Payload(e.to_string()))?;
+
+        let program = json.get("program").and_then(Value::as_str).ok_or(invalid("there is no program"))?;
+        let options = json.get("options").and_then(Value::as_object).map(CartridgeOptions::from_json).unwrap_or_default();
+        Ok(Cartridge{ program: program.to_string(), options })
+    }
+
+    pub fn write(&self, writer: impl Write) -> Result<(), CartridgeError>{
+
+        let json = json!({ "program": self.program, "options": self.options.to_json() }).to_string();
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        self.write_payload(&payload, writer)
+    }
+
+    fn write_payload(&self, payload: &[u8], writer: impl Write) -> Result<(), CartridgeError>{
+
+        let label = self.label();
+        let palette = self.palette();
+        let mut encoder = gif::Encoder::new(writer, WIDTH, HEIGHT, &palette)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for chunk in payload.chunks(BYTES_PER_FRAME){
+
+            let mut pixels = label.clone();
+            for (byte, out) in chunk.iter().zip(pixels.chunks_exact_mut(4)){
+
+                for (index, pixel) in out.iter_mut().enumerate(){
+
+                    *pixel |= byte >> (6 - 2 * index) & 3;
+                }
+            }
+            let frame = gif::Frame{ width: WIDTH, height: HEIGHT, delay: 100, buffer: pixels.into(), ..gif::Frame::default() };
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    // Body, edge and shadow of the cartridge, then the program's own colours
+    fn palette(&self) -> Vec<u8>{
+
+        let mut colours = vec![[0x44, 0x44, 0x4C], [0x22, 0x22, 0x26], [0x66, 0x66, 0x70]];
+        colours.extend_from_slice(&self.options.colours);
+        colours.iter().flat_map(|colour| colour.repeat(4)).collect()
+    }
+
+    // A plain cartridge whose label shows stripes of the program's colours. Each pixel is
+    // a palette index with the two data bits left clear.
+    fn label(&self) -> Vec<u8>{
+
+        let (width, height) = (WIDTH as usize, HEIGHT as usize);
+        let mut pixels = vec![0; width * height];
+        for y in 0..height{
+
+            for x in 0..width{
+
+                let colour = if x < 4 || y < 4 || x >= width - 4 || y >= height - 4{
+
+                    1
+                }else if (16..width - 16).contains(&x) && (16..height - 40).contains(&y){
+
+                    3 + (x - 16) * 4 / (width - 32)
+                }else if (16..width - 16).contains(&x) && (height - 28..height - 20).contains(&y){
+
+                    2
+                }else{
+
+                    0
+                };
+                pixels[y * width + x] = (colour * 4) as u8;
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    fn options() -> CartridgeOptions{
+
+        CartridgeOptions{
+            tickrate: 1000,
+            colours: [[0x01, 0x02, 0x03], [0x10, 0x20, 0x30], [0xAB, 0xCD, 0xEF], [0xFF, 0x00, 0x80]],
+            quirks: Quirks{ shift: true, load_store: LoadStore::Unchanged, vf_reset: true, jump: true, clip: true, display_wait: true },
+        }
+    }
+
+    fn written(cartridge: &Cartridge) -> Vec<u8>{
+
+        let mut gif = Vec::new();
+        cartridge.write(&mut gif).unwrap();
+        gif
+    }
+
+    fn payload_error(payload: &[u8]) -> String{
+
+        let mut gif = Vec::new();
+        Cartridge::from_rom(&[], CartridgeOptions::default()).write_payload(payload, &mut gif).unwrap();
+        Cartridge::read(gif.as_slice()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn write_and_read_round_trip(){
+
+        // Long enough for the payload to need several frames
+        let rom: Vec<u8> = (0..1500u32).map(|i| (i * 7 % 256) as u8).collect();
+        let cartridge = Cartridge::from_rom(&rom, options());
+        assert_ne!(cartridge.options, CartridgeOptions::default());
+
+        let read = Cartridge::read(written(&cartridge).as_slice()).unwrap();
+        assert_eq!(read.rom().unwrap(), rom);
+        assert_eq!(read.options.tickrate, 1000);
+        assert_eq!(read.options.colours, options().colours);
+        assert_eq!(read.options.quirks, options().quirks);
+        assert_eq!(read, cartridge);
+    }
+
+    #[test]
+    fn missing_options_keep_octos_defaults(){
+
+        let json = br#"{"program": ": main clear", "options": {"tickrate": "fast", "fillColor": "red"}}"#;
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json);
+
+        let mut gif = Vec::new();
+        Cartridge::from_rom(&[], CartridgeOptions::default()).write_payload(&payload, &mut gif).unwrap();
+        let read = Cartridge::read(gif.as_slice()).unwrap();
+        assert_eq!(read.options, CartridgeOptions::default());
+        assert_eq!(read.rom().unwrap(), [0x12, 0x02, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn rejects_a_truncated_or_corrupt_image(){
+
+        let gif = written(&Cartridge::from_rom(&[0x00, 0xE0], options()));
+        for length in [0, 6, 100, gif.len() / 2]{
+
+            assert!(matches!(Cartridge::read(&gif[..length]), Err(CartridgeError::Decoding(_))), "{} bytes", length);
+        }
+
+        let mut corrupt = gif.clone();
+        corrupt[..6].copy_from_slice(b"PNG89a");
+        assert!(matches!(Cartridge::read(corrupt.as_slice()), Err(CartridgeError::Decoding(_))));
+    }
+
+    #[test]
+    fn rejects_a_bad_payload(){
+
+        assert_eq!(payload_error(&[]), "not an Octo cartridge: the image is too small");
+        // The rest of the last frame reads as zeros, so the length has to run past it
+        assert_eq!(payload_error(&[0x00, 0x01, 0x00, 0x00, b'{', b'}']), "not an Octo cartridge: the payload is truncated");
+        assert_eq!(payload_error(&[0x00, 0x00, 0x00, 0x02, b'{', b'}']), "not an Octo cartridge: there is no program");
+        assert!(payload_error(&[0x00, 0x00, 0x00, 0x02, b'{', b'{']).starts_with("not an Octo cartridge: "));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{audio::{AudioSink, NullAudio, PatternEvent, BEEP_FREQUENCY}, cpu::{Cpu, StepOutcome}, display::Display, error::Chip8Error, keypad::Keypad, quirks::Quirks};
+use crate::savestate::{StateError, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+
+pub const PROGRAM_START: u16 = 0x200;
+/// Instructions per 60 Hz frame, 600 a second.
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+
+// 64-bit FNV-1a, enough to tell ROMs apart without pulling in a hashing crate
+pub fn rom_hash(rom_bytes: &[u8]) -> u64{
+
+    rom_bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+/// A complete Chip-8 machine: the CPU (which owns memory), the display and the keypad.
+///
+/// Frontends drive it by calling `step` at the CPU rate, feeding key events into
+/// `keypad_mut` and reading the screen from `display`. Time is counted in cycles:
+/// the timers tick after every `cycles_per_frame` steps, so a run depends only on the
+/// ROM, the seed and the inputs. Sound is reported to the attached `AudioSink`.
+pub struct Chip8{
+
+    cpu: Cpu,
+    display: Display,
+    keypad: Keypad,
+
+    audio: Box<dyn AudioSink>,
+    beeping: bool,
+    pattern: Option<PatternEvent>, // Last XO-CHIP pattern and pitch sent to the sink
+    frame: u64,
+    cycles_per_frame: u32,
+    frame_cycle: u32, // Steps run so far in this frame
+    rom_hash: u64,
+}
+
+impl Default for Chip8{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
+}
+
+impl Chip8{
+
+    pub fn new() -> Chip8{
+
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Chip8{
+
+        let cpu = Cpu::with_quirks(quirks);
+        let display = Display::new();
+        let keypad = Keypad::new();
+
+        Chip8{ cpu, display, keypad, audio: Box::new(NullAudio), beeping: false, pattern: None, frame: 0, cycles_per_frame: DEFAULT_CYCLES_PER_FRAME, frame_cycle: 0, rom_hash: rom_hash(&[]) }
+    }
+
+    pub fn set_audio(&mut self, mut audio: Box<dyn AudioSink>){
+
+        audio.set_tone(BEEP_FREQUENCY);
+        if let Some((pattern, pitch)) = self.pattern{
+
+            audio.set_pattern(pattern, pitch);
+        }
+        audio.set_beep(self.beeping, self.frame);
+        self.audio = audio;
+    }
+
+    pub fn load_rom(&mut self, rom_bytes: &[u8])->Result<(), Chip8Error>{
+
+        self.cpu.load_rom(rom_bytes, PROGRAM_START)?;
+        self.rom_hash = rom_hash(rom_bytes);
+        Ok(())
+    }
+
+    pub fn rom_hash(&self) -> u64{
+
+        self.rom_hash
+    }
+
+    pub fn cycles_per_frame(&self) -> u32{
+
+        self.cycles_per_frame
+    }
+
+    /// Instructions per 60 Hz frame; at least one.
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32){
+
+        self.cycles_per_frame = cycles_per_frame.max(1);
+        self.frame_cycle = self.frame_cycle.min(self.cycles_per_frame - 1);
+    }
+
+    pub fn save_state(&self) -> Vec<u8>{
+
+        let mut state = StateWriter::new();
+        state.put_bytes(&STATE_MAGIC);
+        state.put_u16(STATE_VERSION);
+        state.put_u64(self.rom_hash);
+        state.put_u64(self.frame);
+        state.put_u32(self.frame_cycle);
+        self.cpu.save_state(&mut state);
+        self.display.save_state(&mut state);
+        self.keypad.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    // Either the whole state is restored or, on error, nothing is changed
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError>{
+
+        let mut state = StateReader::new(bytes);
+        if state.get_array::<4>()? != STATE_MAGIC{
+
+            return Err(StateError::BadMagic);
+        }
+        let version = state.get_u16()?;
+        if version != STATE_VERSION{
+
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let found = state.get_u64()?;
+        if found != self.rom_hash{
+
+            return Err(StateError::RomMismatch{ expected: self.rom_hash, found });
+        }
+
+        let frame = state.get_u64()?;
+        let frame_cycle = state.get_u32()?;
+        let mut cpu = Cpu::with_quirks(self.cpu.quirks());
+        cpu.set_access_log(self.cpu.is_logging_accesses());
+        cpu.load_state(&mut state)?;
+        let mut display = Display::new();
+        display.load_state(&mut state)?;
+        let mut keypad = Keypad::new();
+        keypad.load_state(&mut state)?;
+        if !state.is_empty(){
+
+            return Err(StateError::Corrupt("trailing data"));
+        }
+
+        // Restoring a state continues the trace and the cycle count rather than the recorded ones
+        cpu.set_trace(self.cpu.take_trace());
+        cpu.set_cycles(self.cpu.cycles());
+        self.cpu = cpu;
+        self.display = display;
+        self.keypad = keypad;
+        self.frame = frame;
+        self.frame_cycle = frame_cycle.min(self.cycles_per_frame - 1);
+        self.pattern = None;
+        if self.cpu.audio_pattern().is_none(){
+
+            self.audio.set_tone(BEEP_FREQUENCY);
+        }
+        self.update_audio();
+        Ok(())
+    }
+
+    // Runs one instruction, ticking the timers when a frame's worth of cycles has passed.
+    // Waiting for a key or the vertical blank takes a cycle too, so time keeps moving.
+    // A fault leaves the machine at the faulting instruction.
+    pub fn step(&mut self)->Result<StepOutcome, Chip8Error>{
+
+        let outcome = if self.cpu.is_halted(){
+
+            // FX0A: only check for a key press while halted
+            if let Some(v_x) = self.cpu.get_wait_register()
+                && let Some(key) = self.keypad.wait_for_press(){
+
+                self.cpu.set_register(v_x, key);
+                self.cpu.set_wait_register(None);
+                self.cpu.resume();
+            }
+            StepOutcome::WaitingForKey
+        }else{
+
+            self.cpu.step(&mut self.keypad, &mut self.display)?
+        };
+
+        self.update_audio();
+        self.frame_cycle += 1;
+        if self.frame_cycle >= self.cycles_per_frame{
+
+            self.frame_cycle = 0;
+            self.tick_timers();
+        }
+        Ok(outcome)
+    }
+
+    /// Ends the frame straight away. `step` calls this by itself; it's for callers that
+    /// keep their own clock.
+    pub fn tick_timers(&mut self){
+
+        if self.cpu.get_delay_timer() > 0{
+
+            self.cpu.decrement_delay_timer();
+        }
+        if self.cpu.get_sound_timer() > 0{
+
+            self.cpu.decrement_sound_timer();
+        }
+
+        self.cpu.end_vblank_wait();
+        self.frame += 1;
+        self.update_audio();
+    }
+
+    /// Steps until the next timer tick, or until the ROM exits.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, Chip8Error>{
+
+        let frame = self.frame;
+        loop{
+
+            let outcome = self.step()?;
+            if outcome == StepOutcome::Exited || self.frame != frame{
+
+                return Ok(outcome);
+            }
+        }
+    }
+
+    // Only changes are reported so sinks don't get a stream of redundant events
+    fn update_audio(&mut self){
+
+        let pattern = self.cpu.audio_pattern().map(|pattern| (pattern, self.cpu.pitch()));
+        if pattern != self.pattern
+            && let Some((pattern, pitch)) = pattern{
+
+            self.pattern = Some((pattern, pitch));
+            self.audio.set_pattern(pattern, pitch);
+        }
+
+        let beeping = self.sound_active();
+        if beeping != self.beeping{
+
+            self.beeping = beeping;
+            self.audio.set_beep(beeping, self.frame);
+        }
+    }
+
+    pub fn sound_active(&self) -> bool{
+
+        self.cpu.get_sound_timer() > 0
+    }
+
+    pub fn frame(&self) -> u64{
+
+        self.frame
+    }
+
+    pub fn cpu(&self) -> &Cpu{
+
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu{
+
+        &mut self.cpu
+    }
+
+    pub fn display(&self) -> &Display{
+
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut Display{
+
+        &mut self.display
+    }
+
+    pub fn keypad(&self) -> &Keypad{
+
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut Keypad{
+
+        &mut self.keypad
+    }
+}
@@ -1,22 +1,35 @@
-use rand::Rng;
-use crate::memory::Memory;
-use winit::window::Window;
-use rodio::{source::SineWave, OutputStream, Sink, Source};
-use std::sync::Arc;
-use std::time::{Instant, Duration};
-use std::thread::sleep;
-
-#[derive(Debug)]
+use std::io::Write;
+
+use crate::memory::{Memory, FONTSET_START, BIG_FONTSET_START};
+use crate::display::PLANE_COUNT;
+use crate::audio::DEFAULT_PITCH;
+use crate::savestate::{StateError, StateReader, StateWriter};
+use crate::error::{Chip8Error, Fault};
+use crate::quirks::{LoadStore, Quirks};
+use crate::opcodes;
+use crate::rng::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     //SystemJump(u16),                       0NNN - Jump to RCA 1802 program (legacy) 
     ClearDisplay,                          // 00E0 - Clear the display
     ReturnFromSubroutine,                  // 00EE - Return from subroutine
 
+    ScrollDown(u8),                        // 00CN - Scroll display N rows down (SCHIP)
+    ScrollRight,                           // 00FB - Scroll display 4 pixels right (SCHIP)
+    ScrollLeft,                            // 00FC - Scroll display 4 pixels left (SCHIP)
+    Exit,                                  // 00FD - Exit the interpreter (SCHIP)
+    LowResolution,                         // 00FE - Switch to 64x32 (SCHIP)
+    HighResolution,                        // 00FF - Switch to 128x64 (SCHIP)
+    ScrollUp(u8),                          // 00DN - Scroll display N rows up (XO-CHIP)
+
     JumpToAddress(u16),                    // 1NNN - Jump to address
     CallSubroutine(u16),                   // 2NNN - Call subroutine
     SkipIfVxEqualsByte(usize, u8),             // 3XNN - Skip next instruction if Vx == byte
     SkipIfVxNotEqualsByte(usize, u8),          // 4XNN - Skip next instruction if Vx != byte
     SkipIfVxEqualsVy(usize, usize),               // 5XY0 - Skip if Vx == Vy
+    StoreRegistersVxThroughVyInMemory(usize, usize),  // 5XY2 - Save Vx..Vy at I (XO-CHIP)
+    ReadRegistersVxThroughVyFromMemory(usize, usize), // 5XY3 - Load Vx..Vy from I (XO-CHIP)
 
     SetVxToByte(usize, u8),                    // 6XNN - Set Vx = byte
     AddByteToVx(usize, u8),                    // 7XNN - Vx += byte
@@ -36,24 +49,56 @@ pub enum Instruction {
     SetIToAddress(u16),                     // ANNN
     JumpToV0PlusAddress(u16),               // BNNN
     SetVxToRandomAndByte(usize, u8),           // CXNN
-    DrawSprite(usize, usize, u8),                  // DXYN
+    DrawSprite(usize, usize, u8),                  // DXYN (DXY0 draws a 16x16 sprite)
 
     SkipIfKeyInVxPressed(usize),               // EX9E
     SkipIfKeyInVxNotPressed(usize),            // EXA1
 
+    SetIToLongAddress(u16),                    // F000 NNNN (XO-CHIP, four bytes long)
+    SelectPlanes(u8),                          // FN01 (XO-CHIP)
+    LoadAudioPattern,                          // F002 (XO-CHIP)
+    SetPitchToVx(usize),                       // FX3A (XO-CHIP)
     SetVxToDelayTimer(usize),                  // FX07
     WaitForKeyPressAndStoreInVx(usize),        // FX0A
     SetDelayTimerToVx(usize),                  // FX15
     SetSoundTimerToVx(usize),                  // FX18
     AddVxToI(usize),                            // FX1E
     SetIToSpriteAddressForDigitVx(usize),      // FX29
+    SetIToBigSpriteAddressForDigitVx(usize),   // FX30 (SCHIP)
     StoreBcdOfVxAtI(usize),                     // FX33
     StoreRegistersV0ThroughVxInMemory(usize),  // FX55
     ReadRegistersV0ThroughVxFromMemory(usize), // FX65
+    StoreRegistersV0ThroughVxInFlags(usize),   // FX75 (SCHIP)
+    ReadRegistersV0ThroughVxFromFlags(usize),  // FX85 (SCHIP)
 
     Invalid(u16), // Invalid
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome{
+
+    Executed,
+    WaitingForKey,       // FX0A is blocking, nothing was executed
+    WaitingForVblank,    // Display wait quirk: a sprite was drawn this frame, nothing was executed
+    Exited,              // 00FD was executed, the program has finished
+    InvalidOpcode(u16),  // Unknown opcode, skipped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind{
+
+    Read,
+    Write,
+}
+
+// A data access made by an instruction, instruction fetches are not included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess{
+
+    pub addr: usize,
+    pub kind: AccessKind,
+}
+
 pub struct Cpu{
 
     v: [u8; 16],
@@ -65,13 +110,26 @@ pub struct Cpu{
     stack: [u16; 16],
     halted: bool,
     wait_register: Option<usize>,
+    vblank_wait: bool,
+    exited: bool,
+    flags: [u8; 16], // SUPER-CHIP RPL user flags
+    audio_pattern: Option<[u8; 16]>, // XO-CHIP 1-bit sample buffer, None until F002 runs
+    pitch: u8,
+    access_log: Option<Vec<MemoryAccess>>, // Data accesses of the last instruction, only kept while a debugger watches memory
+    cycles: u64, // Instructions executed since power on
+    trace: Option<Box<dyn Write + Send>>,
+    rng: Rng, // CXNN, seeded randomly unless set_seed is called
 
     mem: Memory,
+    quirks: Quirks,
+}
+
+impl Default for Cpu{
 
-    stream: rodio::OutputStream,
-    sink: rodio::Sink,
+    fn default() -> Self{
 
-    window: Option<Arc<Window>>,
+        Self::new()
+    }
 }
 
 impl Cpu{
@@ -85,18 +143,174 @@ impl Cpu{
         let delay_timer = 0x00;
         let sound_timer = 0x00;
 
-        let stream_handle = rodio::OutputStreamBuilder::open_default_stream().unwrap();
-        let sink = rodio::Sink::connect_new(&stream_handle.mixer());
-        let source = SineWave::new(440.0).repeat_infinite();
-        sink.append(source);
-
         let stack = [0x0000; 16];
         let halted = false;
         let wait_register = None;
+        let vblank_wait = false;
+        let exited = false;
+        let flags = [0x00; 16];
+        let audio_pattern = None;
+        let pitch = DEFAULT_PITCH;
+        let access_log = None;
+        let cycles = 0;
+        let trace = None;
+        let rng = Rng::new(rand::random());
 
         let mem = Memory::new();
+        let quirks = Quirks::default();
+
+        Cpu{ v, i, pc, sp, delay_timer, sound_timer, stack, halted, wait_register, vblank_wait, exited, flags, audio_pattern, pitch, access_log, cycles, trace, rng, mem, quirks }
+    }
+
+    pub fn with_quirks(quirks: Quirks)->Cpu{
+
+        let mut cpu = Cpu::new();
+        cpu.quirks = quirks;
+        cpu
+    }
 
-        Cpu{ v, i, pc, sp, delay_timer, sound_timer, stack, halted, wait_register, mem, stream: stream_handle, sink, window: None }
+    pub fn quirks(&self) -> Quirks{
+
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks){
+
+        self.quirks = quirks;
+    }
+
+    /// Restarts CXNN's random numbers from `seed`, so runs with the same inputs repeat exactly.
+    pub fn set_seed(&mut self, seed: u64){
+
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn set_access_log(&mut self, enabled: bool){
+
+        self.access_log = enabled.then(Vec::new);
+    }
+
+    pub fn is_logging_accesses(&self) -> bool{
+
+        self.access_log.is_some()
+    }
+
+    // Memory the last executed instruction read or wrote, empty unless the access log is enabled
+    pub fn memory_accesses(&self) -> &[MemoryAccess]{
+
+        self.access_log.as_deref().unwrap_or_default()
+    }
+
+    pub fn cycles(&self) -> u64{
+
+        self.cycles
+    }
+
+    pub(crate) fn set_cycles(&mut self, cycles: u64){
+
+        self.cycles = cycles;
+    }
+
+    /// Writes a line per executed instruction to `trace`, see `write_trace` for the format.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write + Send>>){
+
+        self.trace = trace;
+    }
+
+    pub fn take_trace(&mut self) -> Option<Box<dyn Write + Send>>{
+
+        self.trace.take()
+    }
+
+    pub fn flush_trace(&mut self){
+
+        if let Some(trace) = self.trace.as_mut()
+            && let Err(e) = trace.flush(){
+
+            eprintln!("Warning: could not write the trace ({}), tracing stopped.", e);
+            self.trace = None;
+        }
+    }
+
+    // One line per instruction with the state before it runs, fixed width up to the mnemonic:
+    // cycle PC opcode V0..VF I SP DT ST mnemonic
+    // 00000001 0200 00E0 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 CLS
+    fn write_trace(&mut self, opcode: u16, instruction: Instruction){
+
+        let Some(trace) = self.trace.as_mut() else { return };
+        let registers: Vec<String> = self.v.iter().map(|v| format!("{:02X}", v)).collect();
+        let line = format!("{:08} {:04X} {:04X} {} {:04X} {:02X} {:02X} {:02X} {}",
+            self.cycles, self.pc, opcode, registers.join(" "), self.i, self.sp, self.delay_timer, self.sound_timer, instruction);
+        if let Err(e) = writeln!(trace, "{}", line){
+
+            eprintln!("Warning: could not write the trace ({}), tracing stopped.", e);
+            self.trace = None;
+        }
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter){
+
+        state.put_bytes(&self.v);
+        state.put_u16(self.i);
+        state.put_u16(self.pc);
+        state.put_u8(self.sp as u8);
+        for &address in self.stack.iter(){
+
+            state.put_u16(address);
+        }
+        state.put_u8(self.delay_timer);
+        state.put_u8(self.sound_timer);
+        state.put_bool(self.halted);
+        state.put_u8(self.wait_register.map_or(0xFF, |v_x| v_x as u8));
+        state.put_bool(self.vblank_wait);
+        state.put_bool(self.exited);
+        state.put_bytes(&self.flags);
+        state.put_bool(self.audio_pattern.is_some());
+        state.put_bytes(&self.audio_pattern.unwrap_or_default());
+        state.put_u8(self.pitch);
+        state.put_u64(self.rng.state());
+        self.mem.save_state(state);
+    }
+
+    // Quirks are configuration rather than state, so they are kept as they are
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>{
+
+        self.v = state.get_array()?;
+        self.i = state.get_u16()?;
+        self.pc = state.get_u16()?;
+        self.sp = state.get_u8()? as usize;
+        if self.sp > self.stack.len(){
+
+            return Err(StateError::Corrupt("stack pointer out of range"));
+        }
+        for address in self.stack.iter_mut(){
+
+            *address = state.get_u16()?;
+        }
+        self.delay_timer = state.get_u8()?;
+        self.sound_timer = state.get_u8()?;
+        self.halted = state.get_bool()?;
+        self.wait_register = match state.get_u8()?{
+
+            0xFF => None,
+            v_x if v_x < 16 => Some(v_x as usize),
+            _ => return Err(StateError::Corrupt("invalid wait register")),
+        };
+        self.vblank_wait = state.get_bool()?;
+        self.exited = state.get_bool()?;
+        self.flags = state.get_array()?;
+        let has_pattern = state.get_bool()?;
+        let pattern = state.get_array()?;
+        self.audio_pattern = has_pattern.then_some(pattern);
+        self.pitch = state.get_u8()?;
+        self.rng = Rng::from_state(state.get_u64()?).ok_or(StateError::Corrupt("invalid random number state"))?;
+        self.mem.load_state(state)
+    }
+
+    // Called on every 60 Hz tick, releases a DXYN blocked by the display wait quirk
+    pub fn end_vblank_wait(&mut self){
+
+        self.vblank_wait = false;
     }
 
     pub fn is_halted(&self) -> bool{
@@ -104,6 +318,21 @@ impl Cpu{
         self.halted
     }
 
+    pub fn has_exited(&self) -> bool{
+
+        self.exited
+    }
+
+    pub fn audio_pattern(&self) -> Option<[u8; 16]>{
+
+        self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8{
+
+        self.pitch
+    }
+
     pub fn get_register(&self, index: usize)->u8{
         assert!(index < 16);
 
@@ -116,39 +345,36 @@ impl Cpu{
         self.v[index] = data;
     }
 
-    pub fn push_stack(&mut self)->Result<(), &'static str>{
+    pub fn push_stack(&mut self)->Result<(), Fault>{
         if self.sp < 16{
 
-            self.stack[self.sp] = self.pc + 2;
+            self.stack[self.sp] = self.pc.wrapping_add(2);
             self.sp += 1;
             Ok(())
         }else{
 
-            Err("stack overflow")
+            Err(Fault::StackOverflow)
         }
-
-        
     }
 
-    pub fn pop_stack(&mut self)->Option<u16>{
+    pub fn pop_stack(&mut self)->Result<u16, Fault>{
         
         if self.sp > 0{
 
             self.sp -= 1;
-            Some(self.stack[self.sp])
+            Ok(self.stack[self.sp])
         }else{
 
-            None
+            Err(Fault::StackUnderflow)
         }
     }
 
-    pub fn fetch_instruction(&mut self)->u16{
+    pub fn fetch_instruction(&mut self)->Result<u16, Fault>{
 
-        let instruction = self.mem.read_16(self.pc as usize);
-        instruction
+        self.mem.read_16(self.pc as usize)
     }
 
-    fn get_nibble(opcode: u16, index:usize)->u8{
+    pub(crate) fn get_nibble(opcode: u16, index:usize)->u8{
         assert!(index < 4);
 
         match index{
@@ -165,30 +391,26 @@ impl Cpu{
         
     }
 
-    fn get_x(opcode: u16)->usize{
+    pub(crate) fn get_x(opcode: u16)->usize{
 
         Self::get_nibble(opcode, 1) as usize
     }
 
-    fn get_y(opcode: u16)->usize{
+    pub(crate) fn get_y(opcode: u16)->usize{
 
         Self::get_nibble(opcode, 2) as usize
     }
 
-    fn get_nn(opcode: u16)->u8{
+    pub(crate) fn get_nn(opcode: u16)->u8{
 
         (opcode & 0x00FF) as u8
     }
 
-    fn get_nnn(opcode: u16)->u16{
+    pub(crate) fn get_nnn(opcode: u16)->u16{
 
         opcode & 0x0FFF
     }
 
-    fn invalid_opcode(opcode: u16) {
-        eprintln!("Warning: Unknown opcode {:04X}, ignoring.", opcode);
-    }
-
     pub fn get_wait_register(&self) -> Option<usize>{
 
         self.wait_register
@@ -204,9 +426,68 @@ impl Cpu{
         self.halted = false;
     }
 
-    pub fn write_byte_to_mem(&mut self, byte: u8, index: usize){
+    pub fn write_byte_to_mem(&mut self, byte: u8, index: usize)->Result<(), Fault>{
+
+        self.mem.write_byte(index, byte)
+    }
+
+    pub fn read_byte_from_mem(&self, index: usize)->Result<u8, Fault>{
+
+        self.mem.read_byte(index)
+    }
+
+    // Data accesses made while executing go through these so they can be logged for watchpoints
+    fn load(&mut self, index: usize)->Result<u8, Fault>{
+
+        if let Some(log) = self.access_log.as_mut(){
+
+            log.push(MemoryAccess{ addr: index, kind: AccessKind::Read });
+        }
+        self.mem.read_byte(index)
+    }
+
+    fn load_16(&mut self, index: usize)->Result<u16, Fault>{
+
+        let high = self.load(index)?;
+        let low = self.load(index + 1)?;
+        Ok(((high as u16) << 8) | low as u16)
+    }
+
+    fn store(&mut self, index: usize, byte: u8)->Result<(), Fault>{
+
+        if let Some(log) = self.access_log.as_mut(){
+
+            log.push(MemoryAccess{ addr: index, kind: AccessKind::Write });
+        }
+        self.mem.write_byte(index, byte)
+    }
+
+    pub fn get_i(&self) -> u16{
+
+        self.i
+    }
+
+    pub fn set_i(&mut self, addr: u16){
+
+        self.i = addr;
+    }
+
+    pub fn get_sp(&self) -> usize{
 
-        self.mem.write_byte(index, byte);
+        self.sp
+    }
+
+    // Return addresses currently on the stack, oldest first
+    pub fn get_stack(&self) -> &[u16]{
+
+        &self.stack[..self.sp]
+    }
+
+    pub fn load_rom(&mut self, rom_bytes: &[u8], start_addr: u16)->Result<(), Chip8Error>{
+
+        self.mem.load_rom(rom_bytes, start_addr as usize)?;
+        self.pc = start_addr;
+        Ok(())
     }
 
     pub fn set_pc(&mut self, addr: u16){
@@ -214,6 +495,11 @@ impl Cpu{
         self.pc = addr;
     }
 
+    pub fn get_pc(&self) -> u16{
+
+        self.pc
+    }
+
     pub fn get_sound_timer(&self) -> u8{
 
         self.sound_timer
@@ -234,171 +520,44 @@ impl Cpu{
         self.delay_timer -= 1;
     }
 
-    pub fn set_window(&mut self, window: Arc<Window>){
+    // decode_instruction alone reports F000 as invalid since it can't see the address that follows
+    pub fn decode_instruction(opcode: u16)->Instruction{
 
-        self.window = Some(window);
-    }
+        if Self::instruction_length(opcode) == 4{
 
-    pub fn get_window(&self) -> Option<Arc<Window>> {
-        self.window.as_ref().map(Arc::clone)
+            Instruction::Invalid(opcode)
+        }else{
+
+            Self::decode_instruction_pair(opcode, 0)
+        }
     }
 
-    pub fn decode_instruction(opcode: u16)->Instruction{
+    // F000 NNNN is the only four-byte instruction, every other opcode ignores `next`
+    pub fn decode_instruction_pair(opcode: u16, next: u16)->Instruction{
 
-        let first_nibble = Self::get_nibble(opcode, 0);
+        match opcodes::lookup(opcode){
+
+            Some(entry) => (entry.decode)(opcode, next),
+            None => Instruction::Invalid(opcode),
+        }
+    }
 
-        match first_nibble{
+    pub fn instruction_length(opcode: u16)->u16{
 
-            0x0 => {
-                let second_nibble = Self::get_nibble(opcode, 1);
-                if second_nibble == 0x0{// 00E0 or 00EE
+        opcodes::lookup(opcode).map_or(2, |entry| entry.length())
+    }
 
-                    let last_two = Self::get_nn(opcode);
-                    match last_two{
+    // Skips must step over the whole of a four-byte F000 NNNN
+    fn skip_next_instruction(&mut self){
 
-                        0xE0 => Instruction::ClearDisplay,
-                        0xEE => Instruction::ReturnFromSubroutine,
-                        _ => Instruction::Invalid(opcode),
-                    }
-                }else{// 0NNN
-
-                    Instruction::Invalid(opcode)
-                }
-            },
-            0x1 => {// 1NNN
-                let address = Self::get_nnn(opcode);
-
-                Instruction::JumpToAddress(address)
-            },
-            0x2 => {// 2NNN
-                let address = Self::get_nnn(opcode);
-
-                Instruction::CallSubroutine(address)
-            },
-            0x3 => {// 3XNN
-                let v_x = Self::get_x(opcode);
-                let byte = Self::get_nn(opcode);
-
-                Instruction::SkipIfVxEqualsByte(v_x, byte)
-            },
-            0x4 => {// 4XNN
-                let v_x = Self::get_x(opcode);
-                let byte = Self::get_nn(opcode);
-
-                Instruction::SkipIfVxNotEqualsByte(v_x, byte)
-            },
-            0x5 => {// 5XY0
-                if Self::get_nibble(opcode, 3) == 0{
-                    let v_x = Self::get_x(opcode);
-                    let v_y = Self::get_y(opcode);
-
-                    Instruction::SkipIfVxEqualsVy(v_x, v_y)
-                }else{
-
-                    Instruction::Invalid(opcode)
-                }
-            },
-            0x6 => {// 6XNN
-                let v_x = Self::get_x(opcode);
-                let byte = Self::get_nn(opcode);
-
-                Instruction::SetVxToByte(v_x, byte)
-            },
-            0x7 => {// 7XNN
-                let v_x = Self::get_x(opcode);
-                let byte = Self::get_nn(opcode);
-
-                Instruction::AddByteToVx(v_x, byte)
-            },
-            0x8 => {
-                let v_x = Self::get_x(opcode);
-                let v_y = Self::get_y(opcode);
-                let last_nibble = Self::get_nibble(opcode, 3);
-                
-                match last_nibble{
-
-                    0x0 => Instruction::SetVxToVy(v_x, v_y),
-                    0x1 => Instruction::SetVxToVxOrVy(v_x, v_y),
-                    0x2 => Instruction::SetVxToVxAndVy(v_x, v_y),
-                    0x3 => Instruction::SetVxToVxXorVy(v_x, v_y),
-                    0x4 => Instruction::AddVyToVxWithCarry(v_x, v_y),
-                    0x5 => Instruction::SubtractVyFromVxWithBorrow(v_x, v_y),
-                    0x6 => Instruction::ShiftVxRightByOne(v_x, v_y),
-                    0x7 => Instruction::SetVxToVyMinusVx(v_x, v_y),
-                    0xE => Instruction::ShiftVxLeftByOne(v_x, v_y),
-                    _ => Instruction::Invalid(opcode),
-                }
-            },
-            0x9 => {// 9XY0
-                if Self::get_nibble(opcode, 3) ==0{
-                    let v_x = Self::get_x(opcode);
-                    let v_y = Self::get_y(opcode);
-
-                    Instruction::SkipIfVxNotEqualsVy(v_x, v_y)
-                }else{
-
-                    Instruction::Invalid(opcode)
-                }
-            },
-            0xA => {// ANNN
-                let address = Self::get_nnn(opcode);
-
-                Instruction::SetIToAddress(address)
-            },
-            0xB => {// BNNN
-                let address = Self::get_nnn(opcode);
-
-                Instruction::JumpToV0PlusAddress(address)
-            },
-            0xC => {// CXNN
-                let v_x = Self::get_x(opcode);
-                let byte = Self::get_nn(opcode);
-
-                Instruction::SetVxToRandomAndByte(v_x, byte)
-            },
-            0xD => {// DXYN
-                let v_x = Self::get_x(opcode);
-                let v_y = Self::get_y(opcode);
-                let n = Self::get_nibble(opcode, 3);
-
-                Instruction::DrawSprite(v_x, v_y, n)
-            },
-            0xE => {
-                let last_byte = Self::get_nn(opcode);
-                let v_x = Self::get_x(opcode);
-                match last_byte{
-
-                    0x9E => Instruction::SkipIfKeyInVxPressed(v_x),
-                    0xA1 => Instruction::SkipIfKeyInVxNotPressed(v_x),
-                    _ => Instruction::Invalid(opcode),
-                }
-
-            },
-            0xF => {
-                let last_byte = Self::get_nn(opcode);
-                let v_x = Self::get_x(opcode);
-                match last_byte{
-
-                    0x07 => Instruction::SetVxToDelayTimer(v_x),
-                    0x0A => Instruction::WaitForKeyPressAndStoreInVx(v_x),
-                    0x15 => Instruction::SetDelayTimerToVx(v_x),
-                    0x18 => Instruction::SetSoundTimerToVx(v_x),
-                    0x1E => Instruction::AddVxToI(v_x),
-                    0x29 => Instruction::SetIToSpriteAddressForDigitVx(v_x),
-                    0x33 => Instruction::StoreBcdOfVxAtI(v_x),
-                    0x55 => Instruction::StoreRegistersV0ThroughVxInMemory(v_x),
-                    0x65 => Instruction::ReadRegistersV0ThroughVxFromMemory(v_x),
-                    _ => Instruction::Invalid(opcode),
-                }
-
-            },
-            _ => Instruction::Invalid(opcode),
-            
-        }
+        let next_pc = self.pc.wrapping_add(2);
+        let next_opcode = self.mem.read_16(next_pc as usize).unwrap_or(0);
+        self.pc = self.pc.wrapping_add(Self::instruction_length(next_opcode));
     }
 
-    pub fn execute_instruction(&mut self, instruction:Instruction, keypad: &mut crate::keypad::Keypad, display: &mut crate::display::Display){
+    pub fn execute_instruction(&mut self, instruction:Instruction, keypad: &mut crate::keypad::Keypad, display: &mut crate::display::Display)->Result<StepOutcome, Fault>{
         let mut pc_modified = false;
+        let mut outcome = StepOutcome::Executed;
 
         match instruction{
 
@@ -408,17 +567,39 @@ impl Cpu{
                 },
                 Instruction::ReturnFromSubroutine => {// The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
 
-                    match self.pop_stack(){
+                    self.pc = self.pop_stack()?;
+                    pc_modified = true;
+                },
 
-                        Some(address) =>{
+                Instruction::ScrollDown(n) => {// Scrolls the display down by n pixel rows.
 
-                            self.pc = address;
-                            pc_modified = true;
-                        },
-                        None => {
-                            panic!("Stack underflow (cant pop a stack that is empty)")
-                        }
-                    }
+                    display.scroll_down(n as usize);
+                },
+                Instruction::ScrollRight => {// Scrolls the display right by 4 pixels.
+
+                    display.scroll_right(4);
+                },
+                Instruction::ScrollLeft => {// Scrolls the display left by 4 pixels.
+
+                    display.scroll_left(4);
+                },
+                Instruction::Exit => {// Stops the interpreter.
+
+                    self.exited = true;
+                    outcome = StepOutcome::Exited;
+                    pc_modified = true;
+                },
+                Instruction::ScrollUp(n) => {// Scrolls the display up by n pixel rows.
+
+                    display.scroll_up(n as usize);
+                },
+                Instruction::LowResolution => {// Switches to the 64x32 display and clears it.
+
+                    display.set_hires(false);
+                },
+                Instruction::HighResolution => {// Switches to the 128x64 display and clears it.
+
+                    display.set_hires(true);
                 },                  
 
                 Instruction::JumpToAddress(address) => {//The interpreter sets the program counter to nnn.
@@ -430,38 +611,44 @@ impl Cpu{
                 },
                 Instruction::CallSubroutine(address) => {// The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
 
-                    match self.push_stack(){
-
-                        Ok(()) => {
-
-                            self.pc = address;
-                            pc_modified = true;
-                        },
-                        Err(e) => {
-                            
-                            panic!("Error: {:?}", e);
-                        }
-                    }
+                    self.push_stack()?;
+                    self.pc = address;
+                    pc_modified = true;
                 },
                 Instruction::SkipIfVxEqualsByte(v_x, byte) => {// The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
 
                     if self.get_register(v_x) == byte{
 
-                        self.pc += 2;
+                        self.skip_next_instruction();
                     }
                 },
                 Instruction::SkipIfVxNotEqualsByte(v_x, byte) => {// The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
 
                     if self.get_register(v_x) != byte{
 
-                        self.pc += 2;
+                        self.skip_next_instruction();
                     }
                 },
                 Instruction::SkipIfVxEqualsVy(v_x, v_y) => {// The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
 
                     if self.get_register(v_x) == self.get_register(v_y){
 
-                        self.pc += 2;
+                        self.skip_next_instruction();
+                    }
+                },
+                Instruction::StoreRegistersVxThroughVyInMemory(v_x, v_y) => {// Copies Vx through Vy (in either order) into memory starting at I. I is not changed.
+
+                    for (index, register) in Self::register_range(v_x, v_y).enumerate(){
+
+                        self.store(self.i as usize + index, self.get_register(register))?;
+                    }
+                },
+                Instruction::ReadRegistersVxThroughVyFromMemory(v_x, v_y) => {// Reads Vx through Vy (in either order) from memory starting at I. I is not changed.
+
+                    for (index, register) in Self::register_range(v_x, v_y).enumerate(){
+
+                        let byte = self.load(self.i as usize + index)?;
+                        self.set_register(register, byte);
                     }
                 },
 
@@ -480,14 +667,17 @@ impl Cpu{
                 Instruction::SetVxToVxOrVy(v_x, v_y) => {// Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
 
                     self.set_register(v_x, self.get_register(v_x) | self.get_register(v_y));
+                    self.apply_vf_reset();
                 },
                 Instruction::SetVxToVxAndVy(v_x, v_y) => {// Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
 
                     self.set_register(v_x, self.get_register(v_x) & self.get_register(v_y));
+                    self.apply_vf_reset();
                 },
                 Instruction::SetVxToVxXorVy(v_x, v_y) => {// Performs a bitwise XOR on the values of Vx and Vy, then stores the result in Vx.
 
                     self.set_register(v_x, self.get_register(v_x) ^ self.get_register(v_y));
+                    self.apply_vf_reset();
                 },
                 Instruction::AddVyToVxWithCarry(v_x, v_y) => {// The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0.
 
@@ -511,9 +701,9 @@ impl Cpu{
                         self.set_register(0xF, 1);
                     }
                 },
-                Instruction::ShiftVxRightByOne(v_x, v_y) => {// Shifts Vy to the right by one and stores the shifted bit in Vf.
+                Instruction::ShiftVxRightByOne(v_x, v_y) => {// Shifts Vy (or Vx with the shift quirk) to the right by one and stores the shifted bit in Vf.
 
-                    let original = self.get_register(v_y);
+                    let original = self.get_register(self.shift_source(v_x, v_y));
                     let bit = original & 0x1;
                     let shifted = original >> 1;
                     self.set_register(v_x, shifted);
@@ -531,9 +721,9 @@ impl Cpu{
                         self.set_register(0xF, 1);
                     }
                 },
-                Instruction::ShiftVxLeftByOne(v_x, v_y) => {// Shifts Vy to the left by one and stores the shifted bit in Vf.
+                Instruction::ShiftVxLeftByOne(v_x, v_y) => {// Shifts Vy (or Vx with the shift quirk) to the left by one and stores the shifted bit in Vf.
 
-                    let original = self.get_register(v_y);
+                    let original = self.get_register(self.shift_source(v_x, v_y));
                     let bit = (original & 0x80) >> 7;
                     let shifted = original << 1;
                     self.set_register(v_x, shifted);
@@ -544,7 +734,7 @@ impl Cpu{
 
                     if self.get_register(v_x) != self.get_register(v_y){
 
-                        self.pc += 2;
+                        self.skip_next_instruction();
                     }
                 },
 
@@ -552,50 +742,69 @@ impl Cpu{
 
                     self.i = address;
                 },
-                Instruction::JumpToV0PlusAddress(address) => {// The program counter is set to nnn plus the value of V0.
+                Instruction::JumpToV0PlusAddress(address) => {// The program counter is set to nnn plus the value of V0 (or xnn plus Vx with the jump quirk).
 
-                    self.pc = address + self.get_register(0x0) as u16;
+                    let offset_register = if self.quirks.jump { (address >> 8) as usize } else { 0x0 };
+                    self.pc = address.wrapping_add(self.get_register(offset_register) as u16);
                     pc_modified = true;
                 },
                 Instruction::SetVxToRandomAndByte(v_x, byte) => {// The interpreter generates a random number from 0 to 255, which is then ANDed with the value nn. The results are stored in Vx.
 
-                    let rnd = rand::rng().random_range(0..=255) as u8;
+                    let rnd = self.rng.next_byte();
                     self.set_register(v_x, rnd & byte);
                 },
-                Instruction::DrawSprite(v_x, v_y, n) => {// The interpreter reads n bytes from memory, starting at the address stored in I. These bytes are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen.
+                Instruction::DrawSprite(v_x, v_y, n) => {// The interpreter reads n bytes from memory, starting at the address stored in I. These bytes are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen (or is clipped with the clip quirk). With n == 0 a 16x16 sprite of two bytes per row is drawn.
 
                     let mut collision = false;
                     self.set_register(0xF, 0);
-                    let v_x = self.get_register(v_x);
-                    let v_y = self.get_register(v_y);
+                    let (width, height) = (display.width(), display.height());
+                    let v_x = self.get_register(v_x) as usize % width;
+                    let v_y = self.get_register(v_y) as usize % height;
+                    let (rows, columns) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+                    let bytes_per_row = columns / 8;
+                    let mut sprite_addr = self.i as usize;
 
-                    //println!("Drawing sprite at ({}, {}) with {} rows", v_x, v_y, n);
-                    for row in 0..n{
+                    // XO-CHIP: each selected plane gets its own copy of the sprite data, one after the other
+                    for plane in (0..PLANE_COUNT).map(|index| 1u8 << index){
 
-                        let sprite_byte = self.mem.read_byte((self.i + row as u16) as usize);
-                        let y = ((v_y + row) % 32) as usize;
+                        if display.selected_planes() & plane == 0{
 
-                        //println!("Row {}: {:08b}", row, sprite_byte);
-                        for bit_index in 0..8{
+                            continue;
+                        }
 
-                            let x = ((v_x + bit_index) % 64) as usize;
-                            if sprite_byte & (0b1000_0000 >> bit_index) != 0{
+                        //println!("Drawing sprite at ({}, {}) with {} rows", v_x, v_y, n);
+                        for row in 0..rows{
 
-                                let was_on = display.get_pixel(x, y);
-                                display.flip_pixel(x, y);
-                                if was_on{
+                            if self.quirks.clip && v_y + row >= height{
 
-                                    collision = true;
+                                break;
+                            }
+                            // Left-aligned in 16 bits so both sprite sizes are drawn the same way
+                            let row_addr = sprite_addr + row * bytes_per_row;
+                            let sprite_row = if columns == 16{
+
+                                self.load_16(row_addr)?
+                            }else{
+
+                                (self.load(row_addr)? as u16) << 8
+                            };
+                            let y = (v_y + row) % height;
+
+                            //println!("Row {}: {:016b}", row, sprite_row);
+                            for bit_index in 0..columns{
+
+                                if self.quirks.clip && v_x + bit_index >= width{
+
+                                    break;
                                 }
+                                let x = (v_x + bit_index) % width;
+                                if sprite_row & (0x8000 >> bit_index) != 0 && display.flip_pixel(x, y, plane){
 
-                                //println!(
-                                  //  "  Pixel ON at ({}, {}), was {}",
-                                    //x,
-                                    //y,
-                                    //if was_on { "ON" } else { "OFF" }
-                                //);
+                                    collision = true;
+                                }
                             }
                         }
+                        sprite_addr += rows * bytes_per_row;
                     }
 
                     if collision{
@@ -606,25 +815,54 @@ impl Cpu{
                         self.set_register(0xF, 0);
                     }
                     //println!("VF set to {}\n", self.get_register(0xF));
+
+                    if self.quirks.display_wait{
+
+                        self.vblank_wait = true;
+                    }
                 },
 
                 Instruction::SkipIfKeyInVxPressed(v_x) => {// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
 
                     let key = self.get_register(v_x);
-                    if keypad.is_pressed(key as usize){
+                    if keypad.is_pressed(key as usize)?{
 
                         //println!("CPU sees key {:X} pressed", key);
-                        self.pc += 2;
+                        self.skip_next_instruction();
                     }
                 },
                 Instruction::SkipIfKeyInVxNotPressed(v_x) => {// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position, PC is increased by 2.
 
                     let key = self.get_register(v_x);
-                    if !keypad.is_pressed(key as usize){
+                    if !keypad.is_pressed(key as usize)?{
 
                         //intln!("CPU sees key {:X} not pressed", key);
-                        self.pc += 2;
+                        self.skip_next_instruction();
+                    }
+                },
+
+                Instruction::SetIToLongAddress(address) => {// I is set to the 16-bit address in the second word.
+
+                    self.i = address;
+                    self.pc = self.pc.wrapping_add(4);
+                    pc_modified = true;
+                },
+                Instruction::SelectPlanes(planes) => {// Selects the bitplanes that drawing, clearing and scrolling affect.
+
+                    display.select_planes(planes);
+                },
+                Instruction::LoadAudioPattern => {// Loads 16 bytes starting at I into the audio pattern buffer.
+
+                    let mut pattern = [0; 16];
+                    for (index, byte) in pattern.iter_mut().enumerate(){
+
+                        *byte = self.load(self.i as usize + index)?;
                     }
+                    self.audio_pattern = Some(pattern);
+                },
+                Instruction::SetPitchToVx(v_x) => {// Sets the audio pattern playback pitch to Vx.
+
+                    self.pitch = self.get_register(v_x);
                 },
 
                 Instruction::SetVxToDelayTimer(v_x) => {// The value of DT is placed into Vx.
@@ -635,6 +873,7 @@ impl Cpu{
 
                     self.halted = true;
                     self.wait_register = Some(v_x);
+                    outcome = StepOutcome::WaitingForKey;
                 },
                 Instruction::SetDelayTimerToVx(v_x) => {// DT is set equal to the value of Vx.
 
@@ -646,81 +885,682 @@ impl Cpu{
                 },
                 Instruction::AddVxToI(v_x) => {// The values of I and Vx are added, and the results are stored in I.
 
-                    self.i = self.i + self.get_register(v_x) as u16;
+                    self.i = self.i.wrapping_add(self.get_register(v_x) as u16);
                 },
                 Instruction::SetIToSpriteAddressForDigitVx(v_x) => {// The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx
 
-                    let font_start: usize = 0x50;
-                    let sprite_index = (self.get_register(v_x)  as usize * 5) + font_start;
+                    let sprite_index = ((self.get_register(v_x) & 0xF) as usize * 5) + FONTSET_START;
+                    self.i = sprite_index as u16;
+                },
+                Instruction::SetIToBigSpriteAddressForDigitVx(v_x) => {// The value of I is set to the location for the 8x10 hexadecimal sprite corresponding to the value of Vx
+
+                    let sprite_index = ((self.get_register(v_x) & 0xF) as usize * 10) + BIG_FONTSET_START;
                     self.i = sprite_index as u16;
                 },
                 Instruction::StoreBcdOfVxAtI(v_x) => {// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2
                     let value = self.get_register(v_x);
                     let (hundreds, tens, ones) = ((value / 100) % 10, (value / 10) % 10, value % 10);
-                    self.mem.write_byte(self.i as usize, hundreds);
-                    self.mem.write_byte((self.i + 1) as usize, tens);
-                    self.mem.write_byte((self.i + 2) as usize, ones);
+                    self.store(self.i as usize, hundreds)?;
+                    self.store(self.i as usize + 1, tens)?;
+                    self.store(self.i as usize + 2, ones)?;
 
                 },
                 Instruction::StoreRegistersV0ThroughVxInMemory(v_x) => {// The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
 
                     for (index, register) in (0x0..=v_x).enumerate(){
                         
-                        self.mem.write_byte(self.i as usize + index, self.get_register(register));
+                        self.store(self.i as usize + index, self.get_register(register))?;
                     }
+                    self.apply_load_store(v_x);
                 },
                 Instruction::ReadRegistersV0ThroughVxFromMemory(v_x) => {// The interpreter reads values from memory starting at location I into registers V0 through Vx.
 
                     for (index, register) in (0x0..=v_x).enumerate(){
 
-                        self.set_register(register, self.mem.read_byte(self.i as usize + index));
+                        let byte = self.load(self.i as usize + index)?;
+                        self.set_register(register, byte);
                     }
+                    self.apply_load_store(v_x);
+                },
+
+                Instruction::StoreRegistersV0ThroughVxInFlags(v_x) => {// The interpreter copies the values of registers V0 through Vx into the RPL user flags.
+
+                    self.flags[..=v_x].copy_from_slice(&self.v[..=v_x]);
+                },
+                Instruction::ReadRegistersV0ThroughVxFromFlags(v_x) => {// The interpreter reads the RPL user flags into registers V0 through Vx.
+
+                    self.v[..=v_x].copy_from_slice(&self.flags[..=v_x]);
                 },
 
                 Instruction::Invalid(opcode) => {// Catch all for invalid opcodes
 
-                    Self::invalid_opcode(opcode);
+                    outcome = StepOutcome::InvalidOpcode(opcode);
                 },
         }
 
         if !pc_modified{
 
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
 
+        Ok(outcome)
     }
 
-    pub fn cycle(&mut self, keypad: &mut crate::keypad::Keypad, display: &mut crate::display::Display){
+    fn register_range(v_x: usize, v_y: usize) -> Box<dyn Iterator<Item = usize>>{
+
+        if v_x <= v_y { Box::new(v_x..=v_y) } else { Box::new((v_y..=v_x).rev()) }
+    }
 
-        let opcode = self.fetch_instruction();
+    fn apply_vf_reset(&mut self){
 
-        //println!("--- Cycle Start ---");
-        //println!("PC: {:03X}", self.pc);
-        //println!("Opcode: {:04X}", opcode);
-        //println!("Registers: {:?}", self.v);
-        //println!("I: {:03X}", self.i);
-        //println!("SP: {}", self.sp);
-        //println!("Stack: {:?}", &self.stack[..self.sp as usize]);
-        //println!("DT: {}, ST: {}", self.delay_timer, self.sound_timer);
+        if self.quirks.vf_reset{
+
+            self.set_register(0xF, 0);
+        }
+    }
 
-        let instruction = Self::decode_instruction(opcode);
+    fn shift_source(&self, v_x: usize, v_y: usize) -> usize{
 
-        //println!("Decoded instruction: {:?}", instruction);
+        if self.quirks.shift { v_x } else { v_y }
+    }
+
+    fn apply_load_store(&mut self, v_x: usize){
+
+        match self.quirks.load_store{
+
+            LoadStore::Unchanged => {},
+            LoadStore::IncrementByX => self.i = self.i.wrapping_add(v_x as u16),
+            LoadStore::IncrementByXPlusOne => self.i = self.i.wrapping_add(v_x as u16 + 1),
+        }
+    }
 
-        self.execute_instruction(instruction, keypad, display);
+    pub fn step(&mut self, keypad: &mut crate::keypad::Keypad, display: &mut crate::display::Display)->Result<StepOutcome, Chip8Error>{
 
-        if self.sound_timer > 0{
+        if self.exited{
 
-            //println!("Playing audio");
-            self.sink.play();
+            return Ok(StepOutcome::Exited);
+        }
+        if self.vblank_wait{
+
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+
+        if let Some(log) = self.access_log.as_mut(){
+
+            log.clear();
+        }
+        let pc = self.pc;
+        self.cycle(keypad, display).map_err(|fault| Chip8Error::RomFault{ pc, fault })
+    }
+
+    fn cycle(&mut self, keypad: &mut crate::keypad::Keypad, display: &mut crate::display::Display)->Result<StepOutcome, Fault>{
+
+        let opcode = self.fetch_instruction()?;
+        let next = if Self::instruction_length(opcode) == 4{
+
+            self.mem.read_16(self.pc as usize + 2)?
         }else{
 
-            self.sink.pause();
-            //println!("Pausing audio");
+            0
+        };
+
+        let instruction = Self::decode_instruction_pair(opcode, next);
+        self.cycles += 1;
+        if self.trace.is_some(){
+
+            self.write_trace(opcode, instruction);
+        }
+
+        self.execute_instruction(instruction, keypad, display)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+    use crate::{display::Display, keypad::Keypad, test_support};
+
+    // A CPU with the program at 0x200 and its own keypad and display
+    struct Machine{
+
+        cpu: Cpu,
+        keypad: Keypad,
+        display: Display,
+    }
+
+    impl Machine{
+
+        fn new(program: &[u16]) -> Machine{
+
+            Machine::with_quirks(program, Quirks::default())
+        }
+
+        fn with_quirks(program: &[u16], quirks: Quirks) -> Machine{
+
+            let mut cpu = Cpu::with_quirks(quirks);
+            cpu.load_rom(&test_support::rom(program), 0x200).unwrap();
+            Machine{ cpu, keypad: Keypad::new(), display: Display::new() }
+        }
+
+        fn step(&mut self) -> StepOutcome{
+
+            self.cpu.step(&mut self.keypad, &mut self.display).unwrap()
+        }
+
+        fn run(&mut self, steps: usize){
+
+            for _ in 0..steps{
+
+                self.step();
+            }
+        }
+
+        fn v(&self, index: usize) -> u8{
+
+            self.cpu.get_register(index)
+        }
+
+        fn mem(&self, addr: usize) -> u8{
+
+            self.cpu.read_byte_from_mem(addr).unwrap()
+        }
+    }
+
+    #[test]
+    fn clear_display_00e0(){
+
+        let mut machine = Machine::new(&[0x00E0]);
+        machine.display.set_pixel(3, 4, 1);
+        machine.step();
+        assert_eq!(machine.display.get_pixel(3, 4), 0);
+        assert_eq!(machine.cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn call_2nnn_and_return_00ee(){
+
+        let mut machine = Machine::new(&[0x2206, 0x0000, 0x0000, 0x00EE]);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x206);
+        assert_eq!(machine.cpu.get_sp(), 1);
+        assert_eq!(machine.cpu.get_stack()[0], 0x202);
+
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x202);
+        assert_eq!(machine.cpu.get_sp(), 0);
+    }
+
+    #[test]
+    fn return_with_an_empty_stack_faults(){
+
+        let mut machine = Machine::new(&[0x00EE]);
+        let result = machine.cpu.step(&mut machine.keypad, &mut machine.display);
+        assert_eq!(result, Err(Chip8Error::RomFault{ pc: 0x200, fault: Fault::StackUnderflow }));
+    }
+
+    #[test]
+    fn seventeenth_call_overflows_the_stack(){
+
+        // 2200 calls itself forever
+        let mut machine = Machine::new(&[0x2200]);
+        machine.run(16);
+        let result = machine.cpu.step(&mut machine.keypad, &mut machine.display);
+        assert_eq!(result, Err(Chip8Error::RomFault{ pc: 0x200, fault: Fault::StackOverflow }));
+    }
+
+    #[test]
+    fn scroll_down_00cn_and_up_00dn(){
+
+        let mut machine = Machine::new(&[0x00C3, 0x00D2]);
+        machine.display.set_pixel(5, 1, 1);
+        machine.step();
+        assert_eq!(machine.display.get_pixel(5, 4), 1);
+        assert_eq!(machine.display.get_pixel(5, 1), 0);
+        machine.step();
+        assert_eq!(machine.display.get_pixel(5, 2), 1);
+    }
+
+    #[test]
+    fn scroll_right_00fb_and_left_00fc(){
+
+        let mut machine = Machine::new(&[0x00FB, 0x00FC, 0x00FC]);
+        machine.display.set_pixel(10, 0, 1);
+        machine.step();
+        assert_eq!(machine.display.get_pixel(14, 0), 1);
+        machine.run(2);
+        assert_eq!(machine.display.get_pixel(6, 0), 1);
+        assert_eq!(machine.display.get_pixel(10, 0), 0);
+    }
+
+    #[test]
+    fn exit_00fd_stops_the_cpu(){
+
+        let mut machine = Machine::new(&[0x00FD, 0x6005]);
+        assert_eq!(machine.step(), StepOutcome::Exited);
+        assert!(machine.cpu.has_exited());
+        assert_eq!(machine.step(), StepOutcome::Exited);
+        assert_eq!(machine.v(0), 0);
+    }
+
+    #[test]
+    fn resolution_00ff_and_00fe_switch_and_clear(){
+
+        let mut machine = Machine::new(&[0x00FF, 0x00FE]);
+        machine.display.set_pixel(1, 1, 1);
+        machine.step();
+        assert!(machine.display.is_hires());
+        assert_eq!(machine.display.get_pixel(1, 1), 0);
+        machine.display.set_pixel(1, 1, 1);
+        machine.step();
+        assert!(!machine.display.is_hires());
+        assert_eq!(machine.display.get_pixel(1, 1), 0);
+    }
+
+    #[test]
+    fn jump_1nnn(){
+
+        let mut machine = Machine::new(&[0x1ABC]);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0xABC);
+    }
+
+    #[test]
+    fn skip_if_equal_byte_3xnn(){
+
+        let mut machine = Machine::new(&[0x6142, 0x3142, 0x0000, 0x3143]);
+        machine.run(2);
+        assert_eq!(machine.cpu.get_pc(), 0x206);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x208);
+    }
+
+    #[test]
+    fn skip_if_not_equal_byte_4xnn(){
+
+        let mut machine = Machine::new(&[0x6142, 0x4142, 0x4143]);
+        machine.run(2);
+        assert_eq!(machine.cpu.get_pc(), 0x204);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x208);
+    }
+
+    #[test]
+    fn skip_if_registers_equal_5xy0_and_not_equal_9xy0(){
+
+        let mut machine = Machine::new(&[0x6107, 0x6207, 0x5120, 0x0000, 0x9120, 0x6208, 0x9120]);
+        machine.run(3);
+        assert_eq!(machine.cpu.get_pc(), 0x208);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x20A);
+        machine.run(2);
+        assert_eq!(machine.cpu.get_pc(), 0x210);
+    }
+
+    #[test]
+    fn skip_jumps_over_a_four_byte_f000(){
+
+        // 3000 skips F000 NNNN as a whole and lands on 6105
+        let mut machine = Machine::new(&[0x3000, 0xF000, 0x1234, 0x6105]);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x206);
+        machine.step();
+        assert_eq!(machine.v(1), 0x05);
+    }
+
+    #[test]
+    fn save_5xy2_and_load_5xy3_ranges_in_either_order(){
+
+        let mut machine = Machine::new(&[0x6211, 0x6322, 0x6433, 0xA300, 0x5242, 0xA310, 0x5422, 0xA300, 0x5A83]);
+        machine.run(7);
+        assert_eq!([machine.mem(0x300), machine.mem(0x301), machine.mem(0x302)], [0x11, 0x22, 0x33]);
+        assert_eq!([machine.mem(0x310), machine.mem(0x311), machine.mem(0x312)], [0x33, 0x22, 0x11]);
+        assert_eq!(machine.cpu.get_i(), 0x310);
+
+        machine.run(2);
+        assert_eq!([machine.v(0xA), machine.v(0x9), machine.v(0x8)], [0x11, 0x22, 0x33]);
+        assert_eq!(machine.cpu.get_i(), 0x300);
+    }
+
+    #[test]
+    fn load_byte_6xnn_and_add_byte_7xnn(){
+
+        let mut machine = Machine::new(&[0x6AF0, 0x7A20, 0x6F00, 0x7FFF]);
+        machine.run(2);
+        assert_eq!(machine.v(0xA), 0x10);
+        machine.run(2);
+        assert_eq!(machine.v(0xF), 0xFF, "7XNN never sets the carry");
+    }
+
+    #[test]
+    fn logic_8xy0_to_8xy3(){
+
+        let mut machine = Machine::new(&[0x610F, 0x623C, 0x8120, 0x610F, 0x8121, 0x610F, 0x8122, 0x610F, 0x8123]);
+        machine.run(3);
+        assert_eq!(machine.v(1), 0x3C);
+        machine.run(2);
+        assert_eq!(machine.v(1), 0x3F);
+        machine.run(2);
+        assert_eq!(machine.v(1), 0x0C);
+        machine.run(2);
+        assert_eq!(machine.v(1), 0x33);
+    }
+
+    #[test]
+    fn vf_reset_quirk_clears_vf_after_logic(){
+
+        let quirks = Quirks{ vf_reset: true, ..Quirks::default() };
+        let mut machine = Machine::with_quirks(&[0x6F05, 0x8121], quirks);
+        machine.run(2);
+        assert_eq!(machine.v(0xF), 0);
+
+        let mut machine = Machine::new(&[0x6F05, 0x8121]);
+        machine.run(2);
+        assert_eq!(machine.v(0xF), 5);
+    }
+
+    #[test]
+    fn add_with_carry_8xy4(){
+
+        let mut machine = Machine::new(&[0x61F0, 0x6220, 0x8124, 0x8124]);
+        machine.run(3);
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x10, 1));
+        machine.step();
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x30, 0));
+    }
+
+    #[test]
+    fn add_into_vf_8fy4_keeps_the_carry_not_the_sum(){
+
+        let mut machine = Machine::new(&[0x6FFF, 0x6102, 0x8F14, 0x6F01, 0x8F14]);
+        machine.run(3);
+        assert_eq!(machine.v(0xF), 1);
+        machine.run(2);
+        assert_eq!(machine.v(0xF), 0);
+    }
+
+    #[test]
+    fn subtract_8xy5_and_reverse_subtract_8xy7(){
+
+        let mut machine = Machine::new(&[0x6130, 0x6210, 0x8125, 0x8125, 0x6330, 0x6410, 0x8347, 0x6310, 0x6440, 0x8347]);
+        machine.run(3);
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x20, 1));
+        machine.step();
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x10, 1), "equal values don't borrow");
+
+        machine.run(3);
+        assert_eq!((machine.v(3), machine.v(0xF)), (0xE0, 0));
+        machine.run(3);
+        assert_eq!((machine.v(3), machine.v(0xF)), (0x30, 1));
+    }
+
+    #[test]
+    fn shifts_8xy6_and_8xye_use_vy(){
+
+        let mut machine = Machine::new(&[0x6203, 0x8126, 0x6281, 0x812E]);
+        machine.run(2);
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x01, 1));
+        machine.run(2);
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x02, 1));
+        assert_eq!(machine.v(2), 0x81);
+    }
+
+    #[test]
+    fn shift_quirk_shifts_vx_in_place(){
+
+        let quirks = Quirks{ shift: true, ..Quirks::default() };
+        let mut machine = Machine::with_quirks(&[0x6140, 0x62FF, 0x8126, 0x812E], quirks);
+        machine.run(3);
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x20, 0));
+        machine.step();
+        assert_eq!((machine.v(1), machine.v(0xF)), (0x40, 0));
+    }
+
+    #[test]
+    fn load_i_annn(){
+
+        let mut machine = Machine::new(&[0xA123]);
+        machine.step();
+        assert_eq!(machine.cpu.get_i(), 0x123);
+    }
+
+    #[test]
+    fn jump_with_offset_bnnn_and_bxnn_quirk(){
+
+        let mut machine = Machine::new(&[0x6004, 0x6302, 0xB300]);
+        machine.run(3);
+        assert_eq!(machine.cpu.get_pc(), 0x304);
+
+        let quirks = Quirks{ jump: true, ..Quirks::default() };
+        let mut machine = Machine::with_quirks(&[0x6004, 0x6302, 0xB300], quirks);
+        machine.run(3);
+        assert_eq!(machine.cpu.get_pc(), 0x302);
+    }
+
+    #[test]
+    fn random_cxnn_is_masked(){
+
+        let mut machine = Machine::new(&[0xC10F; 32]);
+        for _ in 0..32{
+
+            machine.step();
+            assert_eq!(machine.v(1) & 0xF0, 0);
         }
+        let mut machine = Machine::new(&[0xC100]);
+        machine.step();
+        assert_eq!(machine.v(1), 0);
+    }
+
+    #[test]
+    fn seeded_cxnn_repeats(){
+
+        let run = |seed| {
+
+            let mut machine = Machine::new(&[0xC1FF; 8]);
+            machine.cpu.set_seed(seed);
+            (0..8).map(|_| { machine.step(); machine.v(1) }).collect::<Vec<u8>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn draw_dxyn_xors_and_reports_collisions(){
 
+        // Font digit 0 at (2, 3), drawn twice
+        let mut machine = Machine::new(&[0x6000, 0xF029, 0x6102, 0x6203, 0xD125, 0xD125]);
+        machine.run(5);
+        assert_eq!(machine.display.get_pixel(2, 3), 1);
+        assert_eq!(machine.display.get_pixel(5, 3), 1);
+        assert_eq!(machine.display.get_pixel(3, 4), 0);
+        assert_eq!(machine.v(0xF), 0);
+
+        machine.step();
+        assert!(machine.display.get_buffer().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+        assert_eq!(machine.v(0xF), 1);
+    }
+
+    #[test]
+    fn draw_wraps_or_clips_at_the_edge(){
+
+        // A one-row 0xFF sprite at x = 60 spills 4 pixels past the right edge
+        let program = [0xA20C, 0x613C, 0x6200, 0xD121, 0x1208, 0x0000, 0xFF00];
+        let mut machine = Machine::new(&program);
+        machine.run(4);
+        assert_eq!(machine.display.get_pixel(63, 0), 1);
+        assert_eq!(machine.display.get_pixel(0, 0), 1);
+        assert_eq!(machine.display.get_pixel(3, 0), 1);
+
+        let quirks = Quirks{ clip: true, ..Quirks::default() };
+        let mut machine = Machine::with_quirks(&program, quirks);
+        machine.run(4);
+        assert_eq!(machine.display.get_pixel(63, 0), 1);
+        assert_eq!(machine.display.get_pixel(0, 0), 0);
     }
 
-    
+    #[test]
+    fn draw_dxy0_is_a_16x16_sprite_in_hires(){
 
-}
\ No newline at end of file
+        let mut program = vec![0x00FF, 0xA20A, 0x6100, 0xD110, 0x1208];
+        program.extend([0x8001; 16]);
+        let mut machine = Machine::new(&program);
+        machine.run(4);
+        for y in 0..16{
+
+            assert_eq!(machine.display.get_pixel(0, y), 1);
+            assert_eq!(machine.display.get_pixel(15, y), 1);
+            assert_eq!(machine.display.get_pixel(7, y), 0);
+        }
+        assert_eq!(machine.display.get_pixel(0, 16), 0);
+    }
+
+    #[test]
+    fn draw_sets_the_display_wait(){
+
+        let quirks = Quirks{ display_wait: true, ..Quirks::default() };
+        let mut machine = Machine::with_quirks(&[0xD011, 0x6005], quirks);
+        machine.step();
+        assert_eq!(machine.step(), StepOutcome::WaitingForVblank);
+        machine.cpu.end_vblank_wait();
+        machine.step();
+        assert_eq!(machine.v(0), 5);
+    }
+
+    #[test]
+    fn skip_on_key_ex9e_and_exa1(){
+
+        let mut machine = Machine::new(&[0x6107, 0xE19E, 0xE1A1, 0x0000, 0xE19E]);
+        machine.run(2);
+        assert_eq!(machine.cpu.get_pc(), 0x204);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x208);
+
+        machine.keypad.press(7);
+        machine.step();
+        assert_eq!(machine.cpu.get_pc(), 0x20C);
+    }
+
+    #[test]
+    fn key_outside_the_keypad_faults(){
+
+        let mut machine = Machine::new(&[0x6110, 0xE19E]);
+        machine.step();
+        let result = machine.cpu.step(&mut machine.keypad, &mut machine.display);
+        assert_eq!(result, Err(Chip8Error::RomFault{ pc: 0x202, fault: Fault::InvalidKey(0x10) }));
+    }
+
+    #[test]
+    fn long_i_f000_nnnn(){
+
+        let mut machine = Machine::new(&[0xF000, 0xBEEF, 0x6105]);
+        machine.step();
+        assert_eq!(machine.cpu.get_i(), 0xBEEF);
+        assert_eq!(machine.cpu.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn select_planes_fn01(){
+
+        let mut machine = Machine::new(&[0xF301, 0xF001]);
+        machine.step();
+        assert_eq!(machine.display.selected_planes(), 0b11);
+        machine.step();
+        assert_eq!(machine.display.selected_planes(), 0);
+    }
+
+    #[test]
+    fn audio_pattern_f002_and_pitch_fx3a(){
+
+        let mut machine = Machine::new(&[0xA300, 0xF002, 0x6180, 0xF13A]);
+        for index in 0..16{
+
+            machine.cpu.write_byte_to_mem(index as u8, 0x300 + index).unwrap();
+        }
+        assert_eq!(machine.cpu.audio_pattern(), None);
+        machine.run(2);
+        assert_eq!(machine.cpu.audio_pattern(), Some(core::array::from_fn(|index| index as u8)));
+        machine.run(2);
+        assert_eq!(machine.cpu.pitch(), 0x80);
+    }
+
+    #[test]
+    fn timers_fx07_fx15_fx18(){
+
+        let mut machine = Machine::new(&[0x6130, 0xF115, 0xF118, 0xF207]);
+        machine.run(3);
+        assert_eq!((machine.cpu.get_delay_timer(), machine.cpu.get_sound_timer()), (0x30, 0x30));
+        machine.cpu.decrement_delay_timer();
+        machine.step();
+        assert_eq!(machine.v(2), 0x2F);
+    }
+
+    #[test]
+    fn wait_for_key_fx0a_halts(){
+
+        let mut machine = Machine::new(&[0xF30A]);
+        assert_eq!(machine.step(), StepOutcome::WaitingForKey);
+        assert!(machine.cpu.is_halted());
+        assert_eq!(machine.cpu.get_wait_register(), Some(3));
+    }
+
+    #[test]
+    fn add_to_i_fx1e(){
+
+        let mut machine = Machine::new(&[0xAFFF, 0x6102, 0xF11E]);
+        machine.run(3);
+        assert_eq!(machine.cpu.get_i(), 0x1001);
+        assert_eq!(machine.v(0xF), 0, "FX1E leaves VF alone");
+    }
+
+    #[test]
+    fn font_addresses_fx29_and_fx30(){
+
+        let mut machine = Machine::new(&[0x611A, 0xF129, 0xF130]);
+        machine.run(2);
+        assert_eq!(machine.cpu.get_i() as usize, FONTSET_START + 0xA * 5);
+        machine.step();
+        assert_eq!(machine.cpu.get_i() as usize, BIG_FONTSET_START + 0xA * 10);
+    }
+
+    #[test]
+    fn bcd_fx33(){
+
+        let mut machine = Machine::new(&[0x61FF, 0xA300, 0xF133, 0x6107, 0xF133]);
+        machine.run(3);
+        assert_eq!([machine.mem(0x300), machine.mem(0x301), machine.mem(0x302)], [2, 5, 5]);
+        machine.run(2);
+        assert_eq!([machine.mem(0x300), machine.mem(0x301), machine.mem(0x302)], [0, 0, 7]);
+    }
+
+    #[test]
+    fn save_fx55_and_load_fx65_with_each_load_store_quirk(){
+
+        let program = [0x6011, 0x6122, 0x6233, 0xA300, 0xF255, 0xA300, 0x6000, 0xF165];
+        for (load_store, i_after) in [(LoadStore::Unchanged, 0x300), (LoadStore::IncrementByX, 0x301), (LoadStore::IncrementByXPlusOne, 0x302)]{
+
+            let mut machine = Machine::with_quirks(&program, Quirks{ load_store, ..Quirks::default() });
+            machine.run(5);
+            assert_eq!([machine.mem(0x300), machine.mem(0x301), machine.mem(0x302)], [0x11, 0x22, 0x33]);
+            machine.run(3);
+            assert_eq!(machine.v(0), 0x11);
+            assert_eq!(machine.cpu.get_i(), i_after, "{:?}", load_store);
+        }
+    }
+
+    #[test]
+    fn flags_fx75_and_fx85(){
+
+        let mut machine = Machine::new(&[0x6001, 0x6102, 0xF175, 0x6000, 0x6100, 0xF085]);
+        machine.run(6);
+        assert_eq!((machine.v(0), machine.v(1)), (0x01, 0x00));
+    }
+
+    #[test]
+    fn invalid_opcode_is_skipped(){
+
+        let mut machine = Machine::new(&[0xFFFF, 0x6105]);
+        assert_eq!(machine.step(), StepOutcome::InvalidOpcode(0xFFFF));
+        machine.step();
+        assert_eq!(machine.v(1), 5);
+    }
+}
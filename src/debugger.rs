@@ -0,0 +1,700 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{chip8::Chip8, cpu::{AccessKind, Cpu, Instruction}, expr::Condition, memory::MEMORY_SIZE, opcodes::{parse_number, parse_register}};
+
+pub const PROMPT: &str = "(rcdb) ";
+
+pub const HELP: &str = "\
+Numbers are decimal unless prefixed with 0x.
+  break <addr> [if <cond>]  b    Stop when PC reaches addr and cond holds
+  delete <addr>        d    Remove a breakpoint
+  watch <addr> [len] [r|w|rw] [if <cond>]
+                       w    Stop when the ROM reads/writes len bytes at addr (default 1, w)
+  unwatch <addr>       uw   Remove the watchpoints starting at addr
+  breakpoints          bl   List breakpoints and watchpoints
+  continue             c    Run until a breakpoint
+  pause                p    Stop running
+  step [count]         s    Execute count instructions (default 1)
+  next                 n    Step, running over subroutine calls
+  finish               fin  Run until the current subroutine returns
+  regs                 r    Show registers, I, stack and timers
+  x <addr> [len]            Examine len bytes of memory (default 16)
+  poke <addr> <byte>...     Write bytes to memory
+  set <reg> <value>         Set V0-VF, I or PC
+  dis [addr] [count]        Disassemble count instructions (default 10, from PC)
+  help                 h    Show this message
+Conditions compare V0-VF, I, PC, SP, DT, ST, numbers and memory bytes [addr] with
+== != < <= > >=, combined with ! && || and parentheses, e.g. V3 == 0x10 && DT == 0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode{
+
+    Paused,
+    Running,
+    Step(u32),
+    Next{ return_to: u16, sp: usize }, // Run until a CallSubroutine has returned
+    Finish{ sp: usize },               // Run until the stack drops below sp
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchKind{
+
+    Read,
+    Write,
+    Access, // Either
+}
+
+impl WatchKind{
+
+    fn matches(self, kind: AccessKind) -> bool{
+
+        match self{
+
+            WatchKind::Read => kind == AccessKind::Read,
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::Access => true,
+        }
+    }
+
+    fn name(self) -> &'static str{
+
+        match self{
+
+            WatchKind::Read => "r",
+            WatchKind::Write => "w",
+            WatchKind::Access => "rw",
+        }
+    }
+}
+
+struct Watchpoint{
+
+    start: usize,
+    len: usize,
+    kind: WatchKind,
+    condition: Option<Condition>,
+}
+
+/// Breakpoints, watchpoints and run control for one machine.
+///
+/// The emulator loop asks `should_execute` before every instruction and reports back
+/// through `after_step`; both return a message when execution stops so the frontend
+/// can print it. Commands typed by the user go through `execute`.
+pub struct Debugger{
+
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    mode: Mode,
+    resume_from: Option<u16>, // Breakpoint to ignore once when resuming from it
+    last_pc: u16,             // Address of the instruction being executed, for watchpoint hits
+}
+
+impl Default for Debugger{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
+}
+
+impl Debugger{
+
+    // Starts paused so breakpoints can be set before the ROM runs
+    pub fn new() -> Debugger{
+
+        Debugger{ breakpoints: BTreeMap::new(), watchpoints: Vec::new(), mode: Mode::Paused, resume_from: None, last_pc: 0 }
+    }
+
+    pub fn is_paused(&self) -> bool{
+
+        self.mode == Mode::Paused
+    }
+
+    pub fn should_execute(&mut self, machine: &Chip8) -> (bool, Option<String>){
+
+        let cpu = machine.cpu();
+        let pc = cpu.get_pc();
+        self.last_pc = pc;
+        match self.mode{
+
+            Mode::Paused => (false, None),
+            Mode::Step(_) => (true, None),
+            Mode::Running | Mode::Next{ .. } | Mode::Finish{ .. } => {
+
+                if let Some(condition) = self.breakpoints.get(&pc)
+                    && self.resume_from != Some(pc)
+                    && condition.as_ref().is_none_or(|condition| condition.holds(cpu)){
+
+                    self.mode = Mode::Paused;
+                    (false, Some(format!("Breakpoint at 0x{:03X}\n{}", pc, location(machine.cpu()))))
+                }else{
+
+                    (true, None)
+                }
+            }
+        }
+    }
+
+    pub fn after_step(&mut self, machine: &Chip8) -> Option<String>{
+
+        self.resume_from = None;
+        let cpu = machine.cpu();
+        if self.mode != Mode::Paused
+            && let Some(hit) = self.watch_hit(cpu){
+
+            self.mode = Mode::Paused;
+            return Some(format!("{}\n{}", hit, location(cpu)));
+        }
+        let stop = match self.mode{
+
+            Mode::Step(remaining) => {
+
+                self.mode = Mode::Step(remaining - 1);
+                remaining <= 1
+            },
+            Mode::Next{ return_to, sp } => cpu.get_pc() == return_to && cpu.get_sp() == sp,
+            Mode::Finish{ sp } => cpu.get_sp() < sp,
+            Mode::Paused | Mode::Running => false,
+        };
+
+        if stop{
+
+            self.mode = Mode::Paused;
+            Some(location(cpu))
+        }else{
+
+            None
+        }
+    }
+
+    // Describes the first access of the last instruction that a watchpoint is waiting for
+    fn watch_hit(&self, cpu: &Cpu) -> Option<String>{
+
+        for access in cpu.memory_accesses(){
+
+            let hit = self.watchpoints.iter().find(|watch| {
+
+                (watch.start..watch.start + watch.len).contains(&access.addr)
+                    && watch.kind.matches(access.kind)
+                    && watch.condition.as_ref().is_none_or(|condition| condition.holds(cpu))
+            });
+            if let Some(watch) = hit{
+
+                let action = if access.kind == AccessKind::Read { "read" } else { "write" };
+                let value = cpu.read_byte_from_mem(access.addr).unwrap_or(0);
+                return Some(format!("Watchpoint 0x{:03X}: {} of 0x{:03X} = 0x{:02X} at PC 0x{:03X}", watch.start, action, access.addr, value, self.last_pc));
+            }
+        }
+        None
+    }
+
+    // Stops execution, e.g. on a ROM fault, without waiting for a command
+    pub fn pause(&mut self){
+
+        self.mode = Mode::Paused;
+    }
+
+    pub fn execute(&mut self, line: &str, machine: &mut Chip8) -> String{
+
+        match self.run_command(line, machine){
+
+            Ok(output) => output,
+            Err(message) => format!("Error: {}", message),
+        }
+    }
+
+    fn run_command(&mut self, line: &str, machine: &mut Chip8) -> Result<String, String>{
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else{
+
+            return Ok(String::new());
+        };
+        let args: Vec<&str> = words.collect();
+        let pc = machine.cpu().get_pc();
+
+        match command{
+
+            "break" | "b" => {
+
+                let (args, condition) = split_condition(&args)?;
+                let addr = parse_address(arg(args, 0)?)?;
+                let message = match &condition{
+
+                    Some(condition) => format!("Breakpoint set at 0x{:03X} if {}", addr, condition),
+                    None => format!("Breakpoint set at 0x{:03X}", addr),
+                };
+                self.breakpoints.insert(addr, condition);
+                Ok(message)
+            },
+            "delete" | "d" => {
+
+                let addr = parse_address(arg(&args, 0)?)?;
+                if self.breakpoints.remove(&addr).is_some(){
+
+                    Ok(format!("Breakpoint at 0x{:03X} deleted", addr))
+                }else{
+
+                    Err(format!("no breakpoint at 0x{:03X}", addr))
+                }
+            },
+            "watch" | "w" => {
+
+                let (args, condition) = split_condition(&args)?;
+                let start = parse_address(arg(args, 0)?)? as usize;
+                let mut len = 1;
+                let mut kind = WatchKind::Write;
+                for word in &args[1..]{
+
+                    match *word{
+
+                        "r" => kind = WatchKind::Read,
+                        "w" => kind = WatchKind::Write,
+                        "rw" => kind = WatchKind::Access,
+                        _ => len = parse_number(word)?,
+                    }
+                }
+                if len == 0{
+
+                    return Err("watch length must be at least 1".to_string());
+                }
+                check_range(start, len)?;
+                let watch = Watchpoint{ start, len, kind, condition };
+                let message = format!("Watchpoint set on {}", describe_watch(&watch));
+                self.watchpoints.push(watch);
+                machine.cpu_mut().set_access_log(true);
+                Ok(message)
+            },
+            "unwatch" | "uw" => {
+
+                let start = parse_address(arg(&args, 0)?)? as usize;
+                let count = self.watchpoints.len();
+                self.watchpoints.retain(|watch| watch.start != start);
+                if self.watchpoints.len() == count{
+
+                    return Err(format!("no watchpoint at 0x{:03X}", start));
+                }
+                machine.cpu_mut().set_access_log(!self.watchpoints.is_empty());
+                Ok(format!("Watchpoints at 0x{:03X} deleted", start))
+            },
+            "breakpoints" | "bl" => {
+
+                if self.breakpoints.is_empty() && self.watchpoints.is_empty(){
+
+                    return Ok("No breakpoints".to_string());
+                }
+                let breakpoints = self.breakpoints.iter().map(|(addr, condition)| match condition{
+
+                    Some(condition) => format!("break 0x{:03X} if {}", addr, condition),
+                    None => format!("break 0x{:03X}", addr),
+                });
+                let watchpoints = self.watchpoints.iter().map(|watch| format!("watch {}", describe_watch(watch)));
+                Ok(breakpoints.chain(watchpoints).collect::<Vec<_>>().join("\n"))
+            },
+            "continue" | "c" => {
+
+                self.resume(Mode::Running, pc);
+                Ok("Continuing".to_string())
+            },
+            "pause" | "p" => {
+
+                self.mode = Mode::Paused;
+                Ok(location(machine.cpu()))
+            },
+            "step" | "s" => {
+
+                let count = match args.first(){
+
+                    Some(count) => u32::try_from(parse_number(count)?).map_err(|_| format!("step count '{}' is too large", count))?,
+                    None => 1,
+                };
+                if count == 0{
+
+                    return Err("step count must be at least 1".to_string());
+                }
+                self.resume(Mode::Step(count), pc);
+                Ok(String::new())
+            },
+            "next" | "n" => {
+
+                let mode = match current_instruction(machine.cpu()){
+
+                    Instruction::CallSubroutine(_) => Mode::Next{ return_to: pc.wrapping_add(2), sp: machine.cpu().get_sp() },
+                    _ => Mode::Step(1),
+                };
+                self.resume(mode, pc);
+                Ok(String::new())
+            },
+            "finish" | "fin" => {
+
+                let sp = machine.cpu().get_sp();
+                if sp == 0{
+
+                    return Err("not inside a subroutine".to_string());
+                }
+                self.resume(Mode::Finish{ sp }, pc);
+                Ok(String::new())
+            },
+            "regs" | "r" => Ok(registers(machine)),
+            "x" => {
+
+                let addr = parse_address(arg(&args, 0)?)? as usize;
+                let len = match args.get(1){
+
+                    Some(len) => parse_number(len)?,
+                    None => 16,
+                };
+                examine(machine.cpu(), addr, len)
+            },
+            "poke" => {
+
+                let addr = parse_address(arg(&args, 0)?)? as usize;
+                if args.len() < 2{
+
+                    return Err("poke needs at least one byte".to_string());
+                }
+                for (offset, byte) in args[1..].iter().enumerate(){
+
+                    let byte = u8::try_from(parse_number(byte)?).map_err(|_| format!("'{}' is not a byte", byte))?;
+                    machine.cpu_mut().write_byte_to_mem(byte, addr + offset).map_err(|fault| fault.to_string())?;
+                }
+                Ok(format!("Wrote {} byte(s) at 0x{:03X}", args.len() - 1, addr))
+            },
+            "set" => {
+
+                let register = arg(&args, 0)?.to_ascii_uppercase();
+                let value = parse_number(arg(&args, 1)?)?;
+                let cpu = machine.cpu_mut();
+                match register.as_str(){
+
+                    "I" => cpu.set_i(to_u16(value)?),
+                    "PC" => cpu.set_pc(to_u16(value)?),
+                    name => {
+
+                        let index = parse_register(name).ok_or(format!("unknown register '{}'", name))?;
+                        cpu.set_register(index, u8::try_from(value).map_err(|_| "value doesn't fit in a byte".to_string())?);
+                    }
+                }
+                Ok(registers(machine))
+            },
+            "dis" => {
+
+                let addr = match args.first(){
+
+                    Some(addr) => parse_address(addr)?,
+                    None => pc,
+                };
+                let count = match args.get(1){
+
+                    Some(count) => parse_number(count)?,
+                    None => 10,
+                };
+                Ok(disassemble(machine.cpu(), addr, count))
+            },
+            "help" | "h" | "?" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command '{}', try 'help'", command)),
+        }
+    }
+
+    fn resume(&mut self, mode: Mode, pc: u16){
+
+        self.mode = mode;
+        self.resume_from = Some(pc);
+    }
+}
+
+// Splits "<args> if <condition>" into the arguments and the parsed condition
+fn split_condition<'a, 'b>(args: &'b [&'a str]) -> Result<(&'b [&'a str], Option<Condition>), String>{
+
+    match args.iter().position(|&word| word == "if"){
+
+        Some(index) => Ok((&args[..index], Some(Condition::parse(&args[index + 1..].join(" "))?))),
+        None => Ok((args, None)),
+    }
+}
+
+fn describe_watch(watch: &Watchpoint) -> String{
+
+    let mut text = format!("0x{:03X}", watch.start);
+    if watch.len > 1{
+
+        let _ = write!(text, "..0x{:03X}", watch.start + watch.len - 1);
+    }
+    let _ = write!(text, " ({})", watch.kind.name());
+    if let Some(condition) = &watch.condition{
+
+        let _ = write!(text, " if {}", condition);
+    }
+    text
+}
+
+// Memory ranges typed by the user must end inside memory
+fn check_range(addr: usize, len: usize) -> Result<(), String>{
+
+    match addr.checked_add(len){
+
+        Some(end) if end <= MEMORY_SIZE => Ok(()),
+        _ => Err(format!("{} byte(s) at 0x{:03X} run past the end of memory", len, addr)),
+    }
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str, String>{
+
+    args.get(index).copied().ok_or("missing argument, try 'help'".to_string())
+}
+
+fn parse_address(text: &str) -> Result<u16, String>{
+
+    to_u16(parse_number(text)?)
+}
+
+fn to_u16(value: usize) -> Result<u16, String>{
+
+    u16::try_from(value).map_err(|_| format!("0x{:X} is out of range", value))
+}
+
+fn current_instruction(cpu: &Cpu) -> Instruction{
+
+    decode_at(cpu, cpu.get_pc()).1
+}
+
+// The opcode words at addr and the instruction they decode to
+fn decode_at(cpu: &Cpu, addr: u16) -> (Vec<u16>, Instruction){
+
+    let word = |addr: u16| {
+
+        let high = cpu.read_byte_from_mem(addr as usize).unwrap_or(0);
+        let low = cpu.read_byte_from_mem(addr as usize + 1).unwrap_or(0);
+        ((high as u16) << 8) | low as u16
+    };
+    let opcode = word(addr);
+    let next = word(addr.wrapping_add(2));
+    let words = if Cpu::instruction_length(opcode) == 4 { vec![opcode, next] } else { vec![opcode] };
+    (words, Cpu::decode_instruction_pair(opcode, next))
+}
+
+fn format_line(cpu: &Cpu, addr: u16) -> (String, u16){
+
+    let (words, instruction) = decode_at(cpu, addr);
+    let raw: Vec<String> = words.iter().map(|word| format!("{:04X}", word)).collect();
+    let marker = if addr == cpu.get_pc() { "=>" } else { "  " };
+    (format!("{} 0x{:03X}: {:<9}  {}", marker, addr, raw.join(" "), instruction), words.len() as u16 * 2)
+}
+
+fn location(cpu: &Cpu) -> String{
+
+    format_line(cpu, cpu.get_pc()).0
+}
+
+// Instructions are at least two bytes, so more than this would go round memory again
+fn disassemble(cpu: &Cpu, mut addr: u16, count: usize) -> String{
+
+    let mut lines = Vec::new();
+    for _ in 0..count.min(MEMORY_SIZE / 2){
+
+        let (line, len) = format_line(cpu, addr);
+        lines.push(line);
+        addr = addr.wrapping_add(len);
+    }
+    lines.join("\n")
+}
+
+fn registers(machine: &Chip8) -> String{
+
+    let cpu = machine.cpu();
+    let mut out = String::new();
+    let _ = writeln!(out, "PC 0x{:03X}  I 0x{:03X}  SP {}  DT {}  ST {}", cpu.get_pc(), cpu.get_i(), cpu.get_sp(), cpu.get_delay_timer(), cpu.get_sound_timer());
+    for row in 0..2{
+
+        let line: Vec<String> = (row * 8..row * 8 + 8).map(|index| format!("V{:X} {:02X}", index, cpu.get_register(index))).collect();
+        let _ = writeln!(out, "{}", line.join("  "));
+    }
+    let stack: Vec<String> = cpu.get_stack().iter().map(|addr| format!("0x{:03X}", addr)).collect();
+    let _ = write!(out, "Stack: {}", if stack.is_empty() { "(empty)".to_string() } else { stack.join(" ") });
+    out
+}
+
+fn examine(cpu: &Cpu, addr: usize, len: usize) -> Result<String, String>{
+
+    check_range(addr, len)?;
+    let mut lines = Vec::new();
+    for row_start in (addr..addr + len).step_by(16){
+
+        let row_end = (row_start + 16).min(addr + len);
+        let bytes = (row_start..row_end)
+            .map(|index| cpu.read_byte_from_mem(index).map(|byte| format!("{:02X}", byte)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|fault| fault.to_string())?;
+        lines.push(format!("0x{:03X}: {}", row_start, bytes.join(" ")));
+    }
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    // 0x200: V0 = 5, call 0x208, then V1 += 1 forever. 0x208 stores V0 at 0x300 and returns.
+    const PROGRAM: [u16; 7] = [0x6005, 0x2208, 0x7101, 0x1204, 0xA300, 0xF055, 0x00EE];
+
+    fn machine() -> Chip8{
+
+        Chip8::from_opcodes(&PROGRAM)
+    }
+
+    // Runs the machine under the debugger, as the emulator loop does, until it stops.
+    // Returns the message it stopped with.
+    fn run(debugger: &mut Debugger, machine: &mut Chip8) -> Option<String>{
+
+        for _ in 0..1000{
+
+            let (execute, message) = debugger.should_execute(machine);
+            if message.is_some() || !execute{
+
+                return message;
+            }
+            machine.step().unwrap();
+            if let Some(message) = debugger.after_step(machine){
+
+                return Some(message);
+            }
+        }
+        panic!("the debugger never stopped");
+    }
+
+    #[test]
+    fn starts_paused(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        assert!(debugger.is_paused());
+        assert_eq!(run(&mut debugger, &mut machine), None);
+        assert_eq!(machine.cpu().get_pc(), 0x200);
+    }
+
+    #[test]
+    fn breakpoints_stop_execution(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        assert_eq!(debugger.execute("break 0x208", &mut machine), "Breakpoint set at 0x208");
+        assert_eq!(debugger.execute("c", &mut machine), "Continuing");
+        let message = run(&mut debugger, &mut machine).unwrap();
+        assert!(message.starts_with("Breakpoint at 0x208\n=> 0x208: A300"), "{}", message);
+        assert!(debugger.is_paused());
+
+        // Continuing steps off the breakpoint instead of stopping on it again
+        debugger.execute("b 0x204 if V1 == 3", &mut machine);
+        debugger.execute("c", &mut machine);
+        assert!(run(&mut debugger, &mut machine).unwrap().starts_with("Breakpoint at 0x204"));
+        assert_eq!(machine.cpu().get_register(1), 3);
+        assert_eq!(debugger.execute("bl", &mut machine), "break 0x204 if V1 == 3\nbreak 0x208");
+    }
+
+    #[test]
+    fn delete_removes_breakpoints(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        debugger.execute("b 0x208", &mut machine);
+        assert_eq!(debugger.execute("d 0x208", &mut machine), "Breakpoint at 0x208 deleted");
+        assert_eq!(debugger.execute("delete 0x208", &mut machine), "Error: no breakpoint at 0x208");
+        assert_eq!(debugger.execute("bl", &mut machine), "No breakpoints");
+        assert_eq!(debugger.execute("b", &mut machine), "Error: missing argument, try 'help'");
+        assert_eq!(debugger.execute("b 0x10000", &mut machine), "Error: 0x10000 is out of range");
+    }
+
+    #[test]
+    fn watchpoints_stop_on_matching_accesses(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        assert_eq!(debugger.execute("watch 0x2FF 2 r", &mut machine), "Watchpoint set on 0x2FF..0x300 (r)");
+        debugger.execute("watch 0x300", &mut machine);
+        debugger.execute("c", &mut machine);
+        let message = run(&mut debugger, &mut machine).unwrap();
+        assert!(message.starts_with("Watchpoint 0x300: write of 0x300 = 0x05 at PC 0x20A\n"), "{}", message);
+
+        assert_eq!(debugger.execute("uw 0x300", &mut machine), "Watchpoints at 0x300 deleted");
+        assert_eq!(debugger.execute("uw 0x300", &mut machine), "Error: no watchpoint at 0x300");
+        assert_eq!(debugger.execute("w 0x300 0", &mut machine), "Error: watch length must be at least 1");
+        assert_eq!(debugger.execute("w 0xFFFF 2", &mut machine), "Error: 2 byte(s) at 0xFFFF run past the end of memory");
+    }
+
+    #[test]
+    fn step_next_and_finish(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        debugger.execute("s", &mut machine);
+        assert_eq!(run(&mut debugger, &mut machine).unwrap(), "=> 0x202: 2208       CALL 0x208");
+
+        // next runs the whole subroutine
+        debugger.execute("n", &mut machine);
+        run(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().get_pc(), machine.cpu().get_sp()), (0x204, 0));
+        assert_eq!(machine.cpu().read_byte_from_mem(0x300), Ok(5));
+
+        debugger.execute("s 3", &mut machine);
+        run(&mut debugger, &mut machine);
+        assert_eq!(machine.cpu().get_register(1), 2);
+
+        assert_eq!(debugger.execute("finish", &mut machine), "Error: not inside a subroutine");
+        assert_eq!(debugger.execute("s 0", &mut machine), "Error: step count must be at least 1");
+        assert_eq!(debugger.execute("s 5000000000", &mut machine), "Error: step count '5000000000' is too large");
+        assert_eq!(debugger.execute("s five", &mut machine), "Error: 'five' is not a number");
+    }
+
+    #[test]
+    fn finish_returns_from_the_subroutine(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        debugger.execute("s 2", &mut machine);
+        run(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().get_pc(), machine.cpu().get_sp()), (0x208, 1));
+        debugger.execute("fin", &mut machine);
+        run(&mut debugger, &mut machine);
+        assert_eq!((machine.cpu().get_pc(), machine.cpu().get_sp()), (0x204, 0));
+    }
+
+    #[test]
+    fn poke_and_examine_memory(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        assert_eq!(debugger.execute("poke 0x300 1 2 0xFF", &mut machine), "Wrote 3 byte(s) at 0x300");
+        assert_eq!(debugger.execute("x 0x300 4", &mut machine), "0x300: 01 02 FF 00");
+        assert_eq!(debugger.execute("poke 0x300 256", &mut machine), "Error: '256' is not a byte");
+        assert_eq!(debugger.execute("poke 0x300", &mut machine), "Error: poke needs at least one byte");
+        assert_eq!(debugger.execute("x 0xFFF0 17", &mut machine), "Error: 17 byte(s) at 0xFFF0 run past the end of memory");
+        assert_eq!(debugger.execute("x 0xFFFF 18446744073709551615", &mut machine), "Error: 18446744073709551615 byte(s) at 0xFFFF run past the end of memory");
+        assert_eq!(debugger.execute("x 0xFFF0", &mut machine).lines().count(), 1);
+    }
+
+    #[test]
+    fn set_registers(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        debugger.execute("set v3 0x12", &mut machine);
+        debugger.execute("set I 0x345", &mut machine);
+        let registers = debugger.execute("set pc 0x20A", &mut machine);
+        assert!(registers.starts_with("PC 0x20A  I 0x345"), "{}", registers);
+        assert_eq!(machine.cpu().get_register(3), 0x12);
+        assert_eq!(debugger.execute("set VG 1", &mut machine), "Error: unknown register 'VG'");
+        assert_eq!(debugger.execute("set V0 300", &mut machine), "Error: value doesn't fit in a byte");
+        assert_eq!(debugger.execute("set I 0x10000", &mut machine), "Error: 0x10000 is out of range");
+    }
+
+    #[test]
+    fn disassembly_is_bounded(){
+
+        let mut debugger = Debugger::new();
+        let mut machine = machine();
+        assert_eq!(debugger.execute("dis 0x200 2", &mut machine), "=> 0x200: 6005       LD V0, 0x05\n   0x202: 2208       CALL 0x208");
+        assert_eq!(debugger.execute("dis 0 1000000000", &mut machine).lines().count(), MEMORY_SIZE / 2);
+        assert_eq!(debugger.execute("frobnicate", &mut machine), "Error: unknown command 'frobnicate', try 'help'");
+    }
+}
@@ -0,0 +1,628 @@
+//! Mnemonic formatting for decoded instructions, and ROM listings made either by a linear
+//! sweep or by following the program's control flow from its entry point.
+
+use std::{collections::{BTreeMap, BTreeSet}, fmt};
+
+use crate::cpu::{Cpu, Instruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax{
+
+    #[default]
+    Cowgod, // CLS, LD I, 0x2F0, DRW V0, V1, 5
+    Octo,   // clear, i := 0x2F0, sprite v0 v1 5
+}
+
+impl Syntax{
+
+    pub fn from_name(name: &str) -> Option<Syntax>{
+
+        match name{
+
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+// Ordered so that a platform also accepts everything the ones before it do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Platform{
+
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform{
+
+    pub fn from_name(name: &str) -> Option<Platform>{
+
+        match name{
+
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str{
+
+        match self{
+
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl Instruction{
+
+    // The first platform that has this instruction
+    pub fn platform(&self) -> Platform{
+
+        match self{
+
+            Instruction::ScrollDown(_) | Instruction::ScrollRight | Instruction::ScrollLeft | Instruction::Exit
+            | Instruction::LowResolution | Instruction::HighResolution | Instruction::DrawSprite(_, _, 0)
+            | Instruction::SetIToBigSpriteAddressForDigitVx(_) | Instruction::StoreRegistersV0ThroughVxInFlags(_)
+            | Instruction::ReadRegistersV0ThroughVxFromFlags(_) => Platform::SuperChip,
+
+            Instruction::ScrollUp(_) | Instruction::StoreRegistersVxThroughVyInMemory(..) | Instruction::ReadRegistersVxThroughVyFromMemory(..)
+            | Instruction::SetIToLongAddress(_) | Instruction::SelectPlanes(_) | Instruction::LoadAudioPattern
+            | Instruction::SetPitchToVx(_) => Platform::XoChip,
+
+            _ => Platform::Chip8,
+        }
+    }
+
+    pub fn mnemonic(&self, syntax: Syntax) -> String{
+
+        match syntax{
+
+            Syntax::Cowgod => self.cowgod(&hex_address),
+            Syntax::Octo => self.octo(&hex_address),
+        }
+    }
+
+    // Same as mnemonic, with addresses that have a label written as the label
+    fn mnemonic_with_labels(&self, syntax: Syntax, labels: &BTreeMap<u16, String>) -> String{
+
+        let address = |target: u16| labels.get(&target).cloned().unwrap_or_else(|| hex_address(target));
+        match syntax{
+
+            Syntax::Cowgod => self.cowgod(&address),
+            Syntax::Octo => self.octo(&address),
+        }
+    }
+
+    fn cowgod(&self, address: &dyn Fn(u16) -> String) -> String{
+
+        match *self{
+
+            Instruction::ClearDisplay => "CLS".to_string(),
+            Instruction::ReturnFromSubroutine => "RET".to_string(),
+            Instruction::ScrollDown(n) => format!("SCD {}", n),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowResolution => "LOW".to_string(),
+            Instruction::HighResolution => "HIGH".to_string(),
+            Instruction::ScrollUp(n) => format!("SCU {}", n),
+
+            Instruction::JumpToAddress(target) => format!("JP {}", address(target)),
+            Instruction::CallSubroutine(target) => format!("CALL {}", address(target)),
+            Instruction::SkipIfVxEqualsByte(x, byte) => format!("SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipIfVxNotEqualsByte(x, byte) => format!("SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipIfVxEqualsVy(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::StoreRegistersVxThroughVyInMemory(x, y) => format!("LD [I], V{:X}-V{:X}", x, y),
+            Instruction::ReadRegistersVxThroughVyFromMemory(x, y) => format!("LD V{:X}-V{:X}, [I]", x, y),
+
+            Instruction::SetVxToByte(x, byte) => format!("LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddByteToVx(x, byte) => format!("ADD V{:X}, 0x{:02X}", x, byte),
+
+            Instruction::SetVxToVy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::SetVxToVxOrVy(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::SetVxToVxAndVy(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::SetVxToVxXorVy(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddVyToVxWithCarry(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::SubtractVyFromVxWithBorrow(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftVxRightByOne(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::SetVxToVyMinusVx(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftVxLeftByOne(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+
+            Instruction::SkipIfVxNotEqualsVy(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+
+            Instruction::SetIToAddress(target) => format!("LD I, {}", address(target)),
+            Instruction::JumpToV0PlusAddress(target) => format!("JP V0, {}", address(target)),
+            Instruction::SetVxToRandomAndByte(x, byte) => format!("RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::DrawSprite(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+
+            Instruction::SkipIfKeyInVxPressed(x) => format!("SKP V{:X}", x),
+            Instruction::SkipIfKeyInVxNotPressed(x) => format!("SKNP V{:X}", x),
+
+            Instruction::SetIToLongAddress(target) => format!("LD I, LONG {}", address(target)),
+            Instruction::SelectPlanes(planes) => format!("PLANE {}", planes),
+            Instruction::LoadAudioPattern => "AUDIO".to_string(),
+            Instruction::SetPitchToVx(x) => format!("PITCH V{:X}", x),
+            Instruction::SetVxToDelayTimer(x) => format!("LD V{:X}, DT", x),
+            Instruction::WaitForKeyPressAndStoreInVx(x) => format!("LD V{:X}, K", x),
+            Instruction::SetDelayTimerToVx(x) => format!("LD DT, V{:X}", x),
+            Instruction::SetSoundTimerToVx(x) => format!("LD ST, V{:X}", x),
+            Instruction::AddVxToI(x) => format!("ADD I, V{:X}", x),
+            Instruction::SetIToSpriteAddressForDigitVx(x) => format!("LD F, V{:X}", x),
+            Instruction::SetIToBigSpriteAddressForDigitVx(x) => format!("LD HF, V{:X}", x),
+            Instruction::StoreBcdOfVxAtI(x) => format!("LD B, V{:X}", x),
+            Instruction::StoreRegistersV0ThroughVxInMemory(x) => format!("LD [I], V{:X}", x),
+            Instruction::ReadRegistersV0ThroughVxFromMemory(x) => format!("LD V{:X}, [I]", x),
+            Instruction::StoreRegistersV0ThroughVxInFlags(x) => format!("LD R, V{:X}", x),
+            Instruction::ReadRegistersV0ThroughVxFromFlags(x) => format!("LD V{:X}, R", x),
+
+            Instruction::Invalid(opcode) => format!("DW 0x{:04X}", opcode),
+        }
+    }
+
+    // Octo skips are written as the condition under which the next instruction runs
+    fn octo(&self, address: &dyn Fn(u16) -> String) -> String{
+
+        match *self{
+
+            Instruction::ClearDisplay => "clear".to_string(),
+            Instruction::ReturnFromSubroutine => "return".to_string(),
+            Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::LowResolution => "lores".to_string(),
+            Instruction::HighResolution => "hires".to_string(),
+            Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+
+            Instruction::JumpToAddress(target) => format!("jump {}", address(target)),
+            Instruction::CallSubroutine(target) => format!(":call {}", address(target)),
+            Instruction::SkipIfVxEqualsByte(x, byte) => format!("if v{:x} != 0x{:02X} then", x, byte),
+            Instruction::SkipIfVxNotEqualsByte(x, byte) => format!("if v{:x} == 0x{:02X} then", x, byte),
+            Instruction::SkipIfVxEqualsVy(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::StoreRegistersVxThroughVyInMemory(x, y) => format!("save v{:x} - v{:x}", x, y),
+            Instruction::ReadRegistersVxThroughVyFromMemory(x, y) => format!("load v{:x} - v{:x}", x, y),
+
+            Instruction::SetVxToByte(x, byte) => format!("v{:x} := 0x{:02X}", x, byte),
+            Instruction::AddByteToVx(x, byte) => format!("v{:x} += 0x{:02X}", x, byte),
+
+            Instruction::SetVxToVy(x, y) => format!("v{:x} := v{:x}", x, y),
+            Instruction::SetVxToVxOrVy(x, y) => format!("v{:x} |= v{:x}", x, y),
+            Instruction::SetVxToVxAndVy(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Instruction::SetVxToVxXorVy(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::AddVyToVxWithCarry(x, y) => format!("v{:x} += v{:x}", x, y),
+            Instruction::SubtractVyFromVxWithBorrow(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Instruction::ShiftVxRightByOne(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::SetVxToVyMinusVx(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Instruction::ShiftVxLeftByOne(x, y) => format!("v{:x} <<= v{:x}", x, y),
+
+            Instruction::SkipIfVxNotEqualsVy(x, y) => format!("if v{:x} == v{:x} then", x, y),
+
+            Instruction::SetIToAddress(target) => format!("i := {}", address(target)),
+            Instruction::JumpToV0PlusAddress(target) => format!("jump0 {}", address(target)),
+            Instruction::SetVxToRandomAndByte(x, byte) => format!("v{:x} := random 0x{:02X}", x, byte),
+            Instruction::DrawSprite(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+
+            Instruction::SkipIfKeyInVxPressed(x) => format!("if v{:x} -key then", x),
+            Instruction::SkipIfKeyInVxNotPressed(x) => format!("if v{:x} key then", x),
+
+            Instruction::SetIToLongAddress(target) => format!("i := long {}", address(target)),
+            Instruction::SelectPlanes(planes) => format!("plane {}", planes),
+            Instruction::LoadAudioPattern => "audio".to_string(),
+            Instruction::SetPitchToVx(x) => format!("pitch := v{:x}", x),
+            Instruction::SetVxToDelayTimer(x) => format!("v{:x} := delay", x),
+            Instruction::WaitForKeyPressAndStoreInVx(x) => format!("v{:x} := key", x),
+            Instruction::SetDelayTimerToVx(x) => format!("delay := v{:x}", x),
+            Instruction::SetSoundTimerToVx(x) => format!("buzzer := v{:x}", x),
+            Instruction::AddVxToI(x) => format!("i += v{:x}", x),
+            Instruction::SetIToSpriteAddressForDigitVx(x) => format!("i := hex v{:x}", x),
+            Instruction::SetIToBigSpriteAddressForDigitVx(x) => format!("i := bighex v{:x}", x),
+            Instruction::StoreBcdOfVxAtI(x) => format!("bcd v{:x}", x),
+            Instruction::StoreRegistersV0ThroughVxInMemory(x) => format!("save v{:x}", x),
+            Instruction::ReadRegistersV0ThroughVxFromMemory(x) => format!("load v{:x}", x),
+            Instruction::StoreRegistersV0ThroughVxInFlags(x) => format!("saveflags v{:x}", x),
+            Instruction::ReadRegistersV0ThroughVxFromFlags(x) => format!("loadflags v{:x}", x),
+
+            Instruction::Invalid(opcode) => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+        }
+    }
+}
+
+// Cowgod syntax, which is also what the debugger shows
+impl fmt::Display for Instruction{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        write!(f, "{}", self.cowgod(&hex_address))
+    }
+}
+
+fn hex_address(address: u16) -> String{
+
+    format!("0x{:03X}", address)
+}
+
+/// Decodes the instruction at the start of `bytes` as `platform` sees it, returning it
+/// with its length in bytes. Opcodes the platform doesn't have come back as `Invalid`.
+/// Returns None if `bytes` is shorter than an opcode.
+pub fn decode(bytes: &[u8], platform: Platform) -> Option<(Instruction, usize)>{
+
+    let word = |index: usize| ((bytes[index] as u16) << 8) | bytes[index + 1] as u16;
+    if bytes.len() < 2{
+
+        return None;
+    }
+    let opcode = word(0);
+    if platform == Platform::XoChip && Cpu::instruction_length(opcode) == 4 && bytes.len() >= 4{
+
+        return Some((Cpu::decode_instruction_pair(opcode, word(2)), 4));
+    }
+    match Cpu::decode_instruction(opcode){
+
+        instruction if instruction.platform() > platform => Some((Instruction::Invalid(opcode), 2)),
+        instruction => Some((instruction, 2)),
+    }
+}
+
+/// Lists `rom` loaded at `origin` one instruction per line: address, raw bytes, mnemonic.
+pub fn listing(rom: &[u8], origin: u16, syntax: Syntax, platform: Platform) -> String{
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len(){
+
+        let addr = origin as usize + offset;
+        let Some((instruction, len)) = decode(&rom[offset..], platform) else{
+
+            // A trailing odd byte can only be data
+            let data = match syntax{
+
+                Syntax::Cowgod => format!("DB 0x{:02X}", rom[offset]),
+                Syntax::Octo => format!("0x{:02X}", rom[offset]),
+            };
+            lines.push(format!("0x{:03X}: {:<9}  {}", addr, format!("{:02X}", rom[offset]), data));
+            break;
+        };
+        let raw: Vec<String> = rom[offset..offset + len].chunks(2).map(|word| format!("{:02X}{:02X}", word[0], word[1])).collect();
+        lines.push(format!("0x{:03X}: {:<9}  {}", addr, raw.join(" "), instruction.mnemonic(syntax)));
+        offset += len;
+    }
+    lines.join("\n")
+}
+
+// What the control flow trace found out about each byte of the ROM
+struct Trace{
+
+    code: BTreeMap<usize, (Instruction, usize)>, // ROM offset -> instruction and its length
+    sprites: BTreeMap<usize, usize>,             // ROM offset of a sprite row -> bytes per row
+    jump_targets: BTreeSet<u16>,
+    data_targets: BTreeSet<u16>,                 // Addresses loaded into I
+}
+
+// Follows every path from `origin`, tracking the value of I where it's known so the
+// rows a DXYN draws can be told apart from code.
+fn trace(rom: &[u8], origin: u16, platform: Platform) -> Trace{
+
+    let mut result = Trace{ code: BTreeMap::new(), sprites: BTreeMap::new(), jump_targets: BTreeSet::new(), data_targets: BTreeSet::new() };
+    let offset_of = |addr: u16| (addr as usize).checked_sub(origin as usize).filter(|&offset| offset + 1 < rom.len());
+    let mut pending: Vec<(u16, Option<u16>)> = vec![(origin, None)];
+
+    while let Some((start, mut i)) = pending.pop(){
+
+        let mut addr = start;
+        while let Some(offset) = offset_of(addr){
+
+            if result.code.contains_key(&offset){
+
+                break;
+            }
+            let Some((instruction, len)) = decode(&rom[offset..], platform) else{
+
+                break;
+            };
+            if let Instruction::Invalid(_) = instruction{
+
+                break;
+            }
+            result.code.insert(offset, (instruction, len));
+            let next = addr.wrapping_add(len as u16);
+
+            match instruction{
+
+                Instruction::JumpToAddress(target) => {
+
+                    result.jump_targets.insert(target);
+                    pending.push((target, i));
+                    break;
+                },
+                Instruction::JumpToV0PlusAddress(target) => {
+
+                    // Usually a jump table, whose first entry at least is code
+                    result.jump_targets.insert(target);
+                    pending.push((target, i));
+                    break;
+                },
+                Instruction::CallSubroutine(target) => {
+
+                    result.jump_targets.insert(target);
+                    pending.push((target, i));
+                    i = None; // The subroutine may have changed it
+                },
+                Instruction::ReturnFromSubroutine | Instruction::Exit => break,
+
+                Instruction::SkipIfVxEqualsByte(..) | Instruction::SkipIfVxNotEqualsByte(..) | Instruction::SkipIfVxEqualsVy(..)
+                | Instruction::SkipIfVxNotEqualsVy(..) | Instruction::SkipIfKeyInVxPressed(_) | Instruction::SkipIfKeyInVxNotPressed(_) => {
+
+                    // The skipped instruction might be F000 NNNN, so skip by its length
+                    if let Some(next_offset) = offset_of(next)
+                        && let Some((_, skipped)) = decode(&rom[next_offset..], platform){
+
+                        pending.push((next.wrapping_add(skipped as u16), i));
+                    }
+                },
+
+                Instruction::SetIToAddress(target) | Instruction::SetIToLongAddress(target) => {
+
+                    result.data_targets.insert(target);
+                    i = Some(target);
+                },
+                Instruction::DrawSprite(_, _, n) => {
+
+                    if let Some(sprite) = i{
+
+                        let (rows, row_bytes) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+                        for row in 0..rows{
+
+                            let row_addr = sprite as usize + row * row_bytes;
+                            if let Some(offset) = row_addr.checked_sub(origin as usize).filter(|&offset| offset < rom.len()){
+
+                                result.sprites.entry(offset).or_insert(row_bytes);
+                            }
+                        }
+                    }
+                },
+                Instruction::AddVxToI(_) | Instruction::SetIToSpriteAddressForDigitVx(_) | Instruction::SetIToBigSpriteAddressForDigitVx(_)
+                | Instruction::StoreRegistersV0ThroughVxInMemory(_) | Instruction::ReadRegistersV0ThroughVxFromMemory(_) => i = None,
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+    result
+}
+
+fn label_name(addr: u16, code: bool) -> String{
+
+    if code { format!("L{:03X}", addr) } else { format!("D{:03X}", addr) }
+}
+
+fn sprite_art(bits: u16, width: usize) -> String{
+
+    (0..width).map(|bit| if bits & (0x8000 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+/// Lists the code reachable from `origin` as instructions with labels on jump and call
+/// targets. Bytes drawn by DXYN are shown as sprite rows, anything else as plain data.
+pub fn trace_listing(rom: &[u8], origin: u16, syntax: Syntax, platform: Platform) -> String{
+
+    let result = trace(rom, origin, platform);
+    let in_rom = |addr: &u16| (*addr as usize).checked_sub(origin as usize).is_some_and(|offset| offset < rom.len());
+    let mut labels: BTreeMap<u16, String> = result.data_targets.iter().filter(|addr| in_rom(addr)).map(|&addr| (addr, label_name(addr, false))).collect();
+    labels.extend(result.jump_targets.iter().filter(|addr| in_rom(addr)).map(|&addr| (addr, label_name(addr, true))));
+
+    let (data, comment) = match syntax{
+
+        Syntax::Cowgod => ("DB ", ";"),
+        Syntax::Octo => ("", "#"),
+    };
+    let byte_list = |bytes: &[u8]| {
+
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        match syntax{
+
+            Syntax::Cowgod => bytes.join(", "),
+            Syntax::Octo => bytes.join(" "),
+        }
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len(){
+
+        let addr = (origin as usize + offset) as u16;
+        if let Some(label) = labels.get(&addr){
+
+            lines.push(match syntax{
+
+                Syntax::Cowgod => format!("{}:", label),
+                Syntax::Octo => format!(": {}", label),
+            });
+        }
+
+        let len = if let Some(&(instruction, len)) = result.code.get(&offset){
+
+            let raw: Vec<String> = rom[offset..offset + len].chunks(2).map(|word| format!("{:02X}{:02X}", word[0], word[1])).collect();
+            lines.push(format!("0x{:03X}: {:<9}  {}", addr, raw.join(" "), instruction.mnemonic_with_labels(syntax, &labels)));
+            len
+        }else if let Some(&row_bytes) = result.sprites.get(&offset){
+
+            let row = &rom[offset..(offset + row_bytes).min(rom.len())];
+            let bits = row.iter().enumerate().fold(0u16, |bits, (index, &byte)| bits | (byte as u16) << (8 - index * 8));
+            let raw: String = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+            lines.push(format!("0x{:03X}: {:<9}  {}{:<12} {} {}", addr, raw, data, byte_list(row), comment, sprite_art(bits, row_bytes * 8)));
+            row.len()
+        }else{
+
+            // Plain data runs up to 4 bytes a line, stopping early at anything listed differently
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < 4 && !result.code.contains_key(&end) && !result.sprites.contains_key(&end)
+                && !labels.contains_key(&((origin as usize + end) as u16)){
+
+                end += 1;
+            }
+            let raw: String = rom[offset..end].iter().map(|byte| format!("{:02X}", byte)).collect();
+            lines.push(format!("0x{:03X}: {:<9}  {}{}", addr, raw, data, byte_list(&rom[offset..end])));
+            end - offset
+        };
+        offset += len;
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn mnemonics_in_both_syntaxes(){
+
+        let cases = [
+            (0x00E0, "CLS", "clear"),
+            (0x00EE, "RET", "return"),
+            (0x1234, "JP 0x234", "jump 0x234"),
+            (0x2456, "CALL 0x456", ":call 0x456"),
+            (0x3A12, "SE VA, 0x12", "if va != 0x12 then"),
+            (0x4A12, "SNE VA, 0x12", "if va == 0x12 then"),
+            (0x5120, "SE V1, V2", "if v1 != v2 then"),
+            (0x5122, "LD [I], V1-V2", "save v1 - v2"),
+            (0x6C0F, "LD VC, 0x0F", "vc := 0x0F"),
+            (0x8124, "ADD V1, V2", "v1 += v2"),
+            (0x8127, "SUBN V1, V2", "v1 =- v2"),
+            (0x812E, "SHL V1, V2", "v1 <<= v2"),
+            (0xA2F0, "LD I, 0x2F0", "i := 0x2F0"),
+            (0xB300, "JP V0, 0x300", "jump0 0x300"),
+            (0xC1FF, "RND V1, 0xFF", "v1 := random 0xFF"),
+            (0xD015, "DRW V0, V1, 5", "sprite v0 v1 5"),
+            (0xE19E, "SKP V1", "if v1 -key then"),
+            (0xE1A1, "SKNP V1", "if v1 key then"),
+            (0xF10A, "LD V1, K", "v1 := key"),
+            (0xF129, "LD F, V1", "i := hex v1"),
+            (0xF133, "LD B, V1", "bcd v1"),
+            (0xF165, "LD V1, [I]", "load v1"),
+            (0x00C4, "SCD 4", "scroll-down 4"),
+            (0x00FF, "HIGH", "hires"),
+            (0xF175, "LD R, V1", "saveflags v1"),
+            (0xF201, "PLANE 2", "plane 2"),
+            (0xF13A, "PITCH V1", "pitch := v1"),
+            (0x5001, "DW 0x5001", "0x50 0x01"),
+        ];
+        for (opcode, cowgod, octo) in cases{
+
+            let instruction = Cpu::decode_instruction(opcode);
+            assert_eq!(instruction.mnemonic(Syntax::Cowgod), cowgod, "{:04X}", opcode);
+            assert_eq!(instruction.mnemonic(Syntax::Octo), octo, "{:04X}", opcode);
+            assert_eq!(instruction.to_string(), cowgod);
+        }
+        assert_eq!(Cpu::decode_instruction_pair(0xF000, 0xBEEF).mnemonic(Syntax::Cowgod), "LD I, LONG 0xBEEF");
+        assert_eq!(Cpu::decode_instruction_pair(0xF000, 0xBEEF).mnemonic(Syntax::Octo), "i := long 0xBEEF");
+    }
+
+    #[test]
+    fn platforms(){
+
+        assert_eq!(Cpu::decode_instruction(0x00E0).platform(), Platform::Chip8);
+        assert_eq!(Cpu::decode_instruction(0xD015).platform(), Platform::Chip8);
+        assert_eq!(Cpu::decode_instruction(0xD010).platform(), Platform::SuperChip);
+        assert_eq!(Cpu::decode_instruction(0x00FD).platform(), Platform::SuperChip);
+        assert_eq!(Cpu::decode_instruction(0xF130).platform(), Platform::SuperChip);
+        assert_eq!(Cpu::decode_instruction(0x00D2).platform(), Platform::XoChip);
+        assert_eq!(Cpu::decode_instruction(0x5122).platform(), Platform::XoChip);
+        assert_eq!(Cpu::decode_instruction(0xF002).platform(), Platform::XoChip);
+        assert!(Platform::Chip8 < Platform::SuperChip && Platform::SuperChip < Platform::XoChip);
+        for name in ["chip8", "schip", "xochip"]{
+
+            assert_eq!(Platform::from_name(name).unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn decode_follows_the_platform(){
+
+        let rom = test_support::rom(&[0xF000, 0xBEEF]);
+        assert_eq!(decode(&rom, Platform::XoChip), Some((Instruction::SetIToLongAddress(0xBEEF), 4)));
+        assert_eq!(decode(&rom, Platform::Chip8), Some((Instruction::Invalid(0xF000), 2)));
+        assert_eq!(decode(&test_support::rom(&[0x00FF]), Platform::Chip8), Some((Instruction::Invalid(0x00FF), 2)));
+        assert_eq!(decode(&test_support::rom(&[0x00FF]), Platform::SuperChip), Some((Instruction::HighResolution, 2)));
+        // F000 without its address is read as a plain opcode, and less than an opcode is nothing
+        assert_eq!(decode(&rom[..2], Platform::XoChip), Some((Instruction::Invalid(0xF000), 2)));
+        assert_eq!(decode(&rom[..1], Platform::XoChip), None);
+        assert_eq!(decode(&[], Platform::XoChip), None);
+    }
+
+    #[test]
+    fn linear_listing(){
+
+        let mut rom = test_support::rom(&[0x00E0, 0xF000, 0x0300]);
+        rom.push(0xAB);
+        assert_eq!(listing(&rom, 0x200, Syntax::Cowgod, Platform::XoChip), "\
+0x200: 00E0       CLS
+0x202: F000 0300  LD I, LONG 0x300
+0x206: AB         DB 0xAB");
+        assert_eq!(listing(&rom, 0x200, Syntax::Octo, Platform::Chip8), "\
+0x200: 00E0       clear
+0x202: F000       0xF0 0x00
+0x204: 0300       0x03 0x00
+0x206: AB         0xAB");
+    }
+
+    // I is set to a sprite that's drawn, then a subroutine is called and the program loops.
+    // 0x208 is never reached.
+    const TRACED: [u16; 8] = [0xA20C, 0xD012, 0x220A, 0x1206, 0x0102, 0x00EE, 0x3C81, 0x5566];
+
+    #[test]
+    fn trace_listing_labels_code_and_sprites(){
+
+        assert_eq!(trace_listing(&test_support::rom(&TRACED), 0x200, Syntax::Cowgod, Platform::Chip8), "\
+0x200: A20C       LD I, D20C
+0x202: D012       DRW V0, V1, 2
+0x204: 220A       CALL L20A
+L206:
+0x206: 1206       JP L206
+0x208: 0102       DB 0x01, 0x02
+L20A:
+0x20A: 00EE       RET
+D20C:
+0x20C: 3C         DB 0x3C         ; ..####..
+0x20D: 81         DB 0x81         ; #......#
+0x20E: 5566       DB 0x55, 0x66");
+    }
+
+    #[test]
+    fn trace_listing_in_octo_syntax(){
+
+        assert_eq!(trace_listing(&test_support::rom(&TRACED), 0x200, Syntax::Octo, Platform::Chip8), "\
+0x200: A20C       i := D20C
+0x202: D012       sprite v0 v1 2
+0x204: 220A       :call L20A
+: L206
+0x206: 1206       jump L206
+0x208: 0102       0x01 0x02
+: L20A
+0x20A: 00EE       return
+: D20C
+0x20C: 3C         0x3C         # ..####..
+0x20D: 81         0x81         # #......#
+0x20E: 5566       0x55 0x66");
+    }
+
+    #[test]
+    fn trace_listing_shows_16x16_sprite_rows(){
+
+        let rom = test_support::rom(&[0x00FF, 0xA206, 0xD010, 0xF00F, 0x8001]);
+        assert_eq!(trace_listing(&rom, 0x200, Syntax::Cowgod, Platform::SuperChip), "\
+0x200: 00FF       HIGH
+0x202: A206       LD I, D206
+0x204: D010       DRW V0, V1, 0
+D206:
+0x206: F00F       DB 0xF0, 0x0F   ; ####........####
+0x208: 8001       DB 0x80, 0x01   ; #..............#");
+    }
+}
@@ -1,59 +1,203 @@
+use crate::savestate::{StateError, StateReader, StateWriter};
 
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// XO-CHIP draws into up to four bitplanes, so a pixel is a colour index 0..16
+pub const PLANE_COUNT: usize = 4;
+pub const ALL_PLANES: u8 = 0b1111;
+
+// The buffer is always sized for hi-res; in lo-res only the top-left 64x32 is used.
+// Each pixel holds one bit per plane.
 pub struct Display{
 
-    buffer: [[bool; 64]; 32],
+    buffer: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    hires: bool,
+    planes: u8, // Planes selected by FN01, the target of drawing, clearing and scrolling
     needs_update: bool,
 }
 
+impl Default for Display{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
+}
+
 impl Display{
 
     pub fn new() -> Display{
 
-        let buffer = [[false; 64]; 32];
+        let buffer = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        let hires = false;
+        let planes = 0b0001;
         let needs_update = false;
-        Display{ buffer, needs_update }
+        Display{ buffer, hires, planes, needs_update }
+    }
+
+    pub fn width(&self) -> usize{
+
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize{
+
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    pub fn is_hires(&self) -> bool{
+
+        self.hires
+    }
+
+    // Switching resolution clears every plane, as SUPER-CHIP and XO-CHIP do
+    pub fn set_hires(&mut self, hires: bool){
+
+        self.hires = hires;
+        self.buffer = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.needs_update = true;
+    }
+
+    pub fn selected_planes(&self) -> u8{
+
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8){
+
+        self.planes = planes & ALL_PLANES;
     }
 
-    pub fn get_pixel(&self, x: usize, y: usize) -> bool{
+    // Colour index of the pixel: bit n is set when the pixel is on in plane n
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8{
 
-        if Self::bound(x, y){
+        if self.bound(x, y){
 
             self.buffer[y][x]
         }else {
             
-            false
+            0
         }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, value: bool){
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: u8){
 
-        if Self::bound(x, y){
+        if self.bound(x, y){
 
-            self.buffer[y][x] = value;
+            self.buffer[y][x] = value & ALL_PLANES;
             self.needs_update = true;
         }
     }
 
-    pub fn flip_pixel(&mut self, x: usize, y: usize){
+    // Flips the pixel in a single plane (a one-bit mask) and returns true if it was turned off
+    pub fn flip_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool{
 
-        if Self::bound(x, y){
+        if self.bound(x, y){
 
-            self.buffer[y][x] = !self.buffer[y][x];
+            let was_on = self.buffer[y][x] & plane != 0;
+            self.buffer[y][x] ^= plane;
             self.needs_update = true;
+            was_on
+        }else{
+
+            false
         }
     }
 
-    fn bound(x: usize, y: usize) -> bool{
+    fn bound(&self, x: usize, y: usize) -> bool{
 
-        x < 64 && y < 32
+        x < self.width() && y < self.height()
     }
 
+    // Only the selected planes are cleared
     pub fn clear(&mut self){
 
-        self.buffer = [[false; 64]; 32];
+        let keep = !self.planes;
+        for row in self.buffer.iter_mut(){
+
+            for pixel in row.iter_mut(){
+
+                *pixel &= keep;
+            }
+        }
         self.needs_update = true;
     }
 
+    // Moves the selected planes' bits from (x, y) to (x + dx, y + dy), leaving other planes untouched
+    fn shift(&mut self, dx: isize, dy: isize){
+
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.planes;
+        let source = self.buffer;
+
+        for y in 0..height{
+
+            for x in 0..width{
+
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y){
+
+                    source[from_y as usize][from_x as usize] & planes
+                }else{
+
+                    0
+                };
+                let pixel = &mut self.buffer[y as usize][x as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+        self.needs_update = true;
+    }
+
+    pub fn scroll_down(&mut self, rows: usize){
+
+        self.shift(0, rows as isize);
+    }
+
+    pub fn scroll_up(&mut self, rows: usize){
+
+        self.shift(0, -(rows as isize));
+    }
+
+    pub fn scroll_right(&mut self, columns: usize){
+
+        self.shift(columns as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize){
+
+        self.shift(-(columns as isize), 0);
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter){
+
+        state.put_bool(self.hires);
+        state.put_u8(self.planes);
+        for row in self.buffer.iter(){
+
+            state.put_bytes(row);
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>{
+
+        self.hires = state.get_bool()?;
+        self.planes = state.get_u8()? & ALL_PLANES;
+        for row in self.buffer.iter_mut(){
+
+            *row = state.get_array()?;
+            if row.iter().any(|&pixel| pixel & !ALL_PLANES != 0){
+
+                return Err(StateError::Corrupt("invalid pixel value"));
+            }
+        }
+        self.needs_update = true;
+        Ok(())
+    }
+
     pub fn needs_update(&self) -> bool{
 
         self.needs_update
@@ -64,9 +208,10 @@ impl Display{
         self.needs_update = value;
     }
 
-    pub fn get_buffer(&self) -> &[[bool; 64]]{
+    // Rows of the active resolution; only the first `width()` columns of each row are in use
+    pub fn get_buffer(&self) -> &[[u8; HIRES_WIDTH]]{
 
-        self.buffer.as_ref()
+        &self.buffer[..self.height()]
     }
 
-}
\ No newline at end of file
+}
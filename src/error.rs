@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// What went wrong while executing an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault{
+
+    StackOverflow,
+    StackUnderflow,
+    AddressOutOfRange(usize),
+    InvalidKey(u8),
+}
+
+impl fmt::Display for Fault{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::AddressOutOfRange(addr) => write!(f, "memory address 0x{:X} out of range", addr),
+            Fault::InvalidKey(key) => write!(f, "invalid key 0x{:02X}", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error{
+
+    // The ROM did something the machine can't do; pc is the faulting instruction
+    RomFault{ pc: u16, fault: Fault },
+    RomTooLarge{ size: usize, capacity: usize },
+}
+
+impl fmt::Display for Chip8Error{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            Chip8Error::RomFault{ pc, fault } => write!(f, "ROM fault at PC 0x{:03X}: {}", pc, fault),
+            Chip8Error::RomTooLarge{ size, capacity } => write!(f, "ROM is {} bytes but only {} fit in memory", size, capacity),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error{}
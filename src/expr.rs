@@ -0,0 +1,419 @@
+//! Conditions for debugger breakpoints and watchpoints, e.g. `V3 == 0x10 && DT == 0`.
+//!
+//! Operands are numbers (decimal or 0x hex), the registers V0-VF, I, PC, SP, DT and ST,
+//! and memory bytes written as `[addr]`. Comparisons (`== != < <= > >=`), `!`, `&&`, `||`
+//! and parentheses combine them; anything non-zero is true.
+
+use std::fmt;
+
+use crate::{cpu::Cpu, opcodes::{self, parse_number}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register{
+
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator{
+
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr{
+
+    Number(u32),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token{
+
+    Number(u32),
+    Name(String),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for Token{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        let symbol = match self{
+
+            Token::Number(value) => return write!(f, "'{}'", value),
+            Token::Name(name) => return write!(f, "'{}'", name),
+            Token::Operator(Operator::Or) => "||",
+            Token::Operator(Operator::And) => "&&",
+            Token::Operator(Operator::Equal) => "==",
+            Token::Operator(Operator::NotEqual) => "!=",
+            Token::Operator(Operator::Less) => "<",
+            Token::Operator(Operator::LessOrEqual) => "<=",
+            Token::Operator(Operator::Greater) => ">",
+            Token::Operator(Operator::GreaterOrEqual) => ">=",
+            Token::Not => "!",
+            Token::Open => "(",
+            Token::Close => ")",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+        };
+        write!(f, "'{}'", symbol)
+    }
+}
+
+/// A parsed condition, displayed as the text it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition{
+
+    text: String,
+    expr: Expr,
+}
+
+impl Condition{
+
+    pub fn parse(text: &str) -> Result<Condition, String>{
+
+        let tokens = tokenize(text)?;
+        let mut parser = Parser{ tokens, position: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek(){
+
+            return Err(format!("unexpected {} in condition", token));
+        }
+        Ok(Condition{ text: text.trim().to_string(), expr })
+    }
+
+    pub fn holds(&self, cpu: &Cpu) -> bool{
+
+        evaluate(&self.expr, cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        write!(f, "{}", self.text)
+    }
+}
+
+fn evaluate(expr: &Expr, cpu: &Cpu) -> u32{
+
+    match expr{
+
+        Expr::Number(value) => *value,
+        Expr::Register(register) => match register{
+
+            Register::V(index) => cpu.get_register(*index) as u32,
+            Register::I => cpu.get_i() as u32,
+            Register::Pc => cpu.get_pc() as u32,
+            Register::Sp => cpu.get_sp() as u32,
+            Register::Dt => cpu.get_delay_timer() as u32,
+            Register::St => cpu.get_sound_timer() as u32,
+        },
+        Expr::Memory(addr) => cpu.read_byte_from_mem(evaluate(addr, cpu) as usize).unwrap_or(0) as u32, // Out of range reads as 0
+        Expr::Not(operand) => (evaluate(operand, cpu) == 0) as u32,
+        Expr::Binary(operator, left, right) => {
+
+            let left = evaluate(left, cpu);
+            // && and || short-circuit so [addr] on the right is only read when needed
+            match operator{
+
+                Operator::Or => (left != 0 || evaluate(right, cpu) != 0) as u32,
+                Operator::And => (left != 0 && evaluate(right, cpu) != 0) as u32,
+                _ => {
+
+                    let right = evaluate(right, cpu);
+                    let result = match operator{
+
+                        Operator::Equal => left == right,
+                        Operator::NotEqual => left != right,
+                        Operator::Less => left < right,
+                        Operator::LessOrEqual => left <= right,
+                        Operator::Greater => left > right,
+                        Operator::GreaterOrEqual => left >= right,
+                        Operator::Or | Operator::And => unreachable!(),
+                    };
+                    result as u32
+                }
+            }
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String>{
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len(){
+
+        let c = chars[index];
+        if c.is_whitespace(){
+
+            index += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric(){
+
+            let start = index;
+            while index < chars.len() && chars[index].is_ascii_alphanumeric(){
+
+                index += 1;
+            }
+            let word: String = chars[start..index].iter().collect();
+            if c.is_ascii_digit(){
+
+                let value = parse_number(&word)?;
+                tokens.push(Token::Number(u32::try_from(value).map_err(|_| format!("'{}' is too large", word))?));
+            }else{
+
+                tokens.push(Token::Name(word));
+            }
+            continue;
+        }
+
+        let next = chars.get(index + 1).copied();
+        let (token, len) = match (c, next){
+
+            ('=', Some('=')) => (Token::Operator(Operator::Equal), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::LessOrEqual), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::GreaterOrEqual), 2),
+            ('&', Some('&')) => (Token::Operator(Operator::And), 2),
+            ('|', Some('|')) => (Token::Operator(Operator::Or), 2),
+            ('<', _) => (Token::Operator(Operator::Less), 1),
+            ('>', _) => (Token::Operator(Operator::Greater), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('[', _) => (Token::OpenBracket, 1),
+            (']', _) => (Token::CloseBracket, 1),
+            _ => return Err(format!("unexpected '{}' in condition", c)),
+        };
+        tokens.push(token);
+        index += len;
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, loosest binding first: || then && then comparisons then ! and operands
+struct Parser{
+
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser{
+
+    fn peek(&self) -> Option<&Token>{
+
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token>{
+
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String>{
+
+        match self.next(){
+
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {} but found {}", expected, token)),
+            None => Err(format!("expected {} at the end of the condition", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String>{
+
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Operator(Operator::Or)){
+
+            self.position += 1;
+            left = Expr::Binary(Operator::Or, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String>{
+
+        let mut left = self.comparison()?;
+        while self.peek() == Some(&Token::Operator(Operator::And)){
+
+            self.position += 1;
+            left = Expr::Binary(Operator::And, Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String>{
+
+        let mut left = self.unary()?;
+        while let Some(&Token::Operator(operator)) = self.peek(){
+
+            if matches!(operator, Operator::Or | Operator::And){
+
+                break;
+            }
+            self.position += 1;
+            left = Expr::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String>{
+
+        match self.next(){
+
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Name(name)) => Ok(Expr::Register(parse_register(&name)?)),
+            Some(Token::Open) => {
+
+                let inner = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(inner)
+            },
+            Some(Token::OpenBracket) => {
+
+                let addr = self.or()?;
+                self.expect(Token::CloseBracket)?;
+                Ok(Expr::Memory(Box::new(addr)))
+            },
+            Some(token) => Err(format!("unexpected {} in condition", token)),
+            None => Err("condition ends too early".to_string()),
+        }
+    }
+}
+
+fn parse_register(name: &str) -> Result<Register, String>{
+
+    match name.to_ascii_uppercase().as_str(){
+
+        "I" => Ok(Register::I),
+        "PC" => Ok(Register::Pc),
+        "SP" => Ok(Register::Sp),
+        "DT" => Ok(Register::Dt),
+        "ST" => Ok(Register::St),
+        upper => opcodes::parse_register(upper).map(Register::V).ok_or(format!("unknown register '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    fn cpu() -> Cpu{
+
+        let mut cpu = Cpu::new();
+        cpu.set_register(1, 2);
+        cpu.set_register(0xA, 0x10);
+        cpu.set_i(0x300);
+        cpu.set_pc(0x234);
+        cpu.write_byte_to_mem(7, 0x300).unwrap();
+        cpu.write_byte_to_mem(0x50, 0x301).unwrap(); // The font's first byte, 0xF0
+        cpu
+    }
+
+    fn holds(text: &str) -> bool{
+
+        Condition::parse(text).unwrap_or_else(|e| panic!("{}: {}", text, e)).holds(&cpu())
+    }
+
+    fn error(text: &str) -> String{
+
+        Condition::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn registers(){
+
+        assert!(holds("V1 == 2"));
+        assert!(holds("va == 0x10 && VA == 16"));
+        assert!(holds("I == 0x300 && PC == 0x234"));
+        assert!(holds("SP == 0 && DT == 0 && ST == 0"));
+        assert!(holds("V1"));
+        assert!(!holds("V2"));
+    }
+
+    #[test]
+    fn comparisons(){
+
+        assert!(holds("V1 != 3"));
+        assert!(holds("V1 < 3") && !holds("V1 < 2"));
+        assert!(holds("V1 <= 2") && !holds("V1 <= 1"));
+        assert!(holds("V1 > 1") && !holds("V1 > 2"));
+        assert!(holds("V1 >= 2") && !holds("V1 >= 3"));
+        // Comparisons chain left to right: (V1 < 3) is 1
+        assert!(holds("V1 < 3 == 1"));
+    }
+
+    #[test]
+    fn memory(){
+
+        assert!(holds("[0x300] == 7"));
+        assert!(holds("[I] == 7"));
+        assert!(holds("[[0x301]] == 0xF0"));
+        assert!(holds("[V1 < 3] == [1]"));
+        assert!(holds("[0x10000] == 0")); // Out of range reads as 0
+    }
+
+    #[test]
+    fn logic_and_precedence(){
+
+        // && binds tighter than ||
+        assert!(holds("1 || 0 && 0"));
+        assert!(!holds("(1 || 0) && 0"));
+        assert!(holds("!V2"));
+        assert!(!holds("!(V1 == 2)"));
+        assert!(holds("!!V1"));
+        assert!(holds("V1 == 3 || V1 == 2 && [I] == 7"));
+    }
+
+    #[test]
+    fn keeps_the_text(){
+
+        assert_eq!(Condition::parse("  V1 == 2 ").unwrap().to_string(), "V1 == 2");
+    }
+
+    #[test]
+    fn malformed_conditions(){
+
+        assert_eq!(error(""), "condition ends too early");
+        assert_eq!(error("V1 =="), "condition ends too early");
+        assert_eq!(error("(V1 == 2"), "expected ')' at the end of the condition");
+        assert_eq!(error("[0x300 == 7"), "expected ']' at the end of the condition");
+        assert_eq!(error("(V1 ]"), "expected ')' but found ']'");
+        assert_eq!(error("VG == 1"), "unknown register 'VG'");
+        assert_eq!(error("V1 = 2"), "unexpected '=' in condition");
+        assert_eq!(error("V1 2"), "unexpected '2' in condition");
+        assert_eq!(error("0x1FFFFFFFF"), "'0x1FFFFFFFF' is too large");
+        assert_eq!(error("12ab"), "'12ab' is not a number");
+        assert_eq!(error("&& V1"), "unexpected '&&' in condition");
+    }
+}
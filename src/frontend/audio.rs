@@ -0,0 +1,102 @@
+use std::time::Duration;
+use rodio::{source::SineWave, ChannelCount, OutputStream, OutputStreamBuilder, SampleRate, Sink, Source, StreamError};
+use rustchip::audio::{AudioSink, PatternSynth, BEEP_FREQUENCY};
+
+const PATTERN_SAMPLE_RATE: SampleRate = 44100;
+
+/// Plays the machine's beeper through the default output device.
+pub struct RodioAudio{
+
+    // The stream has to stay alive for as long as the sink plays through it
+    _stream: OutputStream,
+    sink: Sink,
+    beeping: bool,
+}
+
+impl RodioAudio{
+
+    pub fn open() -> Result<RodioAudio, StreamError>{
+
+        let stream = OutputStreamBuilder::open_default_stream()?;
+        let sink = Sink::connect_new(stream.mixer());
+        let mut audio = RodioAudio{ _stream: stream, sink, beeping: false };
+        audio.set_tone(BEEP_FREQUENCY);
+
+        Ok(audio)
+    }
+}
+
+impl AudioSink for RodioAudio{
+
+    fn set_beep(&mut self, on: bool, _frame: u64){
+
+        self.beeping = on;
+        if on{
+
+            self.sink.play();
+        }else{
+
+            self.sink.pause();
+        }
+    }
+
+    fn set_tone(&mut self, frequency: f32){
+
+        self.replace_source(SineWave::new(frequency).repeat_infinite());
+    }
+
+    fn set_pattern(&mut self, pattern: [u8; 16], pitch: u8){
+
+        self.replace_source(PatternSource(PatternSynth::new(pattern, pitch, PATTERN_SAMPLE_RATE)));
+    }
+}
+
+impl RodioAudio{
+
+    fn replace_source(&mut self, source: impl Source + Send + 'static){
+
+        // clear() also pauses the sink, so restore the beep state afterwards
+        self.sink.clear();
+        self.sink.append(source);
+        if self.beeping{
+
+            self.sink.play();
+        }
+    }
+}
+
+// Mono rodio source over the core's XO-CHIP pattern synthesizer
+struct PatternSource(PatternSynth);
+
+impl Iterator for PatternSource{
+
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32>{
+
+        self.0.next()
+    }
+}
+
+impl Source for PatternSource{
+
+    fn current_span_len(&self) -> Option<usize>{
+
+        None
+    }
+
+    fn channels(&self) -> ChannelCount{
+
+        1
+    }
+
+    fn sample_rate(&self) -> SampleRate{
+
+        PATTERN_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration>{
+
+        None
+    }
+}
@@ -0,0 +1,344 @@
+use std::path::Path;
+
+use rustchip::{chip8::DEFAULT_CYCLES_PER_FRAME, quirks::{Quirks, PRESETS, QUIRK_NAMES}, disasm::{Platform, Syntax}, cartridge::{self, CartridgeOptions}};
+
+use crate::frontend::keymap::{DEFAULT_KEYMAP, LAYOUTS};
+
+pub const DEFAULT_ROM: &str = "PONG.ch8";
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+
+pub struct Options{
+
+    pub rom: String,
+    pub quirks: QuirkOptions, // Applied over the default or a cartridge's quirks
+    pub debug: bool,
+    pub trace: Option<String>,
+    pub headless: bool,
+    pub frames: Option<u64>, // Headless, defaults to the movie's length or DEFAULT_HEADLESS_FRAMES
+    pub dump: Option<String>,
+    pub seed: Option<u64>,
+    pub cycles_per_frame: Option<u32>, // Overrides the default and a cartridge's tickrate
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub keymap: Option<String>,
+}
+
+pub enum Command{
+
+    Run(Options),
+    Disasm(DisasmOptions),
+    Asm(AsmOptions),
+    Cart(CartOptions),
+}
+
+pub struct DisasmOptions{
+
+    pub rom: String,
+    pub syntax: Syntax,
+    pub platform: Platform,
+    pub linear: bool,
+}
+
+pub struct AsmOptions{
+
+    pub source: String,
+    pub output: String,
+    pub target: Platform,
+}
+
+pub struct CartOptions{
+
+    pub input: String,
+    pub output: String,
+    pub options: CartridgeOptions,
+}
+
+pub fn usage() -> String{
+
+    format!(
+        "Usage: rustchip [run] [options] [rom]\n\
+         \x20      rustchip disasm [disasm options] <rom>\n\
+         \x20      rustchip asm [asm options] <source>\n\
+         \x20      rustchip cart [cart options] <rom or .8o source>\n\
+         \n\
+         Options:\n\
+         \x20 --quirks <preset>        Quirk preset: {}\n\
+         \x20 --quirk <name>=<value>   Override one quirk (on/off; load-store takes unchanged/x/x+1)\n\
+         \x20                          Quirks: {}\n\
+         \x20 --debug                  Start paused with the command-line debugger on stdin\n\
+         \x20 --trace <file>           Log every executed instruction and the registers before it to <file>\n\
+         \x20 --headless               Run without a window or sound, in emulated time, then print the screen\n\
+         \x20 --frames <n>             Frames to run headless (default: the movie's length, or {})\n\
+         \x20 --dump <file>            Save the final screen as .png, .pbm or .txt instead of printing it\n\
+         \x20 --cycles-per-frame <n>   Instructions per 60 Hz frame (default: {}, or the cartridge's tickrate)\n\
+         \x20 --seed <n>               Seed for CXNN's random numbers (default: random, or 0 headless)\n\
+         \x20 --record <file>          Record the keypad to a movie file, saved when the window closes\n\
+         \x20 --play <file>            Play a movie back, with the seed, speed and quirks it was recorded with\n\
+         \x20 --keymap <file>          TOML keypad bindings (default: {} if it exists, else the qwerty layout)\n\
+         \x20                          Layouts: {}\n\
+         \x20 -h, --help               Show this message\n\
+         \n\
+         Disasm options:\n\
+         \x20 --syntax <name>          Mnemonic syntax: cowgod (default) or octo\n\
+         \x20 --platform <name>        Opcodes to decode: chip8 (default), schip or xochip\n\
+         \x20 --linear                 Decode every byte as code instead of following jumps and calls\n\
+         \n\
+         Asm options:\n\
+         \x20 -o <rom>                 Where to write the ROM (default: the source with a .ch8 extension)\n\
+         \x20 --target <name>          Platform for .8o (Octo) sources: chip8, schip or xochip (default)\n\
+         \n\
+         Cart options (write an Octo cartridge GIF):\n\
+         \x20 -o <gif>                 Where to write the cartridge (default: the input with a .gif extension)\n\
+         \x20 --quirks, --quirk        As above, the quirks the cartridge asks for (default: xochip)\n\
+         \x20 --tickrate <n>           Instructions per frame (default: 20)\n\
+         \x20 --colors <list>          Background, plane 1, plane 2 and blend colours as #RRGGBB,...",
+        PRESETS.join(", "),
+        QUIRK_NAMES.join(", "),
+        DEFAULT_HEADLESS_FRAMES,
+        DEFAULT_CYCLES_PER_FRAME,
+        DEFAULT_KEYMAP,
+        LAYOUTS.join(", "),
+    )
+}
+
+/// `--quirks` and `--quirk`, which `run` and `cart` take alike.
+#[derive(Default)]
+pub struct QuirkOptions{
+
+    preset: Option<Quirks>,
+    overrides: Vec<(String, String)>,
+}
+
+impl QuirkOptions{
+
+    // Takes the option's value from `args`
+    fn parse(&mut self, option: &str, args: &mut impl Iterator<Item = String>) -> Result<(), String>{
+
+        if option == "--quirks"{
+
+            let name = args.next().ok_or("--quirks needs a preset name")?;
+            self.preset = Some(Quirks::preset(&name).ok_or(format!("unknown quirk preset '{}'", name))?);
+        }else{
+
+            let setting = args.next().ok_or("--quirk needs <name>=<value>")?;
+            let (name, value) = setting.split_once('=').ok_or(format!("expected <name>=<value>, got '{}'", setting))?;
+            self.overrides.push((name.to_string(), value.to_string()));
+        }
+        Ok(())
+    }
+
+    /// The preset if one was given, else `default`, with the overrides on top. They always
+    /// win over the preset, whatever order they were given in.
+    pub fn apply(&self, default: Quirks) -> Result<Quirks, String>{
+
+        let mut quirks = self.preset.unwrap_or(default);
+        for (name, value) in &self.overrides{
+
+            quirks.set(name, value).map_err(|e| e.to_string())?;
+        }
+        Ok(quirks)
+    }
+}
+
+impl Command{
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command, String>{
+
+        let mut args = args.by_ref().peekable();
+        match args.peek().map(String::as_str){
+
+            Some("disasm") => {
+
+                args.next();
+                DisasmOptions::parse(args).map(Command::Disasm)
+            },
+            Some("asm") => {
+
+                args.next();
+                AsmOptions::parse(args).map(Command::Asm)
+            },
+            Some("run") => {
+
+                args.next();
+                Options::parse(args).map(Command::Run)
+            },
+            Some("cart") => {
+
+                args.next();
+                CartOptions::parse(args).map(Command::Cart)
+            },
+            _ => Options::parse(args).map(Command::Run),
+        }
+    }
+}
+
+impl DisasmOptions{
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<DisasmOptions, String>{
+
+        let mut rom = None;
+        let mut syntax = Syntax::default();
+        let mut platform = Platform::default();
+        let mut linear = false;
+
+        while let Some(arg) = args.next(){
+
+            match arg.as_str(){
+
+                "--syntax" => {
+
+                    let name = args.next().ok_or("--syntax needs cowgod or octo")?;
+                    syntax = Syntax::from_name(&name).ok_or(format!("unknown syntax '{}'", name))?;
+                },
+                "--platform" => {
+
+                    let name = args.next().ok_or("--platform needs chip8, schip or xochip")?;
+                    platform = Platform::from_name(&name).ok_or(format!("unknown platform '{}'", name))?;
+                },
+                "--linear" => linear = true,
+                "-h" | "--help" => return Err(usage()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n\n{}", arg, usage())),
+                _ => rom = Some(arg),
+            }
+        }
+
+        Ok(DisasmOptions{ rom: rom.ok_or(format!("disasm needs a ROM\n\n{}", usage()))?, syntax, platform, linear })
+    }
+}
+
+impl AsmOptions{
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<AsmOptions, String>{
+
+        let mut source = None;
+        let mut output = None;
+        let mut target = Platform::XoChip;
+
+        while let Some(arg) = args.next(){
+
+            match arg.as_str(){
+
+                "-o" | "--output" => output = Some(args.next().ok_or("-o needs a file name")?),
+                "--target" => {
+
+                    let name = args.next().ok_or("--target needs chip8, schip or xochip")?;
+                    target = Platform::from_name(&name).ok_or(format!("unknown target '{}'", name))?;
+                },
+                "-h" | "--help" => return Err(usage()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n\n{}", arg, usage())),
+                _ => source = Some(arg),
+            }
+        }
+
+        let source = source.ok_or(format!("asm needs a source file\n\n{}", usage()))?;
+        let output = output.unwrap_or_else(|| Path::new(&source).with_extension("ch8").display().to_string());
+        Ok(AsmOptions{ source, output, target })
+    }
+}
+
+impl CartOptions{
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<CartOptions, String>{
+
+        let mut input = None;
+        let mut output = None;
+        let mut options = CartridgeOptions::default();
+        let mut quirks = QuirkOptions::default();
+
+        while let Some(arg) = args.next(){
+
+            match arg.as_str(){
+
+                "-o" | "--output" => output = Some(args.next().ok_or("-o needs a file name")?),
+                "--quirks" | "--quirk" => quirks.parse(&arg, &mut args)?,
+                "--tickrate" => {
+
+                    let value = args.next().ok_or("--tickrate needs a number")?;
+                    options.tickrate = value.parse().ok().filter(|&n| n > 0).ok_or(format!("invalid tickrate '{}'", value))?;
+                },
+                "--colors" | "--colours" => {
+
+                    let list = args.next().ok_or("--colors needs four #RRGGBB colours")?;
+                    let colours: Option<Vec<[u8; 3]>> = list.split(',').map(cartridge::parse_colour).collect();
+                    options.colours = colours.and_then(|colours| colours.try_into().ok()).ok_or(format!("expected four #RRGGBB colours, got '{}'", list))?;
+                },
+                "-h" | "--help" => return Err(usage()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n\n{}", arg, usage())),
+                _ => input = Some(arg),
+            }
+        }
+
+        options.quirks = quirks.apply(options.quirks)?;
+
+        let input = input.ok_or(format!("cart needs a ROM or .8o source\n\n{}", usage()))?;
+        let output = output.unwrap_or_else(|| Path::new(&input).with_extension("gif").display().to_string());
+        Ok(CartOptions{ input, output, options })
+    }
+}
+
+impl Options{
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String>{
+
+        let mut rom = None;
+        let mut quirks = QuirkOptions::default();
+        let mut debug = false;
+        let mut trace = None;
+        let mut headless = false;
+        let mut frames = None;
+        let mut dump = None;
+        let mut seed = None;
+        let mut cycles_per_frame = None;
+        let mut record = None;
+        let mut play = None;
+        let mut keymap = None;
+
+        while let Some(arg) = args.next(){
+
+            match arg.as_str(){
+
+                "--quirks" | "--quirk" => quirks.parse(&arg, &mut args)?,
+                "--debug" => debug = true,
+                "--trace" => trace = Some(args.next().ok_or("--trace needs a file name")?),
+                "--headless" => headless = true,
+                "--frames" => {
+
+                    let value = args.next().ok_or("--frames needs a number")?;
+                    frames = Some(value.parse().map_err(|_| format!("invalid frame count '{}'", value))?);
+                },
+                "--dump" => dump = Some(args.next().ok_or("--dump needs a file name")?),
+                "--record" => record = Some(args.next().ok_or("--record needs a file name")?),
+                "--play" => play = Some(args.next().ok_or("--play needs a file name")?),
+                "--keymap" => keymap = Some(args.next().ok_or("--keymap needs a file name")?),
+                "--cycles-per-frame" => {
+
+                    let value = args.next().ok_or("--cycles-per-frame needs a number")?;
+                    let cycles = value.parse().ok().filter(|&cycles| (1..=100_000).contains(&cycles));
+                    cycles_per_frame = Some(cycles.ok_or(format!("invalid cycles per frame '{}' (1 to 100000)", value))?);
+                },
+                "--seed" => {
+
+                    let value = args.next().ok_or("--seed needs a number")?;
+                    seed = Some(value.parse().map_err(|_| format!("invalid seed '{}'", value))?);
+                },
+                "-h" | "--help" => return Err(usage()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n\n{}", arg, usage())),
+                _ => rom = Some(arg),
+            }
+        }
+
+        // Checks the overrides now, they are applied once the ROM is loaded
+        quirks.apply(Quirks::default())?;
+
+        if dump.is_some() && !headless{
+
+            return Err("--dump only works with --headless".to_string());
+        }
+        if record.is_some() && (headless || play.is_some()){
+
+            return Err("--record needs a window and can't be combined with --play".to_string());
+        }
+
+        Ok(Options{ rom: rom.unwrap_or(DEFAULT_ROM.to_string()), quirks, debug, trace, headless, frames, dump, seed, cycles_per_frame, record, play, keymap })
+    }
+}
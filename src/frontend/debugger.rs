@@ -0,0 +1,45 @@
+use std::{io::{self, BufRead, Write}, sync::mpsc::Sender, thread};
+
+use rustchip::debugger::PROMPT;
+
+use crate::frontend::emulator::Command;
+
+// Reads debugger commands from stdin on its own thread and hands them to the emulator
+// thread, which owns the debugger and prints the replies
+pub fn spawn_repl(commands: Sender<Command>){
+
+    println!("Debugger ready, the ROM is paused. Type 'help' for commands.");
+    show_prompt();
+
+    thread::spawn(move || {
+
+        for line in io::stdin().lock().lines(){
+
+            let Ok(line) = line else { break };
+            if commands.send(Command::Debug(line)).is_err(){
+
+                break;
+            }
+        }
+    });
+}
+
+pub fn show_prompt(){
+
+    print!("{}", PROMPT);
+    let _ = io::stdout().flush();
+}
+
+// The prompt is only shown once the ROM is stopped again, so it doesn't get mixed into
+// the output of a running ROM
+pub fn print_reply(reply: &str, paused: bool){
+
+    if !reply.is_empty(){
+
+        println!("{}", reply);
+    }
+    if paused{
+
+        show_prompt();
+    }
+}
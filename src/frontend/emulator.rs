@@ -0,0 +1,319 @@
+use std::{fs, path::PathBuf, sync::{Arc, OnceLock, mpsc::{Receiver, RecvTimeoutError}}, thread::{self, JoinHandle}, time::Instant};
+
+use rustchip::{Chip8, StepOutcome, debugger::Debugger, display::{Display, HIRES_HEIGHT, HIRES_WIDTH}, movie::Movie, rewind::{RewindBuffer, DEFAULT_REWIND_FRAMES}};
+use winit::window::Window;
+
+use crate::frontend::{debugger as repl, input::Input, render::show_status, timing::{FRAME, FramePacer, Speed}};
+
+/// What the window and the debugger prompt can ask of the emulator thread.
+pub enum Command{
+
+    Key{ key: usize, pressed: bool },
+    Rewind(bool),      // Held down or released
+    FastForward(bool), // Held down or released
+    TogglePause,
+    ChangeSpeed{ faster: bool },
+    SaveState{ path: PathBuf, slot: u8 },
+    LoadState{ path: PathBuf, slot: u8 },
+    Debug(String), // A line typed at the debugger prompt
+    Quit,
+}
+
+/// A finished screen, handed to the renderer through a triple buffer.
+#[derive(Clone)]
+pub struct Frame{
+
+    pub pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT], // Lo-res frames only use the top left quarter
+    pub width: usize,
+}
+
+impl Frame{
+
+    fn capture(display: &Display) -> Frame{
+
+        let mut pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        pixels[..display.height()].copy_from_slice(display.get_buffer());
+        Frame{ pixels, width: display.width() }
+    }
+}
+
+// How a frame run by the emulator ended
+enum FrameEnd{
+
+    Complete,
+    Stopped,  // The debugger took over part way through
+    Finished, // The ROM exited or faulted with nothing left to do
+}
+
+/// The emulator thread. It owns the machine outright; everything else talks to it
+/// through `Command`s and reads the screen from the published frames.
+pub struct Emulator{
+
+    machine: Chip8,
+    input: Input,
+    history: RewindBuffer,
+    debugger: Option<Debugger>,
+    speed: Speed,
+    rewinding: bool,
+    finished: bool, // The ROM is done, only commands are handled until Quit
+    commands: Receiver<Command>,
+    frames: triple_buffer::Input<Frame>,
+    window: Arc<OnceLock<Arc<Window>>>,
+}
+
+impl Emulator{
+
+    pub fn new(machine: Chip8, input: Input, debugger: Option<Debugger>, commands: Receiver<Command>, window: Arc<OnceLock<Arc<Window>>>) -> (Emulator, triple_buffer::Output<Frame>){
+
+        let (frames, output) = triple_buffer::triple_buffer(&Frame::capture(machine.display()));
+        let history = RewindBuffer::new(DEFAULT_REWIND_FRAMES);
+        let emulator = Emulator{ machine, input, history, debugger, speed: Speed::new(), rewinding: false, finished: false, commands, frames, window };
+        (emulator, output)
+    }
+
+    /// Runs until a `Quit` command, or until every sender is gone, and returns the movie
+    /// recorded on the way if there is one.
+    pub fn spawn(self) -> JoinHandle<Option<Movie>>{
+
+        thread::spawn(move || self.run())
+    }
+
+    fn run(mut self) -> Option<Movie>{
+
+        // Frame 0's keys are applied here, the later frames get theirs as they start
+        self.input.apply(&mut self.machine);
+
+        let mut pacer = FramePacer::new(Instant::now());
+        let mut last_redraw = Instant::now();
+        loop {
+            let paused = self.is_paused();
+            let limit = self.speed.limit();
+
+            // --- Wait for the next frame, or a command ---
+            let command = match limit{
+
+                _ if paused => self.commands.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(_) => self.commands.recv_timeout(pacer.until_next(Instant::now())),
+                None => self.commands.try_recv().map_err(|_| RecvTimeoutError::Timeout),
+            };
+            match command{
+
+                Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(command) => self.execute(command),
+                Err(RecvTimeoutError::Timeout) => {},
+            }
+
+            // --- Frames due, whole frames at a time ---
+            let now = Instant::now();
+            let limit = self.speed.limit();
+            let frames = match limit{
+
+                _ if self.is_paused() => {
+
+                    pacer.reset(now);
+                    0
+                },
+                Some(speed) => pacer.due(now, speed),
+                None => {
+
+                    // Fast-forward: one frame per pass, as fast as the host goes
+                    pacer.reset(now);
+                    1
+                },
+            };
+            for _ in 0..frames{
+
+                if self.rewinding{
+
+                    // Backing out of a fault or an exit lets the ROM run again, as loading a state does
+                    if self.history.step_back(&mut self.machine){
+
+                        self.finished = false;
+                    }
+                    self.input.rewound(&self.machine);
+                    continue;
+                }
+                match self.run_frame(){
+
+                    FrameEnd::Complete => {
+
+                        self.input.apply(&mut self.machine);
+                        self.history.record(&self.machine);
+                    },
+                    FrameEnd::Stopped => break,
+                    FrameEnd::Finished => {
+
+                        self.finished = true;
+                        break;
+                    },
+                }
+            }
+
+            // Fast-forward still only redraws at the display's rate
+            if limit.is_some() || now - last_redraw >= FRAME{
+
+                self.publish();
+                last_redraw = now;
+            }
+        }
+
+        self.machine.cpu_mut().flush_trace();
+        self.input.finish_recording(self.machine.frame())
+    }
+
+    // A finished machine can still be rewound
+    fn is_paused(&self) -> bool{
+
+        (self.finished && !self.rewinding) || self.speed.paused || self.debugger.as_ref().is_some_and(|debugger| debugger.is_paused())
+    }
+
+    fn execute(&mut self, command: Command){
+
+        match command{
+
+            Command::Key{ key, pressed } => if pressed { self.input.press(key) } else { self.input.release(key) },
+            Command::Rewind(on) => self.rewinding = on,
+            Command::FastForward(on) => self.speed.fast_forward = on,
+            Command::TogglePause => {
+
+                self.speed.paused = !self.speed.paused;
+                self.show_status(if self.speed.paused { "Paused" } else { "Resumed" });
+            },
+            Command::ChangeSpeed{ faster } => {
+
+                let speed = self.speed.change(faster);
+                self.show_status(&format!("Speed {}x", speed));
+            },
+            Command::SaveState{ path, slot } => match fs::write(&path, self.machine.save_state()){
+
+                Ok(()) => self.show_status(&format!("Saved state to slot {}", slot)),
+                Err(e) => self.show_status(&format!("Could not save {}: {}", path.display(), e)),
+            },
+            Command::LoadState{ path, slot } => {
+
+                // A movie can only follow its own timeline
+                if self.input.is_locked(self.machine.frame()){
+
+                    self.show_status("Can't load a state while a movie is recording or playing");
+                    return;
+                }
+                let result = fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|state| self.machine.load_state(&state).map_err(|e| e.to_string()));
+                match result{
+
+                    Ok(()) => {
+
+                        self.finished = false;
+                        self.show_status(&format!("Loaded state from slot {}", slot));
+                    },
+                    Err(e) => self.show_status(&format!("Could not load {}: {}", path.display(), e)),
+                }
+            },
+            Command::Debug(line) => if let Some(debugger) = self.debugger.as_mut(){
+
+                let reply = debugger.execute(&line, &mut self.machine);
+                repl::print_reply(&reply, debugger.is_paused());
+            },
+            Command::Quit => {},
+        }
+    }
+
+    // Steps until the timers tick, checking breakpoints before each instruction when the
+    // debugger is attached
+    fn run_frame(&mut self) -> FrameEnd{
+
+        let machine = &mut self.machine;
+        let frame = machine.frame();
+        while machine.frame() == frame{
+
+            if let Some(debugger) = self.debugger.as_mut(){
+
+                let (execute, message) = debugger.should_execute(machine);
+                if let Some(message) = message{
+
+                    repl::print_reply(&message, debugger.is_paused());
+                }
+                if !execute{
+
+                    return FrameEnd::Stopped;
+                }
+            }
+
+            let result = machine.step();
+            if let Some(debugger) = self.debugger.as_mut(){
+
+                if result.is_err(){
+
+                    debugger.pause();
+                }
+                if let Some(message) = debugger.after_step(machine){
+
+                    repl::print_reply(&message, debugger.is_paused());
+                }
+            }
+            match result{
+
+                Ok(StepOutcome::InvalidOpcode(opcode)) => {
+
+                    eprintln!("Warning: Unknown opcode {:04X}, ignoring.", opcode);
+                },
+                Ok(StepOutcome::Exited) => {
+
+                    println!("ROM exited.");
+                    machine.cpu_mut().flush_trace();
+                    return match self.debugger.as_mut(){
+
+                        Some(debugger) => {
+
+                            debugger.pause();
+                            repl::show_prompt();
+                            FrameEnd::Stopped
+                        },
+                        None => FrameEnd::Finished,
+                    };
+                },
+                Ok(_) => {},
+                Err(e) => {
+
+                    // Stop the machine but keep the last frame on screen
+                    eprintln!("{}", e);
+                    machine.cpu_mut().flush_trace();
+                    if let Some(window) = self.window.get(){
+
+                        window.set_title(&format!("RustChip - {}", e));
+                    }
+                    // With the debugger attached the faulting state can still be inspected
+                    if self.debugger.is_none(){
+
+                        return FrameEnd::Finished;
+                    }
+                    repl::show_prompt();
+                    return FrameEnd::Stopped;
+                }
+            }
+        }
+        FrameEnd::Complete
+    }
+
+    // Hands the screen to the renderer if it changed since the last time
+    fn publish(&mut self){
+
+        let display = self.machine.display_mut();
+        if !display.needs_update(){
+
+            return;
+        }
+        display.set_needs_update(false);
+        self.frames.write(Frame::capture(display));
+        if let Some(window) = self.window.get(){
+
+            window.request_redraw();
+        }
+    }
+
+    fn show_status(&self, message: &str){
+
+        show_status(&self.window, message);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fs, io, path::Path};
+
+use rustchip::{Chip8, StepOutcome, movie::Playback, screenshot};
+
+use crate::frontend::{cli::{DEFAULT_HEADLESS_FRAMES, Options}, tools};
+
+enum DumpFormat{
+
+    Png,
+    Pbm,
+    Ascii,
+}
+
+impl DumpFormat{
+
+    fn from_path(path: &str) -> Result<DumpFormat, String>{
+
+        let extension = Path::new(path).extension().unwrap_or_default().to_ascii_lowercase();
+        match extension.to_str(){
+
+            Some("png") => Ok(DumpFormat::Png),
+            Some("pbm") => Ok(DumpFormat::Pbm),
+            Some("txt") => Ok(DumpFormat::Ascii),
+            _ => Err(format!("can't tell the dump format of {}, use .png, .pbm or .txt", path)),
+        }
+    }
+}
+
+// Runs a ROM without a window or audio device. Time is emulated and the seed is fixed,
+// so every machine gets the same screen after the same number of frames.
+pub fn run(options: &Options) -> Result<(), String>{
+
+    let dump_format = options.dump.as_deref().map(DumpFormat::from_path).transpose()?;
+    let mut machine = Chip8::new();
+    let palette = tools::load_rom(&mut machine, options)?;
+    machine.cpu_mut().set_seed(options.seed.unwrap_or(0));
+
+    // A movie brings the seed, speed and quirks it was recorded with
+    let mut playback = match &options.play{
+
+        Some(path) => {
+
+            let movie = tools::load_movie(Path::new(path)).map_err(|e| format!("could not load {}: {}", path, e))?;
+            movie.start(&mut machine).map_err(|e| format!("could not play {}: {}", path, e))?;
+            Some(Playback::new(movie))
+        },
+        None => None,
+    };
+    let frames = options.frames.or(playback.as_ref().map(|playback| playback.movie().frames)).unwrap_or(DEFAULT_HEADLESS_FRAMES);
+
+    if let Some(trace_path) = &options.trace{
+
+        let file = fs::File::create(trace_path).map_err(|e| format!("could not create {}: {}", trace_path, e))?;
+        machine.cpu_mut().set_trace(Some(Box::new(io::BufWriter::new(file))));
+    }
+
+    let mut result = Ok(());
+    for frame in 0..frames{
+
+        if let Some(playback) = playback.as_mut(){
+
+            playback.apply(&mut machine);
+        }
+        match machine.run_frame(){
+
+            Ok(StepOutcome::Exited) => {
+
+                println!("ROM exited after {} frames.", frame + 1);
+                break;
+            },
+            Ok(_) => {},
+            Err(e) => {
+
+                // The screen at the fault is still worth dumping
+                result = Err(e.to_string());
+                break;
+            }
+        }
+    }
+    machine.cpu_mut().flush_trace();
+
+    let display = machine.display();
+    match (dump_format, &options.dump){
+
+        (Some(DumpFormat::Png), Some(path)) => {
+
+            let file = fs::File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+            screenshot::write_png(display, &palette, io::BufWriter::new(file)).map_err(|e| format!("could not write {}: {}", path, e))?;
+        },
+        (Some(DumpFormat::Pbm), Some(path)) => fs::write(path, screenshot::pbm(display)).map_err(|e| format!("could not write {}: {}", path, e))?,
+        (Some(DumpFormat::Ascii), Some(path)) => fs::write(path, screenshot::ascii(display)).map_err(|e| format!("could not write {}: {}", path, e))?,
+        _ => print!("{}", screenshot::ascii(display)),
+    }
+    result
+}
@@ -0,0 +1,112 @@
+use rustchip::{Chip8, movie::{Movie, Playback}};
+
+/// Keypad input from the window. Key events are queued and reach the machine at the
+/// start of the next frame, which is what lets a movie record and replay them exactly.
+#[derive(Default)]
+pub struct Input{
+
+    pending: Vec<(usize, bool)>,
+    held: [bool; 16], // Keys physically held down, whatever the machine thinks
+    recording: Option<Movie>,
+    playback: Option<Playback>,
+}
+
+impl Input{
+
+    pub fn new() -> Input{
+
+        Input::default()
+    }
+
+    pub fn record(&mut self, movie: Movie){
+
+        self.recording = Some(movie);
+    }
+
+    pub fn play(&mut self, playback: Playback){
+
+        self.playback = Some(playback);
+    }
+
+    pub fn press(&mut self, key: usize){
+
+        self.set_key(key, true);
+    }
+
+    pub fn release(&mut self, key: usize){
+
+        self.set_key(key, false);
+    }
+
+    // Key repeat sends more presses while a key is held, they don't belong in a movie
+    fn set_key(&mut self, key: usize, pressed: bool){
+
+        if self.held[key] != pressed{
+
+            self.held[key] = pressed;
+            self.pending.push((key, pressed));
+        }
+    }
+
+    // True while a movie is recording or still playing, when the timeline mustn't jump
+    pub fn is_locked(&self, frame: u64) -> bool{
+
+        self.recording.is_some() || self.playback.as_ref().is_some_and(|playback| !playback.is_finished(frame))
+    }
+
+    /// Hands the queued keys, or the movie's, to the machine. Called at the start of every frame.
+    pub fn apply(&mut self, machine: &mut Chip8){
+
+        let frame = machine.frame();
+        if let Some(playback) = self.playback.as_mut()
+            && !playback.is_finished(frame){
+
+            // The keyboard is ignored until the movie is over
+            playback.apply(machine);
+            self.pending.clear();
+            return;
+        }
+
+        for (key, pressed) in self.pending.drain(..){
+
+            let keypad = machine.keypad_mut();
+            if pressed { keypad.press(key) } else { keypad.release(key) };
+            if let Some(movie) = self.recording.as_mut(){
+
+                movie.record(frame, key as u8, pressed);
+            }
+        }
+    }
+
+    // After stepping back, the movie continues from the machine's frame and the keys
+    // held through the rewind are pressed again on the next frame
+    pub fn rewound(&mut self, machine: &Chip8){
+
+        let frame = machine.frame();
+        if let Some(movie) = self.recording.as_mut(){
+
+            movie.truncate(frame);
+        }
+        if let Some(playback) = self.playback.as_mut(){
+
+            playback.seek(frame);
+        }
+
+        self.pending.clear();
+        let keys = machine.keypad().get_keys();
+        for (key, (&pressed, &held)) in keys.iter().zip(self.held.iter()).enumerate(){
+
+            if pressed != held{
+
+                self.pending.push((key, held));
+            }
+        }
+    }
+
+    pub fn finish_recording(&mut self, frame: u64) -> Option<Movie>{
+
+        let mut movie = self.recording.take()?;
+        movie.frames = movie.frames.max(frame);
+        Some(movie)
+    }
+}
@@ -0,0 +1,554 @@
+//! Which host keys press which CHIP-8 keys: one of the built-in layouts, optionally
+//! changed by a TOML keymap file, which can also change it again for particular ROMs:
+//!
+//! ```toml
+//! layout = "qwerty"
+//!
+//! [keys]
+//! 5 = ["KeyW", "ArrowUp"]   # Replaces whatever pressed 5 before
+//! 8 = ["KeyS", "ArrowDown"]
+//!
+//! [roms."PONG.ch8"]
+//! keys = { 1 = ["KeyW", "ArrowUp"], 4 = ["KeyS", "ArrowDown"] }
+//! ```
+//!
+//! A host key is either a winit `KeyCode` name, which is a key's position whatever the
+//! keyboard layout, or a single character, which is the key that types it in the
+//! system's current layout.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use winit::keyboard::{Key, KeyCode, PhysicalKey};
+
+pub const LAYOUTS: [&str; 4] = ["qwerty", "azerty", "dvorak", "numpad"];
+pub const DEFAULT_KEYMAP: &str = "keymap.toml"; // Read from the working directory when there is one
+
+// The CHIP-8 keypad row by row, as the built-in layouts lay it out on the keyboard
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+const DIGITS: [KeyCode; 10] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+const NUMPAD: [KeyCode; 10] = [
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+];
+// The three rows under 1 2 3 4, by position
+const QWERTY: [KeyCode; 12] = [
+    KeyCode::KeyQ, KeyCode::KeyW, KeyCode::KeyE, KeyCode::KeyR,
+    KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD, KeyCode::KeyF,
+    KeyCode::KeyZ, KeyCode::KeyX, KeyCode::KeyC, KeyCode::KeyV,
+];
+
+// The window handles these before the keymap is consulted, so they can't press CHIP-8 keys
+const HOTKEYS: [(KeyCode, &str); 9] = [
+    (KeyCode::Backspace, "rewind"), (KeyCode::Tab, "fast-forward"), (KeyCode::F2, "pause"),
+    (KeyCode::F3, "slower"), (KeyCode::F4, "faster"), (KeyCode::F5, "save state"),
+    (KeyCode::F6, "previous save slot"), (KeyCode::F7, "next save slot"), (KeyCode::F9, "load state"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum HostKey{
+
+    Code(KeyCode),
+    Character(String), // Lower case
+}
+
+impl HostKey{
+
+    fn from_name(name: &str) -> Option<HostKey>{
+
+        if name.chars().count() == 1{
+
+            return Some(HostKey::Character(name.to_lowercase()));
+        }
+        key_code(name).map(HostKey::Code)
+    }
+}
+
+// Every key code winit knows, by the name of its variant
+fn key_code(name: &str) -> Option<KeyCode>{
+
+    let code = match name{
+
+        "Backquote" => KeyCode::Backquote,
+        "Backslash" => KeyCode::Backslash,
+        "BracketLeft" => KeyCode::BracketLeft,
+        "BracketRight" => KeyCode::BracketRight,
+        "Comma" => KeyCode::Comma,
+        "Digit0" => KeyCode::Digit0,
+        "Digit1" => KeyCode::Digit1,
+        "Digit2" => KeyCode::Digit2,
+        "Digit3" => KeyCode::Digit3,
+        "Digit4" => KeyCode::Digit4,
+        "Digit5" => KeyCode::Digit5,
+        "Digit6" => KeyCode::Digit6,
+        "Digit7" => KeyCode::Digit7,
+        "Digit8" => KeyCode::Digit8,
+        "Digit9" => KeyCode::Digit9,
+        "Equal" => KeyCode::Equal,
+        "IntlBackslash" => KeyCode::IntlBackslash,
+        "IntlRo" => KeyCode::IntlRo,
+        "IntlYen" => KeyCode::IntlYen,
+        "KeyA" => KeyCode::KeyA,
+        "KeyB" => KeyCode::KeyB,
+        "KeyC" => KeyCode::KeyC,
+        "KeyD" => KeyCode::KeyD,
+        "KeyE" => KeyCode::KeyE,
+        "KeyF" => KeyCode::KeyF,
+        "KeyG" => KeyCode::KeyG,
+        "KeyH" => KeyCode::KeyH,
+        "KeyI" => KeyCode::KeyI,
+        "KeyJ" => KeyCode::KeyJ,
+        "KeyK" => KeyCode::KeyK,
+        "KeyL" => KeyCode::KeyL,
+        "KeyM" => KeyCode::KeyM,
+        "KeyN" => KeyCode::KeyN,
+        "KeyO" => KeyCode::KeyO,
+        "KeyP" => KeyCode::KeyP,
+        "KeyQ" => KeyCode::KeyQ,
+        "KeyR" => KeyCode::KeyR,
+        "KeyS" => KeyCode::KeyS,
+        "KeyT" => KeyCode::KeyT,
+        "KeyU" => KeyCode::KeyU,
+        "KeyV" => KeyCode::KeyV,
+        "KeyW" => KeyCode::KeyW,
+        "KeyX" => KeyCode::KeyX,
+        "KeyY" => KeyCode::KeyY,
+        "KeyZ" => KeyCode::KeyZ,
+        "Minus" => KeyCode::Minus,
+        "Period" => KeyCode::Period,
+        "Quote" => KeyCode::Quote,
+        "Semicolon" => KeyCode::Semicolon,
+        "Slash" => KeyCode::Slash,
+        "AltLeft" => KeyCode::AltLeft,
+        "AltRight" => KeyCode::AltRight,
+        "Backspace" => KeyCode::Backspace,
+        "CapsLock" => KeyCode::CapsLock,
+        "ContextMenu" => KeyCode::ContextMenu,
+        "ControlLeft" => KeyCode::ControlLeft,
+        "ControlRight" => KeyCode::ControlRight,
+        "Enter" => KeyCode::Enter,
+        "SuperLeft" => KeyCode::SuperLeft,
+        "SuperRight" => KeyCode::SuperRight,
+        "ShiftLeft" => KeyCode::ShiftLeft,
+        "ShiftRight" => KeyCode::ShiftRight,
+        "Space" => KeyCode::Space,
+        "Tab" => KeyCode::Tab,
+        "Convert" => KeyCode::Convert,
+        "KanaMode" => KeyCode::KanaMode,
+        "Lang1" => KeyCode::Lang1,
+        "Lang2" => KeyCode::Lang2,
+        "Lang3" => KeyCode::Lang3,
+        "Lang4" => KeyCode::Lang4,
+        "Lang5" => KeyCode::Lang5,
+        "NonConvert" => KeyCode::NonConvert,
+        "Delete" => KeyCode::Delete,
+        "End" => KeyCode::End,
+        "Help" => KeyCode::Help,
+        "Home" => KeyCode::Home,
+        "Insert" => KeyCode::Insert,
+        "PageDown" => KeyCode::PageDown,
+        "PageUp" => KeyCode::PageUp,
+        "ArrowDown" => KeyCode::ArrowDown,
+        "ArrowLeft" => KeyCode::ArrowLeft,
+        "ArrowRight" => KeyCode::ArrowRight,
+        "ArrowUp" => KeyCode::ArrowUp,
+        "NumLock" => KeyCode::NumLock,
+        "Numpad0" => KeyCode::Numpad0,
+        "Numpad1" => KeyCode::Numpad1,
+        "Numpad2" => KeyCode::Numpad2,
+        "Numpad3" => KeyCode::Numpad3,
+        "Numpad4" => KeyCode::Numpad4,
+        "Numpad5" => KeyCode::Numpad5,
+        "Numpad6" => KeyCode::Numpad6,
+        "Numpad7" => KeyCode::Numpad7,
+        "Numpad8" => KeyCode::Numpad8,
+        "Numpad9" => KeyCode::Numpad9,
+        "NumpadAdd" => KeyCode::NumpadAdd,
+        "NumpadBackspace" => KeyCode::NumpadBackspace,
+        "NumpadClear" => KeyCode::NumpadClear,
+        "NumpadClearEntry" => KeyCode::NumpadClearEntry,
+        "NumpadComma" => KeyCode::NumpadComma,
+        "NumpadDecimal" => KeyCode::NumpadDecimal,
+        "NumpadDivide" => KeyCode::NumpadDivide,
+        "NumpadEnter" => KeyCode::NumpadEnter,
+        "NumpadEqual" => KeyCode::NumpadEqual,
+        "NumpadHash" => KeyCode::NumpadHash,
+        "NumpadMemoryAdd" => KeyCode::NumpadMemoryAdd,
+        "NumpadMemoryClear" => KeyCode::NumpadMemoryClear,
+        "NumpadMemoryRecall" => KeyCode::NumpadMemoryRecall,
+        "NumpadMemoryStore" => KeyCode::NumpadMemoryStore,
+        "NumpadMemorySubtract" => KeyCode::NumpadMemorySubtract,
+        "NumpadMultiply" => KeyCode::NumpadMultiply,
+        "NumpadParenLeft" => KeyCode::NumpadParenLeft,
+        "NumpadParenRight" => KeyCode::NumpadParenRight,
+        "NumpadStar" => KeyCode::NumpadStar,
+        "NumpadSubtract" => KeyCode::NumpadSubtract,
+        "Escape" => KeyCode::Escape,
+        "Fn" => KeyCode::Fn,
+        "FnLock" => KeyCode::FnLock,
+        "PrintScreen" => KeyCode::PrintScreen,
+        "ScrollLock" => KeyCode::ScrollLock,
+        "Pause" => KeyCode::Pause,
+        "BrowserBack" => KeyCode::BrowserBack,
+        "BrowserFavorites" => KeyCode::BrowserFavorites,
+        "BrowserForward" => KeyCode::BrowserForward,
+        "BrowserHome" => KeyCode::BrowserHome,
+        "BrowserRefresh" => KeyCode::BrowserRefresh,
+        "BrowserSearch" => KeyCode::BrowserSearch,
+        "BrowserStop" => KeyCode::BrowserStop,
+        "Eject" => KeyCode::Eject,
+        "LaunchApp1" => KeyCode::LaunchApp1,
+        "LaunchApp2" => KeyCode::LaunchApp2,
+        "LaunchMail" => KeyCode::LaunchMail,
+        "MediaPlayPause" => KeyCode::MediaPlayPause,
+        "MediaSelect" => KeyCode::MediaSelect,
+        "MediaStop" => KeyCode::MediaStop,
+        "MediaTrackNext" => KeyCode::MediaTrackNext,
+        "MediaTrackPrevious" => KeyCode::MediaTrackPrevious,
+        "Power" => KeyCode::Power,
+        "Sleep" => KeyCode::Sleep,
+        "AudioVolumeDown" => KeyCode::AudioVolumeDown,
+        "AudioVolumeMute" => KeyCode::AudioVolumeMute,
+        "AudioVolumeUp" => KeyCode::AudioVolumeUp,
+        "WakeUp" => KeyCode::WakeUp,
+        "Meta" => KeyCode::Meta,
+        "Hyper" => KeyCode::Hyper,
+        "Turbo" => KeyCode::Turbo,
+        "Abort" => KeyCode::Abort,
+        "Resume" => KeyCode::Resume,
+        "Suspend" => KeyCode::Suspend,
+        "Again" => KeyCode::Again,
+        "Copy" => KeyCode::Copy,
+        "Cut" => KeyCode::Cut,
+        "Find" => KeyCode::Find,
+        "Open" => KeyCode::Open,
+        "Paste" => KeyCode::Paste,
+        "Props" => KeyCode::Props,
+        "Select" => KeyCode::Select,
+        "Undo" => KeyCode::Undo,
+        "Hiragana" => KeyCode::Hiragana,
+        "Katakana" => KeyCode::Katakana,
+        "F1" => KeyCode::F1,
+        "F2" => KeyCode::F2,
+        "F3" => KeyCode::F3,
+        "F4" => KeyCode::F4,
+        "F5" => KeyCode::F5,
+        "F6" => KeyCode::F6,
+        "F7" => KeyCode::F7,
+        "F8" => KeyCode::F8,
+        "F9" => KeyCode::F9,
+        "F10" => KeyCode::F10,
+        "F11" => KeyCode::F11,
+        "F12" => KeyCode::F12,
+        "F13" => KeyCode::F13,
+        "F14" => KeyCode::F14,
+        "F15" => KeyCode::F15,
+        "F16" => KeyCode::F16,
+        "F17" => KeyCode::F17,
+        "F18" => KeyCode::F18,
+        "F19" => KeyCode::F19,
+        "F20" => KeyCode::F20,
+        "F21" => KeyCode::F21,
+        "F22" => KeyCode::F22,
+        "F23" => KeyCode::F23,
+        "F24" => KeyCode::F24,
+        "F25" => KeyCode::F25,
+        "F26" => KeyCode::F26,
+        "F27" => KeyCode::F27,
+        "F28" => KeyCode::F28,
+        "F29" => KeyCode::F29,
+        "F30" => KeyCode::F30,
+        "F31" => KeyCode::F31,
+        "F32" => KeyCode::F32,
+        "F33" => KeyCode::F33,
+        "F34" => KeyCode::F34,
+        "F35" => KeyCode::F35,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Host keys to CHIP-8 keys. A CHIP-8 key can have any number of host keys.
+#[derive(Debug, Clone)]
+pub struct Keymap{
+
+    bindings: HashMap<HostKey, u8>,
+}
+
+impl Default for Keymap{
+
+    // The qwerty layout
+    fn default() -> Self{
+
+        let hosts = DIGITS[1..5].iter().chain(&QWERTY).map(|&code| HostKey::Code(code));
+        Keymap{ bindings: hosts.zip(KEYPAD).collect() }
+    }
+}
+
+impl Keymap{
+
+    /// One of the built-in `LAYOUTS`.
+    pub fn layout(name: &str) -> Option<Keymap>{
+
+        // The 4x4 block under 1 2 3 4, by position on qwerty and by the printed letters on the others
+        let letters = match name{
+
+            "qwerty" => return Some(Keymap::default()),
+            "azerty" => ["a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v"],
+            "dvorak" => ["'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k"],
+            "numpad" => {
+
+                // Every key is the hex digit it shows: the numpad or the number row, then A to F
+                let mut bindings = HashMap::new();
+                for digit in 0..16u8{
+
+                    if digit < 10{
+
+                        bindings.insert(HostKey::Code(NUMPAD[digit as usize]), digit);
+                        bindings.insert(HostKey::Code(DIGITS[digit as usize]), digit);
+                    }else{
+
+                        bindings.insert(HostKey::Character(format!("{:x}", digit)), digit);
+                    }
+                }
+                return Some(Keymap{ bindings });
+            },
+            _ => return None,
+        };
+        let digits = DIGITS[1..5].iter().map(|&code| HostKey::Code(code));
+        let hosts = digits.chain(letters.iter().map(|letter| HostKey::Character(letter.to_string())));
+        Some(Keymap{ bindings: hosts.zip(KEYPAD).collect() })
+    }
+
+    /// The keymap for `rom`: the file at `path`, or `DEFAULT_KEYMAP` if there's one, or
+    /// the qwerty layout.
+    pub fn load(path: Option<&Path>, rom: &Path) -> Result<Keymap, String>{
+
+        let default = Path::new(DEFAULT_KEYMAP);
+        let Some(path) = path.or(default.exists().then_some(default)) else{
+
+            return Ok(Keymap::default());
+        };
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Keymap::parse(&text, rom).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str, rom: &Path) -> Result<Keymap, String>{
+
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut keymap = Keymap::default();
+        keymap.apply(&table, true)?;
+
+        // ROMs are picked out by file name, wherever they're loaded from
+        let roms = match table.get("roms"){
+
+            Some(roms) => roms.as_table().ok_or("roms must be a table of ROM file names")?,
+            None => return Ok(keymap),
+        };
+        let name = rom.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        if let Some(section) = roms.get(name.as_ref()){
+
+            let section = section.as_table().ok_or(format!("roms.\"{}\" must be a table", name))?;
+            keymap.apply(section, false).map_err(|e| format!("roms.\"{}\": {}", name, e))?;
+        }
+        Ok(keymap)
+    }
+
+    // A layout replaces everything before it, then the keys replace the bindings of the
+    // CHIP-8 keys they name
+    fn apply(&mut self, table: &toml::Table, top_level: bool) -> Result<(), String>{
+
+        for name in table.keys(){
+
+            let known = matches!(name.as_str(), "layout" | "keys") || (top_level && name == "roms");
+            if !known{
+
+                return Err(format!("unknown setting '{}'", name));
+            }
+        }
+
+        if let Some(layout) = table.get("layout"){
+
+            let layout = layout.as_str().ok_or("layout must be a string")?;
+            *self = Keymap::layout(layout).ok_or(format!("unknown layout '{}' (expected one of {})", layout, LAYOUTS.join(", ")))?;
+        }
+        let Some(keys) = table.get("keys") else{
+
+            return Ok(());
+        };
+        let keys = keys.as_table().ok_or("keys must be a table of CHIP-8 keys")?;
+        for (key, hosts) in keys{
+
+            let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or(format!("invalid CHIP-8 key '{}' (expected 0 to F)", key))?;
+            let names = match hosts{
+
+                toml::Value::String(name) => vec![name.as_str()],
+                toml::Value::Array(names) => names.iter().map(|name| name.as_str()).collect::<Option<_>>().ok_or(format!("key {:X} must list key names", key))?,
+                _ => return Err(format!("key {:X} must list key names", key)),
+            };
+            self.bindings.retain(|_, &mut bound| bound != key);
+            for name in names{
+
+                let host = HostKey::from_name(name).ok_or(format!("unknown key '{}' (expected a key code such as KeyQ or Numpad7, or a single character)", name))?;
+                if let HostKey::Code(code) = host
+                    && let Some((_, hotkey)) = HOTKEYS.iter().find(|(reserved, _)| *reserved == code){
+
+                    return Err(format!("'{}' is the {} hotkey and can't be bound", name, hotkey));
+                }
+                self.bindings.insert(host, key);
+            }
+        }
+        Ok(())
+    }
+
+    /// The CHIP-8 key for a key event. The key's position is looked up first, then the
+    /// character it types.
+    pub fn lookup(&self, physical_key: PhysicalKey, logical_key: &Key) -> Option<usize>{
+
+        if let PhysicalKey::Code(code) = physical_key
+            && let Some(&key) = self.bindings.get(&HostKey::Code(code)){
+
+            return Some(key as usize);
+        }
+        match logical_key{
+
+            Key::Character(text) => self.bindings.get(&HostKey::Character(text.to_lowercase())).map(|&key| key as usize),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    const ROM: &str = "roms/PONG.ch8";
+
+    fn bound(keymap: &Keymap, name: &str) -> Option<u8>{
+
+        keymap.bindings.get(&HostKey::from_name(name)?).copied()
+    }
+
+    fn error(text: &str) -> String{
+
+        Keymap::parse(text, Path::new(ROM)).unwrap_err()
+    }
+
+    #[test]
+    fn key_names(){
+
+        assert_eq!(key_code("KeyQ"), Some(KeyCode::KeyQ));
+        assert_eq!(key_code("NumpadEnter"), Some(KeyCode::NumpadEnter));
+        assert_eq!(key_code("F35"), Some(KeyCode::F35));
+        assert_eq!(key_code("keyq"), None);
+        assert_eq!(key_code("F36"), None);
+        assert_eq!(HostKey::from_name("Q"), Some(HostKey::Character("q".to_string())));
+    }
+
+    #[test]
+    fn every_layout_covers_the_keypad(){
+
+        for name in LAYOUTS{
+
+            let keymap = Keymap::layout(name).unwrap();
+            let mut keys: Vec<u8> = keymap.bindings.values().copied().collect();
+            keys.sort();
+            keys.dedup();
+            assert_eq!(keys, (0..16).collect::<Vec<u8>>(), "{}", name);
+        }
+        assert!(Keymap::layout("colemak").is_none());
+        assert_eq!(Keymap::default().bindings, Keymap::layout("qwerty").unwrap().bindings);
+    }
+
+    #[test]
+    fn built_in_layouts(){
+
+        let qwerty = Keymap::layout("qwerty").unwrap();
+        assert_eq!([bound(&qwerty, "Digit1"), bound(&qwerty, "KeyQ"), bound(&qwerty, "KeyX"), bound(&qwerty, "KeyV")], [Some(1), Some(4), Some(0), Some(0xF)]);
+        assert_eq!(bound(&qwerty, "q"), None);
+
+        let azerty = Keymap::layout("azerty").unwrap();
+        assert_eq!([bound(&azerty, "Digit4"), bound(&azerty, "a"), bound(&azerty, "w"), bound(&azerty, "v")], [Some(0xC), Some(4), Some(0xA), Some(0xF)]);
+
+        let dvorak = Keymap::layout("dvorak").unwrap();
+        assert_eq!([bound(&dvorak, "'"), bound(&dvorak, "o"), bound(&dvorak, ";"), bound(&dvorak, "k")], [Some(4), Some(8), Some(0xA), Some(0xF)]);
+
+        let numpad = Keymap::layout("numpad").unwrap();
+        assert_eq!(numpad.bindings.len(), 26);
+        assert_eq!([bound(&numpad, "Numpad7"), bound(&numpad, "Digit0"), bound(&numpad, "b"), bound(&numpad, "F")], [Some(7), Some(0), Some(0xB), Some(0xF)]);
+    }
+
+    #[test]
+    fn lookup_tries_the_position_then_the_character(){
+
+        let keymap = Keymap::parse("layout = \"azerty\"\n[keys]\n0 = [\"KeyA\"]", Path::new(ROM)).unwrap();
+        assert_eq!(keymap.lookup(PhysicalKey::Code(KeyCode::KeyA), &Key::Character("a".into())), Some(0));
+        assert_eq!(keymap.lookup(PhysicalKey::Code(KeyCode::KeyQ), &Key::Character("A".into())), Some(4));
+        assert_eq!(keymap.lookup(PhysicalKey::Code(KeyCode::KeyM), &Key::Character("m".into())), None);
+    }
+
+    #[test]
+    fn keys_replace_the_bindings_of_their_chip8_key(){
+
+        let keymap = Keymap::parse("[keys]\n5 = [\"KeyI\", \"ArrowUp\"]\n8 = \"s\"", Path::new(ROM)).unwrap();
+        assert_eq!([bound(&keymap, "KeyI"), bound(&keymap, "ArrowUp"), bound(&keymap, "KeyW")], [Some(5), Some(5), None]);
+        assert_eq!([bound(&keymap, "s"), bound(&keymap, "KeyS")], [Some(8), None]);
+        assert_eq!(bound(&keymap, "KeyQ"), Some(4));
+
+        let keymap = Keymap::parse("layout = \"numpad\"\n[keys]\na = \"Enter\"", Path::new(ROM)).unwrap();
+        assert_eq!([bound(&keymap, "Numpad7"), bound(&keymap, "Enter"), bound(&keymap, "a")], [Some(7), Some(0xA), None]);
+    }
+
+    #[test]
+    fn rom_sections_apply_to_that_rom_only(){
+
+        let text = "[keys]\nF = \"Space\"\n\n[roms.\"PONG.ch8\"]\nlayout = \"azerty\"\nkeys = { 1 = [\"KeyW\"] }";
+        let pong = Keymap::parse(text, Path::new(ROM)).unwrap();
+        assert_eq!([bound(&pong, "a"), bound(&pong, "KeyW"), bound(&pong, "Digit1")], [Some(4), Some(1), None]);
+        // The ROM's layout starts over, dropping the top level keys
+        assert_eq!([bound(&pong, "Space"), bound(&pong, "v")], [None, Some(0xF)]);
+
+        let other = Keymap::parse(text, Path::new("TETRIS.ch8")).unwrap();
+        assert_eq!([bound(&other, "KeyQ"), bound(&other, "Digit1"), bound(&other, "Space"), bound(&other, "KeyV")], [Some(4), Some(1), Some(0xF), None]);
+    }
+
+    #[test]
+    fn unknown_keys_and_settings(){
+
+        assert_eq!(error("[keys]\n5 = [\"KeyWW\"]"), "unknown key 'KeyWW' (expected a key code such as KeyQ or Numpad7, or a single character)");
+        assert_eq!(error("[roms.\"PONG.ch8\"]\nkeys = { 5 = \"Nope\" }"), "roms.\"PONG.ch8\": unknown key 'Nope' (expected a key code such as KeyQ or Numpad7, or a single character)");
+        assert_eq!(error("layout = \"colemak\""), "unknown layout 'colemak' (expected one of qwerty, azerty, dvorak, numpad)");
+        assert_eq!(error("speed = 2"), "unknown setting 'speed'");
+        assert_eq!(error("[roms.\"PONG.ch8\"]\nroms = {}"), "roms.\"PONG.ch8\": unknown setting 'roms'");
+        assert!(error("[keys\n").contains("TOML parse error"));
+    }
+
+    #[test]
+    fn hotkeys_cant_be_bound(){
+
+        assert_eq!(error("[keys]\n5 = \"F5\""), "'F5' is the save state hotkey and can't be bound");
+        assert_eq!(error("[keys]\n5 = [\"KeyW\", \"Backspace\"]"), "'Backspace' is the rewind hotkey and can't be bound");
+        assert_eq!(error("[roms.\"PONG.ch8\"]\nkeys = { 1 = \"Tab\" }"), "roms.\"PONG.ch8\": 'Tab' is the fast-forward hotkey and can't be bound");
+        assert!(Keymap::parse("[keys]\n5 = [\"F1\", \"F8\"]", Path::new(ROM)).is_ok());
+        for name in LAYOUTS{
+
+            let keymap = Keymap::layout(name).unwrap();
+            assert!(HOTKEYS.iter().all(|(code, _)| !keymap.bindings.contains_key(&HostKey::Code(*code))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bad_keypad_values(){
+
+        assert_eq!(error("[keys]\nG = \"KeyQ\""), "invalid CHIP-8 key 'G' (expected 0 to F)");
+        assert_eq!(error("[keys]\n10 = \"KeyQ\""), "invalid CHIP-8 key '10' (expected 0 to F)");
+        assert_eq!(error("[keys]\n5 = 3"), "key 5 must list key names");
+        assert_eq!(error("[keys]\n5 = [\"KeyQ\", 3]"), "key 5 must list key names");
+        assert_eq!(error("keys = \"KeyQ\""), "keys must be a table of CHIP-8 keys");
+        assert_eq!(error("roms = 1"), "roms must be a table of ROM file names");
+    }
+}
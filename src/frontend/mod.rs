@@ -0,0 +1,10 @@
+pub mod audio;
+pub mod cli;
+pub mod debugger;
+pub mod emulator;
+pub mod headless;
+pub mod input;
+pub mod keymap;
+pub mod render;
+pub mod timing;
+pub mod tools;
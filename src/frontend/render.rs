@@ -0,0 +1,48 @@
+use std::sync::{Arc, OnceLock};
+
+use winit::window::Window;
+
+use rustchip::display::HIRES_WIDTH;
+
+use crate::frontend::emulator::Frame;
+
+// Indexed by a pixel's plane bits: 0 is the background, 1 plane one, 2 plane two, 3 both.
+// The rest only show up when a ROM selects the third and fourth XO-CHIP planes.
+pub const PALETTE: [[u8; 4]; 16] = [
+    [0xC1, 0x72, 0x22, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF], [0x5A, 0x2E, 0x0A, 0xFF], [0xFF, 0xD8, 0x8A, 0xFF],
+    [0x22, 0x55, 0xAA, 0xFF], [0x88, 0xCC, 0xFF, 0xFF], [0x44, 0x22, 0x66, 0xFF], [0xCC, 0x99, 0xFF, 0xFF],
+    [0x22, 0x88, 0x44, 0xFF], [0x99, 0xEE, 0x99, 0xFF], [0x33, 0x44, 0x22, 0xFF], [0xDD, 0xEE, 0x66, 0xFF],
+    [0xAA, 0x22, 0x33, 0xFF], [0xFF, 0x88, 0x99, 0xFF], [0x22, 0x22, 0x22, 0xFF], [0x88, 0x88, 0x88, 0xFF],
+];
+
+/// Replaces the first four entries with a cartridge's background, plane and blend colours.
+pub fn apply_colours(palette: &mut [[u8; 4]; 16], colours: [[u8; 3]; 4]){
+
+    for (entry, [r, g, b]) in palette.iter_mut().zip(colours){
+
+        *entry = [r, g, b, 0xFF];
+    }
+}
+
+/// Fills a 128x64 RGBA surface. Lo-res pixels are drawn as 2x2 blocks.
+pub fn draw(frame: &Frame, palette: &[[u8; 4]; 16], surface: &mut [u8]){
+
+    let scale = HIRES_WIDTH / frame.width;
+    for (y, row) in surface.chunks_exact_mut(HIRES_WIDTH * 4).enumerate(){
+
+        for (x, out) in row.chunks_exact_mut(4).enumerate(){
+
+            let pixel = frame.pixels[y / scale][x / scale];
+            out.copy_from_slice(&palette[pixel as usize]);
+        }
+    }
+}
+
+pub fn show_status(window: &OnceLock<Arc<Window>>, message: &str){
+
+    println!("{}", message);
+    if let Some(window) = window.get(){
+
+        window.set_title(&format!("RustChip Chip-8 emulator - {}", message));
+    }
+}
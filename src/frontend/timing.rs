@@ -0,0 +1,166 @@
+use std::time::{Duration, Instant};
+
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+pub const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
+// After a stall (a slow host, a dragged window) this many frames are run back to back
+// to catch up, anything further behind is dropped
+const MAX_CATCH_UP: u32 = 4;
+
+/// Pause, speed and fast-forward, as set by the window's hotkeys.
+pub struct Speed{
+
+    level: usize, // Index into SPEEDS
+    pub paused: bool,
+    pub fast_forward: bool, // Held down: run as fast as the host allows
+}
+
+impl Default for Speed{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
+}
+
+impl Speed{
+
+    pub fn new() -> Speed{
+
+        Speed{ level: NORMAL_SPEED, paused: false, fast_forward: false }
+    }
+
+    // Moves one step through SPEEDS and returns the new speed
+    pub fn change(&mut self, faster: bool) -> f64{
+
+        self.level = if faster { (self.level + 1).min(SPEEDS.len() - 1) } else { self.level.saturating_sub(1) };
+        SPEEDS[self.level]
+    }
+
+    /// The speed as a multiple of real time, or None while fast-forwarding without a limit.
+    pub fn limit(&self) -> Option<f64>{
+
+        (!self.fast_forward).then_some(SPEEDS[self.level])
+    }
+}
+
+/// Keeps emulated frames in step with the wall clock. Frames are scheduled on a fixed
+/// grid rather than relative to when the last one ran, so lateness doesn't add up.
+pub struct FramePacer{
+
+    next_frame: Instant,
+}
+
+impl FramePacer{
+
+    pub fn new(now: Instant) -> FramePacer{
+
+        FramePacer{ next_frame: now }
+    }
+
+    // Starts a new grid, after a pause or anything else that wasn't paced
+    pub fn reset(&mut self, now: Instant){
+
+        self.next_frame = now;
+    }
+
+    /// The number of frames due by `now` at `speed`.
+    pub fn due(&mut self, now: Instant, speed: f64) -> u32{
+
+        if now < self.next_frame{
+
+            return 0;
+        }
+        let period = FRAME.div_f64(speed);
+        let behind = ((now - self.next_frame).as_secs_f64() / period.as_secs_f64()) as u32 + 1;
+        if behind > MAX_CATCH_UP{
+
+            self.next_frame = now + period;
+            MAX_CATCH_UP
+        }else{
+
+            self.next_frame += period * behind;
+            behind
+        }
+    }
+
+    pub fn until_next(&self, now: Instant) -> Duration{
+
+        self.next_frame.saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    #[test]
+    fn speed_steps_and_stops_at_the_ends(){
+
+        let mut speed = Speed::new();
+        assert_eq!(speed.limit(), Some(1.0));
+        assert_eq!([true, true, true, true].map(|faster| speed.change(faster)), [2.0, 4.0, 8.0, 8.0]);
+        assert_eq!(speed.limit(), Some(8.0));
+        assert_eq!([false; 6].map(|faster| speed.change(faster)), [4.0, 2.0, 1.0, 0.5, 0.25, 0.25]);
+        assert_eq!(speed.limit(), Some(0.25));
+    }
+
+    #[test]
+    fn fast_forward_lifts_the_limit_and_keeps_the_speed(){
+
+        let mut speed = Speed::new();
+        speed.change(false);
+        speed.fast_forward = true;
+        assert_eq!(speed.limit(), None);
+        speed.fast_forward = false;
+        assert_eq!(speed.limit(), Some(0.5));
+    }
+
+    // Times are kept away from frame boundaries, where float rounding could go either way
+    #[test]
+    fn frames_follow_a_fixed_grid(){
+
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(pacer.due(start, 1.0), 1);
+        assert_eq!(pacer.due(start, 1.0), 0);
+        assert_eq!(pacer.until_next(start), FRAME);
+
+        // Running late doesn't move the grid: the next frame is still due at 3 frames
+        let late = start + FRAME * 2 + FRAME / 2;
+        assert_eq!(pacer.due(late, 1.0), 2);
+        assert_eq!(pacer.until_next(late), FRAME / 2);
+        assert_eq!(pacer.until_next(start + FRAME * 4), Duration::ZERO);
+    }
+
+    #[test]
+    fn speed_changes_the_frame_period(){
+
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(pacer.due(start, 2.0), 1);
+        assert_eq!(pacer.until_next(start), FRAME.div_f64(2.0));
+        assert_eq!(pacer.due(start + FRAME + FRAME / 4, 2.0), 2);
+
+        pacer.reset(start);
+        assert_eq!(pacer.due(start, 0.25), 1);
+        assert_eq!(pacer.due(start + FRAME * 3, 0.25), 0);
+        assert_eq!(pacer.until_next(start), FRAME.div_f64(0.25));
+    }
+
+    #[test]
+    fn catching_up_is_capped(){
+
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        pacer.due(start, 1.0);
+
+        let stalled = start + FRAME * 100;
+        assert_eq!(pacer.due(stalled, 1.0), MAX_CATCH_UP);
+        assert_eq!(pacer.until_next(stalled), FRAME);
+        assert_eq!(pacer.due(stalled, 1.0), 0);
+        assert_eq!(pacer.due(stalled + FRAME + FRAME / 2, 1.0), 1);
+    }
+}
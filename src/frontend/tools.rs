@@ -0,0 +1,120 @@
+use std::{error::Error, fs, io::{self, Write}, path::Path};
+
+use rustchip::{Chip8, asm, cartridge::{Cartridge, CartridgeOptions}, chip8::PROGRAM_START, disasm::{self, Platform}, movie::Movie, octo, quirks::Quirks};
+
+use crate::frontend::{cli::{AsmOptions, CartOptions, DisasmOptions, Options}, render::{apply_colours, PALETTE}};
+
+// The subcommands that work on ROM files without opening a window
+
+pub fn disasm(options: &DisasmOptions) -> Result<(), String>{
+
+    let rom = fs::read(&options.rom).map_err(|e| format!("could not read {}: {}", options.rom, e))?;
+    let listing = if options.linear{
+
+        disasm::listing(&rom, PROGRAM_START, options.syntax, options.platform)
+    }else{
+
+        disasm::trace_listing(&rom, PROGRAM_START, options.syntax, options.platform)
+    };
+    print_output(&listing);
+    Ok(())
+}
+
+pub fn asm(options: &AsmOptions) -> Result<(), String>{
+
+    let path = Path::new(&options.source);
+    let rom = if is_octo_source(path){
+
+        let source = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", options.source, e))?;
+        octo::compile(&source, options.target).map_err(|e| format!("{}: {}", options.source, e))?
+    }else{
+
+        asm::assemble_file(path).map_err(|e| e.to_string())?
+    };
+    fs::write(&options.output, &rom).map_err(|e| format!("could not write {}: {}", options.output, e))?;
+    println!("Wrote {} bytes to {}", rom.len(), options.output);
+    Ok(())
+}
+
+// Octo sources go in as they are, binary ROMs as data bytes
+pub fn cart(options: &CartOptions) -> Result<(), String>{
+
+    let path = Path::new(&options.input);
+    let cartridge = if is_octo_source(path){
+
+        let program = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", options.input, e))?;
+        let cartridge = Cartridge{ program, options: options.options };
+        cartridge.rom().map_err(|e| format!("{}: {}", options.input, e))?;
+        cartridge
+    }else{
+
+        let rom = fs::read(path).map_err(|e| format!("could not read {}: {}", options.input, e))?;
+        Cartridge::from_rom(&rom, options.options)
+    };
+    let file = fs::File::create(&options.output).map_err(|e| format!("could not create {}: {}", options.output, e))?;
+    cartridge.write(io::BufWriter::new(file)).map_err(|e| format!("{}: {}", options.output, e))?;
+    println!("Wrote cartridge {}", options.output);
+    Ok(())
+}
+
+/// Loads `options.rom` into a fresh machine and returns the palette to draw it with. A
+/// cartridge's quirks, speed and colours are only defaults: `--quirks`, `--quirk` and
+/// `--cycles-per-frame` still win over them.
+pub fn load_rom(machine: &mut Chip8, options: &Options) -> Result<[[u8; 4]; 16], String>{
+
+    let (rom, cartridge) = load_program(Path::new(&options.rom)).map_err(|e| format!("could not load {}: {}", options.rom, e))?;
+
+    let mut palette = PALETTE;
+    let mut quirks = Quirks::default();
+    if let Some(cartridge) = cartridge{
+
+        quirks = cartridge.quirks;
+        apply_colours(&mut palette, cartridge.colours);
+        machine.set_cycles_per_frame(cartridge.tickrate);
+    }
+    machine.cpu_mut().set_quirks(options.quirks.apply(quirks)?);
+    if let Some(cycles) = options.cycles_per_frame{
+
+        machine.set_cycles_per_frame(cycles);
+    }
+    machine.load_rom(&rom).map_err(|e| format!("could not load {}: {}", options.rom, e))?;
+    Ok(palette)
+}
+
+/// Reads a ROM, compiling `.8o` sources and unpacking `.gif` cartridges along with their settings.
+pub fn load_program(path: &Path) -> Result<(Vec<u8>, Option<CartridgeOptions>), Box<dyn Error>>{
+
+    // Octo sources are compiled on load so game jam entries run without a build step
+    if is_octo_source(path){
+
+        Ok((octo::compile(&fs::read_to_string(path)?, Platform::XoChip)?, None))
+    }else if is_cartridge(path){
+
+        let cartridge = Cartridge::read(io::BufReader::new(fs::File::open(path)?))?;
+        Ok((cartridge.rom()?, Some(cartridge.options)))
+    }else{
+
+        Ok((fs::read(path)?, None))
+    }
+}
+
+pub fn load_movie(path: &Path) -> Result<Movie, Box<dyn Error>>{
+
+    Ok(Movie::parse(&fs::read_to_string(path)?)?)
+}
+
+pub fn is_cartridge(path: &Path) -> bool{
+
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+pub fn is_octo_source(path: &Path) -> bool{
+
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("8o"))
+}
+
+// Listings are often piped into head or less, so a closed pipe isn't an error
+fn print_output(text: &str){
+
+    let _ = writeln!(io::stdout().lock(), "{}", text);
+}
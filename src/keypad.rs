@@ -1,9 +1,20 @@
 
+use crate::error::Fault;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
 pub struct Keypad{
 
     keys: [bool; 16],
 }
 
+impl Default for Keypad{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
+}
+
 impl Keypad{
 
     pub fn new() -> Keypad{
@@ -27,10 +38,26 @@ impl Keypad{
         self.keys[key] = false;
     }
 
-    pub fn is_pressed(&self, key: usize) -> bool{
-        assert!(key < 16);
+    pub fn is_pressed(&self, key: usize) -> Result<bool, Fault>{
 
-        self.keys[key]
+        self.keys.get(key).copied().ok_or(Fault::InvalidKey(key as u8))
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter){
+
+        for &key in self.keys.iter(){
+
+            state.put_bool(key);
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>{
+
+        for key in self.keys.iter_mut(){
+
+            *key = state.get_bool()?;
+        }
+        Ok(())
     }
 
     pub fn get_keys(&self) -> [bool; 16]{
@@ -40,13 +67,7 @@ impl Keypad{
 
     pub fn wait_for_press(&self) -> Option<u8>{
 
-        if let Some(index) = self.keys.iter().position(|&key| key){
-
-            Some(index as u8)
-        }else{
-
-            None
-        }
+        self.keys.iter().position(|&key| key).map(|index| index as u8)
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,31 @@
+//! Core of the RustChip interpreter.
+//!
+//! Nothing in this crate touches a window or an audio device, so the machine
+//! can be driven from tests, tools or any frontend. The `rustchip` binary is
+//! one such frontend built on winit, pixels and rodio.
+
+pub mod memory;
+pub mod cpu;
+pub mod keypad;
+pub mod display;
+pub mod chip8;
+pub mod audio;
+pub mod error;
+pub mod quirks;
+pub mod savestate;
+pub mod rewind;
+pub mod debugger;
+pub mod expr;
+pub mod disasm;
+pub mod opcodes;
+pub mod asm;
+pub mod octo;
+pub mod cartridge;
+pub mod screenshot;
+pub mod rng;
+pub mod movie;
+
+#[cfg(test)]
+mod test_support;
+
+pub use crate::{chip8::Chip8, cpu::{Cpu, StepOutcome}, error::{Chip8Error, Fault}, quirks::Quirks, memory::Memory, display::Display, keypad::Keypad};
@@ -1,105 +1,226 @@
-pub mod memory;
-pub mod cpu;
-pub mod keypad;
-pub mod display;
-use std::{fs, sync::{Arc, Mutex}, thread, time::{self, Instant, Duration}};
+mod frontend;
+
+use std::{fs, io, path::{Path, PathBuf}, sync::{Arc, OnceLock, mpsc::{self, Sender}}, thread::JoinHandle};
 use winit::{ application::ApplicationHandler, event::*, 
             event_loop::{ActiveEventLoop, EventLoop}, 
             window::{Window, WindowId, WindowAttributes},
             dpi::PhysicalSize,
             keyboard::{ PhysicalKey, KeyCode },
         };
-
-use crate::{cpu::Cpu, keypad::Keypad, display::Display};
 use pixels::Pixels;
 
+use rustchip::{Chip8, display::{HIRES_WIDTH, HIRES_HEIGHT}, movie::{Movie, Playback}, savestate::{self, SLOT_COUNT}, debugger::Debugger, };
+use crate::frontend::{audio::RodioAudio, cli::{Command, Options}, debugger as repl, emulator::{self, Emulator, Frame}, headless, input::Input, keymap::Keymap, render, tools};
+
 const WINDOW_SCALE: u16 = 15;
-const ON: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
-const OFF: [u8; 4] = [0xC1, 0x72, 0x22, 0xFF];
 
 fn main() {
     
+    let options = match Command::parse(std::env::args().skip(1)){
+
+        Ok(Command::Run(options)) if options.headless => exit_with(headless::run(&options)),
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Disasm(options)) => exit_with(tools::disasm(&options)),
+        Ok(Command::Asm(options)) => exit_with(tools::asm(&options)),
+        Ok(Command::Cart(options)) => exit_with(tools::cart(&options)),
+        Err(message) => {
+
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
     println!("Initializing app and peripherals...");
     let game_loop = EventLoop::new().unwrap();
-    let mut game_app = App::new();
+    let mut machine = Chip8::new();
+    match RodioAudio::open(){
 
-    let args: Vec<String> = std::env::args().collect();
-    let rom_file = args.get(1).map(|s| s.as_str()).unwrap_or("PONG.ch8");
+        Ok(audio) => machine.set_audio(Box::new(audio)),
+        Err(e) => eprintln!("Warning: no audio output ({}), running without sound.", e),
+    }
 
     println!("Loading rom...");
-    game_app.load_rom(rom_file, 0x200);
+    let palette = match tools::load_rom(&mut machine, &options){
+
+        Ok(palette) => palette,
+        Err(e) => {
+
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let keymap = match Keymap::load(options.keymap.as_deref().map(Path::new), Path::new(&options.rom)){
+
+        Ok(keymap) => keymap,
+        Err(e) => {
+
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(trace_path) = &options.trace{
+
+        match fs::File::create(trace_path){
+
+            Ok(file) => machine.cpu_mut().set_trace(Some(Box::new(io::BufWriter::new(file)))),
+            Err(e) => {
+
+                eprintln!("Error: could not create {}: {}", trace_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let input = match start_input(&mut machine, &options){
+
+        Ok(input) => input,
+        Err(e) => {
 
-    start_cpu_thread(Arc::clone(&game_app.cpu), Arc::clone(&game_app.display), Arc::clone(&game_app.keypad));
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (commands, receiver) = mpsc::channel();
+    let debugger = options.debug.then(|| {
+
+        repl::spawn_repl(commands.clone());
+        Debugger::new()
+    });
+    let window = Arc::new(OnceLock::new());
+    let (emulator, frames) = Emulator::new(machine, input, debugger, receiver, Arc::clone(&window));
+    let mut game_app = App{ window, pixels: None, frames, commands, emulator: Some(emulator.spawn()), recording: None, rom_path: PathBuf::from(&options.rom), save_slot: 0, palette, keymap };
 
     println!("Starting loop...");
     game_loop.run_app(&mut game_app).unwrap();
+    game_app.stop_emulator();
+    if let Some(path) = &options.record
+        && let Some(movie) = game_app.recording.take(){
+
+        match fs::write(path, movie.to_string()){
+
+            Ok(()) => println!("Saved movie to {}.", path),
+            Err(e) => eprintln!("Error: could not write {}: {}", path, e),
+        }
+    }
     
 }
 
+// Seeds the machine, then starts recording or playing a movie if asked to
+fn start_input(machine: &mut Chip8, options: &Options) -> Result<Input, String>{
+
+    let mut input = Input::new();
+    if let Some(path) = &options.play{
+
+        let movie = tools::load_movie(Path::new(path)).map_err(|e| format!("could not load {}: {}", path, e))?;
+        movie.start(machine).map_err(|e| format!("could not play {}: {}", path, e))?;
+        input.play(Playback::new(movie));
+        return Ok(input);
+    }
+
+    // Printed so a run can be repeated exactly, e.g. to reproduce a bug
+    let seed = options.seed.unwrap_or_else(rand::random);
+    println!("Random seed: {}", seed);
+    machine.cpu_mut().set_seed(seed);
+    if options.record.is_some(){
+
+        input.record(Movie::new(machine, seed));
+    }
+    Ok(input)
+}
+
+// Ends a subcommand, which have nothing more to do once they return
+fn exit_with(result: Result<(), String>) -> !{
+
+    match result{
+
+        Ok(()) => std::process::exit(0),
+        Err(message) => {
+
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+    }
+}
+
 struct App<'w>{
 
-    window: Option<Arc<Window>>,
-    keypad: Arc<Mutex<keypad::Keypad>>,
-    display: Arc<Mutex<display::Display>>,
+    // Shared with the emulator thread so it can request a redraw once the window exists
+    window: Arc<OnceLock<Arc<Window>>>,
     pixels: Option<Pixels<'w>>,
-    cpu: Arc<Mutex<cpu::Cpu>>,
+    frames: triple_buffer::Output<Frame>,
 
-    fps: time::Duration,
-    last_frame: time::Instant,
+    // The emulator thread owns the machine, this is the only way to reach it
+    commands: Sender<emulator::Command>,
+    emulator: Option<JoinHandle<Option<Movie>>>,
+    recording: Option<Movie>, // Handed back by the emulator thread when it stops
+
+    rom_path: PathBuf,
+    save_slot: u8,
+
+    // Cartridges bring their own colours
+    palette: [[u8; 4]; 16],
+    keymap: Keymap,
 }
 
 impl<'w> App<'w>{
 
-    fn new() -> App<'w>{
+    fn render_display(&mut self){
 
-        let keypad = Arc::new(Mutex::new(Keypad::new()));
-        let display = Arc::new(Mutex::new(Display::new()));
-        let cpu = Arc::new(Mutex::new(cpu::Cpu::new()));
+        if let Some(pixels) = self.pixels.as_mut(){
 
-        let fps = time::Duration::from_millis(16);
-        let last_frame = time::Instant::now();
-        App { window: None, keypad, display, pixels: None, cpu, fps, last_frame }
+            render::draw(self.frames.read(), &self.palette, pixels.frame_mut());
+        }
     }
 
-    fn render_display(&mut self){
+    // The thread may already be gone after a panic, so a failed send is fine
+    fn send(&self, command: emulator::Command){
 
-        if let Some(pixels) = self.pixels.as_mut(){
+        let _ = self.commands.send(command);
+    }
 
-            let display = self.display.lock().unwrap();
-            let frame_buffer = display.get_buffer();
-            let pixel_frame = pixels.frame_mut();
-            
-            for y in 0..32{
+    fn stop_emulator(&mut self){
 
-                for x in 0..64{
+        if let Some(emulator) = self.emulator.take(){
 
-                    let pixel = frame_buffer[y][x];
-                    let idx: usize = (y * 64 + x) * 4;
-                    if pixel{
-                        
-                        pixel_frame[idx..idx + 4].copy_from_slice(&ON);
-                    }else{
+            self.send(emulator::Command::Quit);
+            match emulator.join(){
 
-                        pixel_frame[idx..idx + 4].copy_from_slice(&OFF);
-                    }
-                }
+                Ok(recording) => self.recording = recording,
+                Err(_) => eprintln!("Error: the emulator thread panicked"),
             }
         }
     }
 
-    fn load_rom(&mut self, filename: &str, start_addr: u16){
+    fn change_save_slot(&mut self, forward: bool){
 
-        let rom_bytes = fs::read(filename).unwrap();
+        self.save_slot = if forward{
 
-        for index in 0..rom_bytes.len(){
+            (self.save_slot + 1) % SLOT_COUNT
+        }else{
+
+            (self.save_slot + SLOT_COUNT - 1) % SLOT_COUNT
+        };
+        render::show_status(&self.window, &format!("Save slot {}", self.save_slot));
+    }
 
-            let byte = rom_bytes[index];
-            let mut cpu = self.cpu.lock().unwrap();
-            cpu.write_byte_to_mem(byte, index + start_addr as usize);
+    // Emulator hotkeys, handled before the keypad sees the key, which is why the keymap
+    // refuses to bind them (keymap::HOTKEYS). Returns true if the key was used.
+    fn handle_hotkey(&mut self, key: KeyCode) -> bool{
+
+        let slot = self.save_slot;
+        match key{
+
+            KeyCode::F5 => self.send(emulator::Command::SaveState{ path: savestate::slot_path(&self.rom_path, slot), slot }),
+            KeyCode::F6 => self.change_save_slot(false),
+            KeyCode::F7 => self.change_save_slot(true),
+            KeyCode::F9 => self.send(emulator::Command::LoadState{ path: savestate::slot_path(&self.rom_path, slot), slot }),
+            KeyCode::F2 => self.send(emulator::Command::TogglePause),
+            KeyCode::F3 | KeyCode::F4 => self.send(emulator::Command::ChangeSpeed{ faster: key == KeyCode::F4 }),
+            _ => return false,
         }
-    
-        let mut cpu = self.cpu.lock().unwrap();
-        cpu.set_pc(start_addr);
+        true
     }
 
 }
@@ -122,20 +243,14 @@ impl<'w> ApplicationHandler for App<'w>{
             Arc::clone(&window),
         );
 
-        let pixels = pixels::PixelsBuilder::new(64, 32, surface_texture)
+        let pixels = pixels::PixelsBuilder::new(HIRES_WIDTH as u32, HIRES_HEIGHT as u32, surface_texture)
             .build()
             .unwrap();
 
-        self.window = Some(Arc::clone(&window));
+        let _ = self.window.set(window);
         self.pixels = Some(pixels);
-
-        if let Ok(mut cpu) = self.cpu.lock(){
-
-            cpu.set_window(Arc::clone(&window));
-        }
-
     }
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent){
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent){
         
         match event{
 
@@ -149,172 +264,37 @@ impl<'w> ApplicationHandler for App<'w>{
             }
             WindowEvent::CloseRequested => {
 
+                self.stop_emulator();
                 event_loop.exit();
             }
-            WindowEvent::KeyboardInput{event: KeyEvent{ physical_key, state, ..}, ..} => {
-
-                println!("Key event: {:?} {:?}", physical_key, state);
-                let mut keypad = self.keypad.lock().unwrap();
-                match physical_key{
-
-                    PhysicalKey::Code(KeyCode::Digit1) => {
-
-                        let key = 0x1;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::Digit2) => {
-
-                        let key = 0x2;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::Digit3) => {
-
-                        let key = 0x3;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::Digit4) => {
-
-                        let key = 0xC;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyQ) => {
-
-                        let key = 0x4;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyW) => {
-
-                        let key = 0x5;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyE) => {
-
-                        let key = 0x6;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyR) => {
-
-                        let key = 0xD;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyA) => {
-
-                        let key = 0x7;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyS) => {
-
-                        let key = 0x8;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyD) => {
-
-                        let key = 0x9;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyF) => {
-
-                        let key = 0xE;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyZ) => {
-
-                        let key = 0xA;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyX) => {
-
-                        let key = 0x0;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyC) => {
-
-                        let key = 0xB;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    PhysicalKey::Code(KeyCode::KeyV) => {
-
-                        let key = 0xF;
-                        if state.is_pressed(){ keypad.press(key) } else { keypad.release(key)};
-                    },
-                    _ => {}
-                }
-            },
-            _ => {}
-        }
-    }
-    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {}
-}
-
-fn start_cpu_thread(cpu: Arc<Mutex<Cpu>>, display: Arc<Mutex<Display>>, keypad: Arc<Mutex<Keypad>>){
+            WindowEvent::KeyboardInput{event: KeyEvent{ physical_key, logical_key, state, ..}, ..} => {
 
-    thread::spawn(move || {
-        let cpu_hz = 500;
-        let cpu_period = Duration::from_secs_f64(1.0 / cpu_hz as f64);
-        let mut last_cpu_tick = Instant::now();
+                if physical_key == PhysicalKey::Code(KeyCode::Backspace){
 
-        let timer_hz = 60;
-        let timer_period = Duration::from_secs_f64(1.0 / timer_hz as f64);
-        let mut last_timer_tick = Instant::now();
-
-        loop {
-            let now = Instant::now();
-
-            // --- CPU cycle ---
-            if now - last_cpu_tick >= cpu_period {
-                let halted = {
-                    let cpu_guard = cpu.lock().unwrap();
-                    cpu_guard.is_halted()
-                };
-
-                if halted {
-                    // Only check for key press while halted
-                    if let Some(v_x) = {
-                        let cpu_guard = cpu.lock().unwrap();
-                        cpu_guard.get_wait_register()
-                    } {
-                        if let Some(key) = keypad.lock().unwrap().wait_for_press() {
-                            let mut cpu_guard = cpu.lock().unwrap();
-                            cpu_guard.set_register(v_x, key);
-                            cpu_guard.set_wait_register(None);
-                            cpu_guard.resume();
-                        }
-                    }
-                } else {
-                    // Normal cycle
-                    let mut cpu_guard = cpu.lock().unwrap();
-                    let mut display_guard = display.lock().unwrap();
-                    let mut keypad_guard = keypad.lock().unwrap();
-                    cpu_guard.cycle(&mut keypad_guard, &mut display_guard);
+                    self.send(emulator::Command::Rewind(state.is_pressed()));
+                    return;
                 }
+                if physical_key == PhysicalKey::Code(KeyCode::Tab){
 
-                last_cpu_tick += cpu_period;
-            }
-
-            // --- Timers ---
-            if now - last_timer_tick >= timer_period {
-                let mut cpu_guard = cpu.lock().unwrap();
-                if cpu_guard.get_delay_timer() > 0 {
-                    cpu_guard.decrement_delay_timer();
+                    self.send(emulator::Command::FastForward(state.is_pressed()));
+                    return;
                 }
-                if cpu_guard.get_sound_timer() > 0 {
-                    cpu_guard.decrement_sound_timer();
-                }
-
-                last_timer_tick += timer_period;
+                if let PhysicalKey::Code(code) = physical_key
+                    && state.is_pressed()
+                    && self.handle_hotkey(code){
 
-                let mut display = display.lock().unwrap();
-                if display.needs_update(){
-
-                    if let Some(window) = cpu_guard.get_window(){
-
-                        window.request_redraw();
-                    }
+                    return;
                 }
-            }
 
-            thread::sleep(Duration::from_millis(1));
-        }
-    });
+                let Some(key) = self.keymap.lookup(physical_key, &logical_key) else{
 
-}
\ No newline at end of file
+                    return;
+                };
+                // Keys reach the machine at the start of the next frame
+                self.send(emulator::Command::Key{ key, pressed: state.is_pressed() });
+            },
+            _ => {}
+        }
+    }
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {}
+}
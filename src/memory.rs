@@ -1,3 +1,6 @@
+use crate::error::{Chip8Error, Fault};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
 const FONTSET: [u8; 80] = [
             
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -17,18 +20,51 @@ const FONTSET: [u8; 80] = [
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80  // F
         ];
-const FONTSET_START: usize = 0x50;
+pub const FONTSET_START: usize = 0x50;
+
+// SUPER-CHIP 8x10 digits for FX30, with A-F as in Octo
+const BIG_FONTSET: [u8; 160] = [
+
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+        ];
+pub const BIG_FONTSET_START: usize = FONTSET_START + FONTSET.len();
+
+// XO-CHIP addresses the full 16-bit range; classic programs simply never touch the rest
+pub const MEMORY_SIZE: usize = 0x10000;
 
 pub struct Memory{
 
-    ram: [u8; 4096]
+    ram: Vec<u8>
+}
+
+impl Default for Memory{
+
+    fn default() -> Self{
+
+        Self::new()
+    }
 }
 
 impl Memory{
 
     pub fn new()->Memory{
 
-        let ram:[u8; 4096] = [0; 4096];
+        let ram = vec![0; MEMORY_SIZE];
         let mut memory = Memory{ram};
         memory.load_font();
 
@@ -41,36 +77,54 @@ impl Memory{
 
             self.ram[FONTSET_START + index] = byte;
         }
+
+        for (index, &byte) in BIG_FONTSET.iter().enumerate(){
+
+            self.ram[BIG_FONTSET_START + index] = byte;
+        }
     }
 
-    pub fn load_rom(&mut self, rom_bytes: &[u8], start_addr: usize){
-        assert!(start_addr + rom_bytes.len() <= 4096);
+    pub fn load_rom(&mut self, rom_bytes: &[u8], start_addr: usize)->Result<(), Chip8Error>{
 
-        for (index, &byte) in rom_bytes.iter().enumerate(){
+        let capacity = self.ram.len().saturating_sub(start_addr);
+        if rom_bytes.len() > capacity{
 
-            self.ram[start_addr + index] = byte;
+            return Err(Chip8Error::RomTooLarge{ size: rom_bytes.len(), capacity });
         }
+
+        self.ram[start_addr..start_addr + rom_bytes.len()].copy_from_slice(rom_bytes);
+        Ok(())
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter){
+
+        state.put_bytes(&self.ram);
     }
 
-    pub fn read_byte(&self, addr: usize)->u8{
-        assert!(addr < 4096);
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>{
 
-        self.ram[addr]
+        self.ram.copy_from_slice(state.get_bytes(MEMORY_SIZE)?);
+        Ok(())
     }
 
-    pub fn write_byte(&mut self, addr: usize, byte: u8){
-        assert!(addr < 4096);
+    pub fn read_byte(&self, addr: usize)->Result<u8, Fault>{
+
+        self.ram.get(addr).copied().ok_or(Fault::AddressOutOfRange(addr))
+    }
+
+    pub fn write_byte(&mut self, addr: usize, byte: u8)->Result<(), Fault>{
 
         //println!("Memory[{:03X}] <= {:02X}", addr, byte);
-        self.ram[addr] = byte;
+        let cell = self.ram.get_mut(addr).ok_or(Fault::AddressOutOfRange(addr))?;
+        *cell = byte;
+        Ok(())
     }
 
-    pub fn read_16(&self, addr: usize)->u16{
-        assert!(addr + 1 < 4096);
+    pub fn read_16(&self, addr: usize)->Result<u16, Fault>{
 
-        let first_byte = self.ram[addr];
-        let second_byte = self.ram[addr + 1];
+        let first_byte = self.read_byte(addr)?;
+        let second_byte = self.read_byte(addr + 1)?;
 
-        ((first_byte as u16) << 8) | (second_byte as u16)
+        Ok(((first_byte as u16) << 8) | (second_byte as u16))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,315 @@
+//! Input movies: the keypad events of a run, each tagged with the frame it happened on.
+//!
+//! Events take effect at the start of their frame, before any of its instructions run.
+//! Together with the ROM, seed, speed and quirks the movie was made with, that's enough
+//! to repeat the run exactly. Movies are plain text so they can be attached to bug reports:
+//!
+//! ```text
+//! RustChip movie 2
+//! rom 5C4F1E3A0B2D7788
+//! seed 1234
+//! cycles-per-frame 10
+//! quirks shift=off load-store=unchanged vf-reset=off jump=off clip=off display-wait=off
+//! frames 600
+//! 120 press 5
+//! 134 release 5
+//! ```
+
+use std::fmt;
+
+use crate::{chip8::Chip8, quirks::{Quirks, QUIRK_NAMES}};
+
+const HEADER: &str = "RustChip movie 2";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent{
+
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recording that starts at power-on, right after the ROM is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie{
+
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    pub quirks: Quirks,
+    pub frames: u64,           // Length of the recording
+    pub events: Vec<KeyEvent>, // In frame order
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError{
+
+    Parse{ line: usize, message: String },
+    RomMismatch{ expected: u64, found: u64 },
+}
+
+impl fmt::Display for MovieError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            MovieError::Parse{ line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::RomMismatch{ expected, found } => write!(f, "movie was recorded with a different ROM (hash {:016X}, loaded ROM is {:016X})", found, expected),
+        }
+    }
+}
+
+impl std::error::Error for MovieError{}
+
+impl Movie{
+
+    /// An empty recording of `machine`, which has just loaded its ROM and been seeded with `seed`.
+    pub fn new(machine: &Chip8, seed: u64) -> Movie{
+
+        Movie{ rom_hash: machine.rom_hash(), seed, cycles_per_frame: machine.cycles_per_frame(), quirks: machine.cpu().quirks(), frames: 0, events: Vec::new() }
+    }
+
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool){
+
+        self.truncate(frame);
+        self.events.push(KeyEvent{ frame, key, pressed });
+        self.frames = self.frames.max(frame + 1);
+    }
+
+    // Drops everything after `frame`, for when the run rewinds and takes another path
+    pub fn truncate(&mut self, frame: u64){
+
+        self.events.retain(|event| event.frame <= frame);
+        self.frames = self.frames.min(frame + 1);
+    }
+
+    /// Sets `machine` up the way the movie was recorded. The ROM must already be loaded.
+    pub fn start(&self, machine: &mut Chip8) -> Result<(), MovieError>{
+
+        if machine.rom_hash() != self.rom_hash{
+
+            return Err(MovieError::RomMismatch{ expected: machine.rom_hash(), found: self.rom_hash });
+        }
+        machine.cpu_mut().set_quirks(self.quirks);
+        machine.cpu_mut().set_seed(self.seed);
+        machine.set_cycles_per_frame(self.cycles_per_frame);
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError>{
+
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line.trim())).filter(|(_, line)| !line.is_empty());
+        if lines.next().map(|(_, line)| line) != Some(HEADER){
+
+            return Err(MovieError::Parse{ line: 1, message: format!("expected '{}'", HEADER) });
+        }
+
+        let mut movie = Movie{ rom_hash: 0, seed: 0, cycles_per_frame: 0, quirks: Quirks::default(), frames: 0, events: Vec::new() };
+        let mut has_rom = false;
+        for (line, text) in lines{
+
+            let error = |message: String| MovieError::Parse{ line, message };
+            let number = |value: &str| value.parse::<u64>().map_err(|_| error(format!("invalid number '{}'", value)));
+            let (key, value) = text.split_once(' ').ok_or(error(format!("expected a setting or an event, got '{}'", text)))?;
+            match key{
+
+                "rom" => {
+
+                    movie.rom_hash = u64::from_str_radix(value, 16).map_err(|_| error(format!("invalid ROM hash '{}'", value)))?;
+                    has_rom = true;
+                },
+                "seed" => movie.seed = number(value)?,
+                "cycles-per-frame" => movie.cycles_per_frame = number(value)?.try_into().map_err(|_| error(format!("cycles-per-frame {} is too large", value)))?,
+                "quirks" => for setting in value.split_whitespace(){
+
+                    let (name, value) = setting.split_once('=').ok_or(error(format!("expected <name>=<value>, got '{}'", setting)))?;
+                    movie.quirks.set(name, value).map_err(|e| error(e.to_string()))?;
+                },
+                "frames" => movie.frames = number(value)?,
+                _ => {
+
+                    let frame = number(key)?;
+                    let (action, key) = value.split_once(' ').ok_or(error(format!("expected <frame> press|release <key>, got '{}'", text)))?;
+                    let pressed = match action{
+
+                        "press" => true,
+                        "release" => false,
+                        _ => return Err(error(format!("unknown action '{}'", action))),
+                    };
+                    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or(error(format!("invalid key '{}'", key)))?;
+                    if movie.events.last().is_some_and(|last| last.frame > frame){
+
+                        return Err(error("events are out of order".to_string()));
+                    }
+                    movie.events.push(KeyEvent{ frame, key, pressed });
+                },
+            }
+        }
+
+        if !has_rom || movie.cycles_per_frame == 0{
+
+            return Err(MovieError::Parse{ line: 1, message: "missing the rom or cycles-per-frame setting".to_string() });
+        }
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {:016X}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "cycles-per-frame {}", self.cycles_per_frame)?;
+        let quirks: Vec<String> = QUIRK_NAMES.iter().map(|&name| format!("{}={}", name, self.quirks.get(name).unwrap_or_default())).collect();
+        writeln!(f, "quirks {}", quirks.join(" "))?;
+        writeln!(f, "frames {}", self.frames)?;
+        for event in self.events.iter(){
+
+            writeln!(f, "{} {} {:X}", event.frame, if event.pressed { "press" } else { "release" }, event.key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays a movie's events back into a machine set up with `Movie::start`.
+pub struct Playback{
+
+    movie: Movie,
+    next: usize, // First event not applied yet
+}
+
+impl Playback{
+
+    pub fn new(movie: Movie) -> Playback{
+
+        Playback{ movie, next: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie{
+
+        &self.movie
+    }
+
+    /// Presses and releases the keys due by the machine's current frame. Call it at the
+    /// start of every frame, before the frame's first step.
+    pub fn apply(&mut self, machine: &mut Chip8){
+
+        while let Some(event) = self.movie.events.get(self.next)
+            && event.frame <= machine.frame(){
+
+            let keypad = machine.keypad_mut();
+            if event.pressed { keypad.press(event.key as usize) } else { keypad.release(event.key as usize) };
+            self.next += 1;
+        }
+    }
+
+    // After rewinding, the events of later frames have to be played again
+    pub fn seek(&mut self, frame: u64){
+
+        self.next = self.movie.events.partition_point(|event| event.frame <= frame);
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool{
+
+        frame >= self.movie.frames
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+    use crate::quirks::LoadStore;
+
+    const TEXT: &str = "RustChip movie 2\n\
+                        rom 5C4F1E3A0B2D7788\n\
+                        seed 1234\n\
+                        cycles-per-frame 12\n\
+                        quirks shift=on load-store=x vf-reset=on jump=off clip=on display-wait=off\n\
+                        frames 600\n\
+                        120 press 5\n\
+                        120 press A\n\
+                        134 release 5\n";
+
+    fn parse_error(body: &str) -> String{
+
+        let text = format!("{}\nrom 0123456789ABCDEF\ncycles-per-frame 10\n{}", HEADER, body);
+        Movie::parse(&text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_and_display_round_trip(){
+
+        let movie = Movie::parse(TEXT).unwrap();
+        assert_eq!(movie.rom_hash, 0x5C4F1E3A0B2D7788);
+        assert_eq!(movie.seed, 1234);
+        assert_eq!(movie.cycles_per_frame, 12);
+        assert_eq!(movie.quirks, Quirks{ shift: true, load_store: LoadStore::IncrementByX, vf_reset: true, jump: false, clip: true, display_wait: false });
+        assert_eq!(movie.frames, 600);
+        assert_eq!(movie.events, [
+            KeyEvent{ frame: 120, key: 5, pressed: true },
+            KeyEvent{ frame: 120, key: 0xA, pressed: true },
+            KeyEvent{ frame: 134, key: 5, pressed: false },
+        ]);
+        assert_eq!(movie.to_string(), TEXT);
+        assert_eq!(Movie::parse(&movie.to_string()).unwrap(), movie);
+    }
+
+    #[test]
+    fn rejects_a_malformed_header(){
+
+        let error = |text: &str| Movie::parse(text).unwrap_err().to_string();
+        assert_eq!(error(""), "line 1: expected 'RustChip movie 2'");
+        assert_eq!(error(&TEXT.replace("movie 2", "movie 1")), "line 1: expected 'RustChip movie 2'");
+        assert_eq!(error(&TEXT.replace("rom 5C4F1E3A0B2D7788", "rom")), "line 2: expected a setting or an event, got 'rom'");
+        assert_eq!(error(&TEXT.replace("rom 5C4F1E3A0B2D7788\n", "")), "line 1: missing the rom or cycles-per-frame setting");
+        assert_eq!(parse_error("seed -1"), "line 4: invalid number '-1'");
+        assert_eq!(parse_error("cycles-per-frame 5000000000"), "line 4: cycles-per-frame 5000000000 is too large");
+        assert_eq!(parse_error("quirks shift"), "line 4: expected <name>=<value>, got 'shift'");
+    }
+
+    #[test]
+    fn rejects_a_malformed_event(){
+
+        assert_eq!(parse_error("\n12 hold 5"), "line 5: unknown action 'hold'");
+        assert_eq!(parse_error("12 press G"), "line 4: invalid key 'G'");
+        assert_eq!(parse_error("12 press 10"), "line 4: invalid key '10'");
+        assert_eq!(parse_error("12 press"), "line 4: expected <frame> press|release <key>, got '12 press'");
+        assert_eq!(parse_error("twelve press 1"), "line 4: invalid number 'twelve'");
+        assert_eq!(parse_error("12 press 1\n11 release 1"), "line 5: events are out of order");
+    }
+
+    #[test]
+    fn playback_seeks_past_the_events_already_played(){
+
+        let mut machine = Chip8::new();
+        machine.load_rom(&[0x12, 0x00]).unwrap();
+        let movie = Movie::parse(&format!("{}\nrom {:016X}\ncycles-per-frame 10\nframes 5\n0 press 1\n2 press 2\n4 press 3", HEADER, machine.rom_hash())).unwrap();
+        let mut playback = Playback::new(movie);
+        let pressed = |machine: &Chip8| (0..16).filter(|&key| machine.keypad().get_keys()[key]).collect::<Vec<_>>();
+
+        for _ in 0..3{
+
+            machine.run_frame().unwrap();
+        }
+        playback.seek(0);
+        playback.apply(&mut machine);
+        assert_eq!(pressed(&machine), [2]);
+
+        machine.keypad_mut().release(2);
+        playback.seek(2);
+        playback.apply(&mut machine);
+        assert!(pressed(&machine).is_empty());
+
+        machine.run_frame().unwrap();
+        playback.apply(&mut machine);
+        assert_eq!(pressed(&machine), [3]);
+
+        assert!(!playback.is_finished(4));
+        assert!(playback.is_finished(5));
+        assert!(playback.is_finished(6));
+    }
+}
@@ -0,0 +1,1218 @@
+//! Compiler for Octo, the high-level CHIP-8 assembly language:
+//!
+//! ```text
+//! :alias x v0
+//! :const SPEED 2
+//! : main
+//!     i := ball
+//!     loop
+//!         sprite x v1 4
+//!         x += SPEED
+//!         if x == 60 then x := 0
+//!         sprite x v1 4
+//!     again
+//! : ball 0x60 0xF0 0xF0 0x60
+//! ```
+//!
+//! Supported: labels, `:next`, `:alias`, `:const`, `:calc` (evaluated right to left, as in
+//! Octo), `:byte`, `:pointer`, `:org`, `:macro`, `:unpack`, `:call`, `:assert`, every
+//! statement of CHIP-8, SUPER-CHIP and XO-CHIP, `if ... then`, `if ... begin ... else ... end`
+//! and `loop ... while ... again`. The comparisons `< > <= >=` use VF as Octo does.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{chip8::PROGRAM_START, cpu::Cpu, disasm::Platform, opcodes};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError{
+
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for OctoError{}
+
+/// Compiles Octo source to a ROM for `target`, which decides the instructions and the
+/// amount of memory available.
+pub fn compile(source: &str, target: Platform) -> Result<Vec<u8>, OctoError>{
+
+    let mut compiler = Compiler::new(tokenize(source)?, target);
+    while compiler.position < compiler.tokens.len(){
+
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token{
+
+    text: String,
+    line: usize,
+}
+
+// Octo is whitespace separated, with # comments and quoted strings
+fn tokenize(source: &str) -> Result<Vec<Token>, OctoError>{
+
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate(){
+
+        let line = index + 1;
+        let mut chars = text.chars().peekable();
+        while let Some(&c) = chars.peek(){
+
+            if c.is_whitespace(){
+
+                chars.next();
+            }else if c == '#'{
+
+                break;
+            }else if c == '"'{
+
+                let mut text = String::from(chars.next().unwrap_or_default());
+                loop{
+
+                    match chars.next(){
+
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(OctoError{ line, message: "unterminated string".to_string() }),
+                    }
+                }
+                text.push('"');
+                tokens.push(Token{ text, line });
+            }else{
+
+                let mut text = String::new();
+                while let Some(&c) = chars.peek(){
+
+                    if c.is_whitespace(){
+
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token{ text, line });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison{
+
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison{
+
+    fn negate(self) -> Comparison{
+
+        match self{
+
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand{
+
+    Register(usize),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Condition{
+
+    register: usize,
+    comparison: Comparison,
+    operand: Operand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind{
+
+    Addr12,     // Low 12 bits of the opcode at the address
+    Addr16,     // Both bytes at the address
+    UnpackHigh, // Low nibble of the byte at the address gets the top nibble of the label
+    UnpackLow,  // Byte at the address gets the low byte of the label
+}
+
+struct Fixup{
+
+    addr: usize,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+struct Macro{
+
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct Loop{
+
+    start: u16,
+    breaks: Vec<u16>, // Jumps out of the loop from `while`, patched at `again`
+}
+
+struct Compiler{
+
+    tokens: Vec<Token>,
+    position: usize,
+    line: usize,
+    target: Platform,
+
+    rom: Vec<u8>, // From PROGRAM_START
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<Loop>,
+    branches: Vec<u16>, // Pending jumps of `if ... begin` and `else`, patched at `else` or `end`
+    start_written: Option<usize>, // Line that put the program's own bytes in the jump slot at 0x200
+}
+
+impl Compiler{
+
+    fn new(tokens: Vec<Token>, target: Platform) -> Compiler{
+
+        let mut compiler = Compiler{
+            tokens, position: 0, line: 1, target,
+            rom: Vec::new(), here: PROGRAM_START as usize,
+            labels: HashMap::new(), constants: HashMap::new(), aliases: HashMap::new(), macros: HashMap::new(),
+            fixups: Vec::new(), loops: Vec::new(), branches: Vec::new(), start_written: None,
+        };
+        // Room for the jump to main, filled in by finish
+        compiler.rom.extend_from_slice(&[0, 0]);
+        compiler.here += 2;
+        compiler
+    }
+
+    fn error(&self, message: impl Into<String>) -> OctoError{
+
+        OctoError{ line: self.line, message: message.into() }
+    }
+
+    fn memory_size(&self) -> usize{
+
+        if self.target == Platform::XoChip { 0x10000 } else { 0x1000 }
+    }
+
+    fn next(&mut self) -> Result<String, OctoError>{
+
+        let token = self.tokens.get(self.position).ok_or(self.error("unexpected end of file"))?;
+        self.line = token.line;
+        self.position += 1;
+        Ok(token.text.clone())
+    }
+
+    fn peek(&self) -> Option<&str>{
+
+        self.tokens.get(self.position).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), OctoError>{
+
+        let token = self.next()?;
+        if token != expected{
+
+            return Err(self.error(format!("expected '{}', found '{}'", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError>{
+
+        let token = self.next()?;
+        if let Some(register) = self.register(&token){
+
+            return self.assignment(register);
+        }
+        if let Some(value) = parse_number(&token){
+
+            return self.emit_byte_value(value);
+        }
+
+        match token.as_str(){
+
+            ":" => {
+
+                let name = self.next()?;
+                self.define_label(name)
+            },
+            ":next" => {
+
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name, (self.here + 1) as u16);
+                Ok(())
+            },
+            ":alias" => {
+
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let register = self.next()?;
+                let register = self.register(&register).ok_or(self.error(format!("'{}' is not a register", register)))?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":const" => {
+
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":calc" => {
+
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":byte" => {
+
+                let value = if self.peek() == Some("{") { self.calc_block()? } else { let token = self.next()?; self.value(&token)? };
+                self.emit_byte_value(value as i64)
+            },
+            ":pointer" => {
+
+                let target = self.next()?;
+                self.emit_address(&target, FixupKind::Addr16, 0)
+            },
+            ":org" => {
+
+                let addr = if self.peek() == Some("{") { self.calc_block()? } else { let token = self.next()?; self.value(&token)? } as i64;
+                if addr < PROGRAM_START as i64 || addr >= self.memory_size() as i64{
+
+                    return Err(self.error(format!(":org 0x{:X} is outside program memory", addr)));
+                }
+                self.here = addr as usize;
+                Ok(())
+            },
+            ":macro" => self.define_macro(),
+            ":call" => {
+
+                let target = self.next()?;
+                self.emit_address(&target, FixupKind::Addr12, 0x2000)
+            },
+            ":unpack" => {
+
+                let nibble = self.next()?;
+                let nibble = self.value(&nibble)? as i64;
+                let target = self.next()?;
+                let start = self.here;
+                self.emit_op(0x6000 | ((nibble as u16 & 0xF) << 4))?;
+                self.emit_op(0x6100)?;
+                match self.labels.get(&target).copied().or_else(|| self.constants.get(&target).map(|&value| value as u16)){
+
+                    Some(addr) => {
+
+                        self.rom[start + 1 - PROGRAM_START as usize] |= (addr >> 8) as u8 & 0xF;
+                        self.rom[start + 3 - PROGRAM_START as usize] = addr as u8;
+                    },
+                    None => {
+
+                        self.fixups.push(Fixup{ addr: start + 1, name: target.clone(), kind: FixupKind::UnpackHigh, line: self.line });
+                        self.fixups.push(Fixup{ addr: start + 3, name: target, kind: FixupKind::UnpackLow, line: self.line });
+                    }
+                }
+                Ok(())
+            },
+            ":assert" => {
+
+                let message = match self.peek(){
+
+                    Some(text) if text.starts_with('"') => { let text = self.next()?; text.trim_matches('"').to_string() },
+                    _ => "assertion failed".to_string(),
+                };
+                if self.calc_block()? == 0.0{
+
+                    return Err(self.error(message));
+                }
+                Ok(())
+            },
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+
+                self.next()?;
+                self.next().map(|_| ())
+            },
+            ";" | "return" => self.emit_op(0x00EE),
+            "clear" => self.emit_op(0x00E0),
+            "hires" => self.emit_op(0x00FF),
+            "lores" => self.emit_op(0x00FE),
+            "exit" => self.emit_op(0x00FD),
+            "scroll-left" => self.emit_op(0x00FC),
+            "scroll-right" => self.emit_op(0x00FB),
+            "scroll-down" => {
+
+                let n = self.nibble()?;
+                self.emit_op(0x00C0 | n)
+            },
+            "scroll-up" => {
+
+                let n = self.nibble()?;
+                self.emit_op(0x00D0 | n)
+            },
+            "audio" => self.emit_op(0xF002),
+            "plane" => {
+
+                let n = self.nibble()?;
+                self.emit_op(0xF001 | n << 8)
+            },
+            "bcd" => self.register_op(0xF033),
+            "saveflags" => self.register_op(0xF075),
+            "loadflags" => self.register_op(0xF085),
+            "save" | "load" => {
+
+                let x = self.expect_register()?;
+                if self.peek() == Some("-"){
+
+                    self.next()?;
+                    let y = self.expect_register()?;
+                    let op = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit_op(op | (x as u16) << 8 | (y as u16) << 4)
+                }else{
+
+                    let op = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.emit_op(op | (x as u16) << 8)
+                }
+            },
+            "sprite" => {
+
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble()?;
+                self.emit_op(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)
+            },
+            "jump" => {
+
+                let target = self.next()?;
+                self.emit_address(&target, FixupKind::Addr12, 0x1000)
+            },
+            "jump0" => {
+
+                let target = self.next()?;
+                self.emit_address(&target, FixupKind::Addr12, 0xB000)
+            },
+            "native" => {
+
+                let target = self.next()?;
+                self.emit_address(&target, FixupKind::Addr12, 0x0000)
+            },
+            "i" => self.assign_i(),
+            "delay" | "buzzer" | "pitch" => {
+
+                self.expect(":=")?;
+                let op = match token.as_str(){
+
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_op(op)
+            },
+            "loop" => {
+
+                self.loops.push(Loop{ start: self.here as u16, breaks: Vec::new() });
+                Ok(())
+            },
+            "while" => {
+
+                let condition = self.condition()?;
+                if self.loops.is_empty(){
+
+                    return Err(self.error("'while' outside of a loop"));
+                }
+                self.emit_skip_unless(Condition{ comparison: condition.comparison.negate(), ..condition })?;
+                let jump = self.here as u16;
+                self.emit_op(0x1000)?;
+                if let Some(current) = self.loops.last_mut(){
+
+                    current.breaks.push(jump);
+                }
+                Ok(())
+            },
+            "again" => {
+
+                let current = self.loops.pop().ok_or(self.error("'again' without 'loop'"))?;
+                self.emit_op(0x1000 | current.start)?;
+                for jump in current.breaks{
+
+                    self.patch_jump(jump, self.here as u16)?;
+                }
+                Ok(())
+            },
+            "if" => {
+
+                let condition = self.condition()?;
+                match self.next()?.as_str(){
+
+                    "then" => self.emit_skip_unless(condition),
+                    "begin" => {
+
+                        self.emit_skip_unless(Condition{ comparison: condition.comparison.negate(), ..condition })?;
+                        self.branches.push(self.here as u16);
+                        self.emit_op(0x1000)
+                    },
+                    other => Err(self.error(format!("expected 'then' or 'begin', found '{}'", other))),
+                }
+            },
+            "else" => {
+
+                let skip = self.branches.pop().ok_or(self.error("'else' without 'if ... begin'"))?;
+                self.branches.push(self.here as u16);
+                self.emit_op(0x1000)?;
+                self.patch_jump(skip, self.here as u16)
+            },
+            "end" => {
+
+                let jump = self.branches.pop().ok_or(self.error("'end' without 'if ... begin'"))?;
+                self.patch_jump(jump, self.here as u16)
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token),
+            _ if token.starts_with(':') || token.starts_with('"') => Err(self.error(format!("unknown or unsupported directive '{}'", token))),
+            // Anything else is a subroutine call by name
+            _ => self.emit_address(&token, FixupKind::Addr12, 0x2000),
+        }
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), OctoError>{
+
+        if parse_number(name).is_some() || self.register(name).is_some() || name.starts_with(':') || name.starts_with('"'){
+
+            return Err(self.error(format!("'{}' can't be used as a name", name)));
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), OctoError>{
+
+        self.check_name(&name)?;
+        if self.labels.contains_key(&name){
+
+            return Err(self.error(format!("the label '{}' is defined more than once", name)));
+        }
+        self.labels.insert(name, self.here as u16);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError>{
+
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop{
+
+            let token = self.next()?;
+            if token == "{"{
+
+                break;
+            }
+            params.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop{
+
+            let token = self.tokens.get(self.position).cloned().ok_or(self.error(format!("macro '{}' is missing its closing '}}'", name)))?;
+            self.position += 1;
+            match token.text.as_str(){
+
+                "{" => depth += 1,
+                "}" => {
+
+                    depth -= 1;
+                    if depth == 0{
+
+                        break;
+                    }
+                },
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro{ params, body, calls: 0 });
+        Ok(())
+    }
+
+    // Splices the macro body, with its parameters substituted, in place of the call
+    fn expand_macro(&mut self, name: &str) -> Result<(), OctoError>{
+
+        let param_count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for index in 0..param_count{
+
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[index].clone(), arg);
+        }
+        let line = self.line;
+        let Some(definition) = self.macros.get_mut(name) else { return Ok(()) };
+        let calls = definition.calls.to_string();
+        definition.calls += 1;
+        let expansion: Vec<Token> = definition.body.iter().map(|token| {
+
+            let text = match token.text.as_str(){
+
+                "CALLS" => calls.clone(),
+                text => args.get(text).cloned().unwrap_or_else(|| text.to_string()),
+            };
+            Token{ text, line }
+        }).collect();
+        if self.tokens.len() + expansion.len() > 1_000_000{
+
+            return Err(self.error(format!("macro '{}' expands forever", name)));
+        }
+        self.tokens.splice(self.position..self.position, expansion);
+        Ok(())
+    }
+
+    fn register(&self, token: &str) -> Option<usize>{
+
+        if let Some(&register) = self.aliases.get(token){
+
+            return Some(register);
+        }
+        opcodes::parse_register(token)
+    }
+
+    fn expect_register(&mut self) -> Result<usize, OctoError>{
+
+        let token = self.next()?;
+        self.register(&token).ok_or(self.error(format!("expected a register, found '{}'", token)))
+    }
+
+    // A number, constant or already defined label
+    fn value(&self, token: &str) -> Result<f64, OctoError>{
+
+        if let Some(value) = parse_number(token){
+
+            return Ok(value as f64);
+        }
+        if let Some(&value) = self.constants.get(token){
+
+            return Ok(value);
+        }
+        if let Some(&addr) = self.labels.get(token){
+
+            return Ok(addr as f64);
+        }
+        Err(self.error(format!("undefined name '{}'", token)))
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError>{
+
+        let token = self.next()?;
+        let value = self.value(&token)? as i64;
+        if !(-128..=255).contains(&value){
+
+            return Err(self.error(format!("{} doesn't fit in a byte", token)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, OctoError>{
+
+        let token = self.next()?;
+        let value = self.value(&token)? as i64;
+        if !(0..=15).contains(&value){
+
+            return Err(self.error(format!("{} doesn't fit in a nibble", token)));
+        }
+        Ok(value as u16)
+    }
+
+    fn register_op(&mut self, op: u16) -> Result<(), OctoError>{
+
+        let x = self.expect_register()?;
+        self.emit_op(op | (x as u16) << 8)
+    }
+
+    fn assignment(&mut self, x: usize) -> Result<(), OctoError>{
+
+        let operator = self.next()?;
+        let rhs = self.next()?;
+        let x_bits = (x as u16) << 8;
+
+        if let Some(y) = self.register(&rhs){
+
+            let y_bits = (y as u16) << 4;
+            let op = match operator.as_str(){
+
+                ":=" => 0x8000,
+                "|=" => 0x8001,
+                "&=" => 0x8002,
+                "^=" => 0x8003,
+                "+=" => 0x8004,
+                "-=" => 0x8005,
+                ">>=" => 0x8006,
+                "=-" => 0x8007,
+                "<<=" => 0x800E,
+                _ => return Err(self.error(format!("'{}' can't be used between registers", operator))),
+            };
+            return self.emit_op(op | x_bits | y_bits);
+        }
+
+        match (operator.as_str(), rhs.as_str()){
+
+            (":=", "random") => {
+
+                let mask = self.byte()?;
+                self.emit_op(0xC000 | x_bits | mask as u16)
+            },
+            (":=", "key") => self.emit_op(0xF00A | x_bits),
+            (":=", "delay") => self.emit_op(0xF007 | x_bits),
+            (":=", _) | ("+=", _) | ("-=", _) => {
+
+                self.position -= 1;
+                let value = self.byte()?;
+                match operator.as_str(){
+
+                    ":=" => self.emit_op(0x6000 | x_bits | value as u16),
+                    "+=" => self.emit_op(0x7000 | x_bits | value as u16),
+                    _ => self.emit_op(0x7000 | x_bits | value.wrapping_neg() as u16),
+                }
+            },
+            _ => Err(self.error(format!("'{}' needs a register on the right", operator))),
+        }
+    }
+
+    fn assign_i(&mut self) -> Result<(), OctoError>{
+
+        let operator = self.next()?;
+        match operator.as_str(){
+
+            "+=" => self.register_op(0xF01E),
+            ":=" => {
+
+                let rhs = self.next()?;
+                match rhs.as_str(){
+
+                    "hex" => self.register_op(0xF029),
+                    "bighex" => self.register_op(0xF030),
+                    "long" => {
+
+                        let target = self.next()?;
+                        self.emit_op(0xF000)?;
+                        self.emit_address(&target, FixupKind::Addr16, 0)
+                    },
+                    _ => self.emit_address(&rhs, FixupKind::Addr12, 0xA000),
+                }
+            },
+            _ => Err(self.error(format!("expected ':=' or '+=' after i, found '{}'", operator))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError>{
+
+        let register = self.expect_register()?;
+        let operator = self.next()?;
+        let comparison = match operator.as_str(){
+
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => return Ok(Condition{ register, comparison: Comparison::Key, operand: Operand::Byte(0) }),
+            "-key" => return Ok(Condition{ register, comparison: Comparison::NotKey, operand: Operand::Byte(0) }),
+            _ => return Err(self.error(format!("'{}' is not a comparison", operator))),
+        };
+        let rhs = self.next()?;
+        let operand = match self.register(&rhs){
+
+            Some(y) => Operand::Register(y),
+            None => {
+
+                self.position -= 1;
+                Operand::Byte(self.byte()?)
+            }
+        };
+        Ok(Condition{ register, comparison, operand })
+    }
+
+    // Emits code that skips the next instruction unless the condition holds
+    fn emit_skip_unless(&mut self, condition: Condition) -> Result<(), OctoError>{
+
+        let x_bits = (condition.register as u16) << 8;
+        match (condition.comparison, condition.operand){
+
+            (Comparison::Equal, Operand::Byte(byte)) => self.emit_op(0x4000 | x_bits | byte as u16),
+            (Comparison::Equal, Operand::Register(y)) => self.emit_op(0x9000 | x_bits | (y as u16) << 4),
+            (Comparison::NotEqual, Operand::Byte(byte)) => self.emit_op(0x3000 | x_bits | byte as u16),
+            (Comparison::NotEqual, Operand::Register(y)) => self.emit_op(0x5000 | x_bits | (y as u16) << 4),
+            (Comparison::Key, _) => self.emit_op(0xE0A1 | x_bits),
+            (Comparison::NotKey, _) => self.emit_op(0xE09E | x_bits),
+            (comparison, operand) => {
+
+                // VF := rhs, then subtract in the direction whose borrow flag answers the question
+                match operand{
+
+                    Operand::Byte(byte) => self.emit_op(0x6F00 | byte as u16)?,
+                    Operand::Register(y) => self.emit_op(0x8F00 | (y as u16) << 4)?,
+                }
+                let (subtract, holds_when) = match comparison{
+
+                    Comparison::Greater => (0x8F05, 0),        // VF = rhs >= x
+                    Comparison::LessOrEqual => (0x8F05, 1),
+                    Comparison::Less => (0x8F07, 0),           // VF = x >= rhs
+                    _ => (0x8F07, 1),                          // GreaterOrEqual
+                };
+                self.emit_op(subtract | (condition.register as u16) << 4)?;
+                self.emit_op(0x4F00 | holds_when)
+            }
+        }
+    }
+
+    fn calc_block(&mut self) -> Result<f64, OctoError>{
+
+        self.expect("{")?;
+        let start = self.position;
+        let mut depth = 1;
+        while depth > 0{
+
+            match self.next()?.as_str(){
+
+                "{" | "(" => depth += 1,
+                "}" | ")" => depth -= 1,
+                _ => {}
+            }
+        }
+        let tokens: Vec<String> = self.tokens[start..self.position - 1].iter().map(|token| token.text.clone()).collect();
+        let mut calc = Calc{ tokens: &tokens, position: 0, compiler: self };
+        let value = calc.expression()?;
+        if calc.position < tokens.len(){
+
+            return Err(self.error(format!("unexpected '{}' in calculation", tokens[calc.position])));
+        }
+        Ok(value)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), OctoError>{
+
+        if self.here >= self.memory_size(){
+
+            return Err(self.error(format!("the program doesn't fit in the {} bytes of memory", self.memory_size())));
+        }
+        let index = self.here - PROGRAM_START as usize;
+        if index < 2{
+
+            self.start_written.get_or_insert(self.line);
+        }
+        if index >= self.rom.len(){
+
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_byte_value(&mut self, value: i64) -> Result<(), OctoError>{
+
+        if !(-128..=255).contains(&value){
+
+            return Err(self.error(format!("{} doesn't fit in a byte", value)));
+        }
+        self.write_byte(value as u8)
+    }
+
+    // Checks the instruction exists on the target before writing it
+    fn emit_op(&mut self, op: u16) -> Result<(), OctoError>{
+
+        let platform = Cpu::decode_instruction_pair(op, 0).platform();
+        if platform > self.target{
+
+            return Err(self.error(format!("0x{:04X} needs the {} target", op, platform.name())));
+        }
+        self.write_byte((op >> 8) as u8)?;
+        self.write_byte(op as u8)
+    }
+
+    // Writes op with an address that may not be defined yet, patched by finish if so
+    fn emit_address(&mut self, target: &str, kind: FixupKind, op: u16) -> Result<(), OctoError>{
+
+        let addr = self.here;
+        if kind == FixupKind::Addr16{
+
+            if op != 0{
+
+                self.emit_op(op)?;
+            }
+            let addr = self.here;
+            self.write_byte(0)?;
+            self.write_byte(0)?;
+            return self.resolve_or_defer(target, addr, kind);
+        }
+        self.emit_op(op)?;
+        self.resolve_or_defer(target, addr, kind)
+    }
+
+    fn resolve_or_defer(&mut self, target: &str, addr: usize, kind: FixupKind) -> Result<(), OctoError>{
+
+        let known = if parse_number(target).is_some() || self.constants.contains_key(target) || self.labels.contains_key(target){
+
+            Some(self.value(target)? as i64)
+        }else{
+
+            None
+        };
+        match known{
+
+            Some(value) => self.patch(addr, value, kind, target),
+            None if self.register(target).is_some() => Err(self.error(format!("expected an address, found the register '{}'", target))),
+            None => {
+
+                self.fixups.push(Fixup{ addr, name: target.to_string(), kind, line: self.line });
+                Ok(())
+            }
+        }
+    }
+
+    fn patch(&mut self, addr: usize, value: i64, kind: FixupKind, name: &str) -> Result<(), OctoError>{
+
+        let index = addr - PROGRAM_START as usize;
+        match kind{
+
+            FixupKind::Addr12 => {
+
+                if !(0..=0xFFF).contains(&value){
+
+                    return Err(self.error(format!("'{}' = 0x{:X} is beyond the 12-bit address range", name, value)));
+                }
+                self.rom[index] = (self.rom[index] & 0xF0) | (value >> 8) as u8;
+                self.rom[index + 1] = value as u8;
+            },
+            FixupKind::Addr16 => {
+
+                self.rom[index] = (value >> 8) as u8;
+                self.rom[index + 1] = value as u8;
+            },
+            FixupKind::UnpackHigh => self.rom[index] |= (value >> 8) as u8 & 0xF,
+            FixupKind::UnpackLow => self.rom[index] = value as u8,
+        }
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, jump: u16, target: u16) -> Result<(), OctoError>{
+
+        self.patch(jump as usize, target as i64, FixupKind::Addr12, "jump")
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, OctoError>{
+
+        if !self.loops.is_empty(){
+
+            return Err(self.error("'loop' without 'again'"));
+        }
+        if !self.branches.is_empty(){
+
+            return Err(self.error("'if ... begin' without 'end'"));
+        }
+        for fixup in std::mem::take(&mut self.fixups){
+
+            self.line = fixup.line;
+            let value = *self.labels.get(&fixup.name).ok_or(self.error(format!("undefined name '{}'", fixup.name)))?;
+            self.patch(fixup.addr, value as i64, fixup.kind, &fixup.name)?;
+        }
+        let main = *self.labels.get("main").ok_or(OctoError{ line: 1, message: "the program has no ': main' label".to_string() })?;
+        // A main placed at 0x200 with :org starts the program itself
+        if main != PROGRAM_START{
+
+            if let Some(line) = self.start_written{
+
+                return Err(OctoError{ line, message: "main must be at 0x200 when :org 0x200 is used".to_string() });
+            }
+            self.patch(PROGRAM_START as usize, 0x1000, FixupKind::Addr16, "main")?;
+            self.patch(PROGRAM_START as usize, main as i64, FixupKind::Addr12, "main")?;
+        }
+        Ok(self.rom)
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64>{
+
+    let (negative, digits) = match token.strip_prefix('-'){
+
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")){
+
+        i64::from_str_radix(hex, 16).ok()?
+    }else if let Some(binary) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")){
+
+        i64::from_str_radix(binary, 2).ok()?
+    }else if digits.starts_with(|c: char| c.is_ascii_digit()){
+
+        digits.parse().ok()?
+    }else{
+
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// :calc expressions have no precedence, they are evaluated right to left
+struct Calc<'a>{
+
+    tokens: &'a [String],
+    position: usize,
+    compiler: &'a Compiler,
+}
+
+impl Calc<'_>{
+
+    fn next(&mut self) -> Result<&str, OctoError>{
+
+        let token = self.tokens.get(self.position).ok_or(self.compiler.error("calculation ends too early"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> Result<f64, OctoError>{
+
+        let left = self.term()?;
+        let Some(operator) = self.tokens.get(self.position).cloned() else { return Ok(left) };
+        let binary: fn(f64, f64) -> f64 = match operator.as_str(){
+
+            "+" => |a, b| a + b,
+            "-" => |a, b| a - b,
+            "*" => |a, b| a * b,
+            "/" => |a, b| a / b,
+            "%" => |a, b| a % b,
+            "&" => |a, b| (a as i64 & b as i64) as f64,
+            "|" => |a, b| (a as i64 | b as i64) as f64,
+            "^" => |a, b| (a as i64 ^ b as i64) as f64,
+            "<<" => |a, b| ((a as i64) << (b as i64 & 63)) as f64,
+            ">>" => |a, b| ((a as i64) >> (b as i64 & 63)) as f64,
+            "pow" => f64::powf,
+            "min" => f64::min,
+            "max" => f64::max,
+            "<" => |a, b| (a < b) as i64 as f64,
+            ">" => |a, b| (a > b) as i64 as f64,
+            "<=" => |a, b| (a <= b) as i64 as f64,
+            ">=" => |a, b| (a >= b) as i64 as f64,
+            "==" => |a, b| (a == b) as i64 as f64,
+            "!=" => |a, b| (a != b) as i64 as f64,
+            ")" | "}" => return Ok(left),
+            other => return Err(self.compiler.error(format!("'{}' is not a calc operator", other))),
+        };
+        self.position += 1;
+        let right = self.expression()?;
+        Ok(binary(left, right))
+    }
+
+    fn term(&mut self) -> Result<f64, OctoError>{
+
+        let token = self.next()?.to_string();
+        let unary: Option<fn(f64) -> f64> = match token.as_str(){
+
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(unary) = unary{
+
+            return Ok(unary(self.term()?));
+        }
+        match token.as_str(){
+
+            "(" => {
+
+                let value = self.expression()?;
+                match self.next()?.to_string().as_str(){
+
+                    ")" => Ok(value),
+                    other => Err(self.compiler.error(format!("expected ')', found '{}'", other))),
+                }
+            },
+            "@" => {
+
+                // Reads a byte already compiled into the ROM
+                let addr = self.term()? as usize;
+                Ok(addr.checked_sub(PROGRAM_START as usize).and_then(|index| self.compiler.rom.get(index)).copied().unwrap_or(0) as f64)
+            },
+            "HERE" => Ok(self.compiler.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self.compiler.value(&token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    fn build(source: &str) -> Vec<u8>{
+
+        compile(source, Platform::XoChip).unwrap()
+    }
+
+    fn error(source: &str) -> String{
+
+        compile(source, Platform::XoChip).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn main_gets_a_jump_unless_it_is_at_0x200(){
+
+        assert_eq!(build(": main\n clear"), [0x12, 0x02, 0x00, 0xE0]);
+        assert_eq!(build(": draw\n clear\n ;\n: main\n draw"), [0x12, 0x06, 0x00, 0xE0, 0x00, 0xEE, 0x22, 0x02]);
+        assert_eq!(build(":org 0x200\n: main\n clear"), [0x00, 0xE0]);
+        assert_eq!(error(":org 0x200\n0xAA 0xBB\n: main\n clear"), "line 2: main must be at 0x200 when :org 0x200 is used");
+        assert_eq!(error(":org 0x201\n0xAA\n: main\n clear"), "line 2: main must be at 0x200 when :org 0x200 is used");
+        assert_eq!(error(": draw\n clear"), "line 1: the program has no ': main' label");
+    }
+
+    #[test]
+    fn if_then(){
+
+        assert_eq!(build(": main\n if v1 == 5 then v2 := 1\n if v1 != v3 then v2 := 2"),
+            [0x12, 0x02, 0x41, 0x05, 0x62, 0x01, 0x51, 0x30, 0x62, 0x02]);
+    }
+
+    #[test]
+    fn if_begin_else_end(){
+
+        assert_eq!(build(": main\n if v0 == 1 begin\n v1 := 2\n else\n v1 := 3\n end\n clear"),
+            [0x12, 0x02, 0x30, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x12, 0x0C, 0x61, 0x03, 0x00, 0xE0]);
+        assert_eq!(error(": main\n if v0 == 1 begin\n clear"), "line 3: 'if ... begin' without 'end'");
+        assert_eq!(error(": main\n else"), "line 2: 'else' without 'if ... begin'");
+    }
+
+    #[test]
+    fn loop_while_again(){
+
+        assert_eq!(build(": main\n loop\n v0 += 1\n while v0 != 10\n v1 := v0\n again\n clear"),
+            [0x12, 0x02, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0C, 0x81, 0x00, 0x12, 0x02, 0x00, 0xE0]);
+        assert_eq!(error(": main\n loop\n clear"), "line 3: 'loop' without 'again'");
+        assert_eq!(error(": main\n again"), "line 2: 'again' without 'loop'");
+    }
+
+    #[test]
+    fn ordered_comparisons_go_through_vf(){
+
+        assert_eq!(build(": main\n if v1 < 5 then clear\n if v1 > 5 then clear\n if v1 <= 5 then clear\n if v1 >= v3 then clear"), [
+            0x12, 0x02,
+            0x6F, 0x05, 0x8F, 0x17, 0x4F, 0x00, 0x00, 0xE0,
+            0x6F, 0x05, 0x8F, 0x15, 0x4F, 0x00, 0x00, 0xE0,
+            0x6F, 0x05, 0x8F, 0x15, 0x4F, 0x01, 0x00, 0xE0,
+            0x8F, 0x30, 0x8F, 0x17, 0x4F, 0x01, 0x00, 0xE0,
+        ]);
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left(){
+
+        let source = ":calc DIFF { 10 - 4 - 3 }\n:calc MIXED { 2 * 3 + 1 }\n:calc GROUPED { ( 2 * 3 ) + 1 }\n: main\n v0 := DIFF\n v1 := MIXED\n v2 := GROUPED";
+        assert_eq!(build(source), [0x12, 0x02, 0x60, 0x09, 0x61, 0x08, 0x62, 0x07]);
+    }
+
+    #[test]
+    fn macros(){
+
+        assert_eq!(build(":macro twice reg { reg += 1 reg += 1 }\n: main\n twice v3\n twice v4"),
+            [0x12, 0x02, 0x73, 0x01, 0x73, 0x01, 0x74, 0x01, 0x74, 0x01]);
+        assert_eq!(build(":macro count { :byte CALLS }\n: main\n count count"), [0x12, 0x02, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn aliases_and_constants(){
+
+        assert_eq!(build(":alias x v5\n:const SPEED 3\n: main\n x += SPEED\n x := v6"), [0x12, 0x02, 0x75, 0x03, 0x85, 0x60]);
+    }
+
+    #[test]
+    fn forward_references_are_fixed_up(){
+
+        assert_eq!(build(": main\n jump later\n i := sprite\n: later\n clear\n: sprite 0xF0"),
+            [0x12, 0x02, 0x12, 0x06, 0xA2, 0x08, 0x00, 0xE0, 0xF0]);
+        assert_eq!(build(": main\n i := long data\n:pointer data\n: data 0xAA"),
+            [0x12, 0x02, 0xF0, 0x00, 0x02, 0x08, 0x02, 0x08, 0xAA]);
+    }
+
+    #[test]
+    fn unpack_next_and_org(){
+
+        assert_eq!(build(": main\n :unpack 0xA data\n: data 0x12"), [0x12, 0x02, 0x60, 0xA2, 0x61, 0x06, 0x12]);
+        assert_eq!(build(": data 0x12\n: main\n :unpack 0xA data"), [0x12, 0x03, 0x12, 0x60, 0xA2, 0x61, 0x02]);
+        assert_eq!(build(": main\n:next target v0 := 7\n i := target"), [0x12, 0x02, 0x60, 0x07, 0xA2, 0x03]);
+
+        let rom = build(": main\n jump table\n:org 0x300\n: table clear");
+        assert_eq!(rom.len(), 0x102);
+        assert_eq!(rom[..4], [0x12, 0x02, 0x13, 0x00]);
+        assert!(rom[4..0x100].iter().all(|&byte| byte == 0));
+        assert_eq!(rom[0x100..], [0x00, 0xE0]);
+        assert_eq!(error(": main\n:org 0x100"), "line 2: :org 0x100 is outside program memory");
+    }
+
+    #[test]
+    fn errors_name_the_line(){
+
+        assert_eq!(error(": main\n clear\n jump nowhere"), "line 3: undefined name 'nowhere'");
+        assert_eq!(error(": main\n v0 := SPEED"), "line 2: undefined name 'SPEED'");
+        assert_eq!(error(": main\n clear\n sprite v0 vg 5"), "line 3: expected a register, found 'vg'");
+        assert_eq!(error(":alias x q1\n: main"), "line 1: 'q1' is not a register");
+        assert_eq!(error(": main\n jump v3"), "line 2: expected an address, found the register 'v3'");
+        assert_eq!(error(": main\n: main"), "line 2: the label 'main' is defined more than once");
+    }
+
+    #[test]
+    fn failed_asserts(){
+
+        // Right to left, so this is 0 == (N % 2)
+        assert_eq!(error(":const N 3\n:assert \"N must be even\" { 0 == N % 2 }\n: main"), "line 2: N must be even");
+        assert_eq!(error(": main\n clear\n:assert { 1 - 1 }"), "line 3: assertion failed");
+        assert_eq!(build(":const N 4\n:assert \"N must be even\" { 0 == N % 2 }\n: main"), [0x12, 0x02]);
+    }
+}
@@ -0,0 +1,133 @@
+//! The opcode table. `Cpu::decode_instruction` and the assembler both work from it, so
+//! whatever the assembler writes decodes back to the instruction it was written as.
+
+use crate::cpu::{Cpu, Instruction};
+
+// How an operand is written in Cowgod syntax and where it goes in the opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand{
+
+    Vx,                  // Register in the X nibble
+    Vy,                  // Register in the Y nibble
+    Range,               // Vx-Vy, in the X and Y nibbles
+    Byte,                // NN
+    Addr,                // NNN
+    Nibble,              // N
+    Planes,              // A plane mask in the X nibble
+    Long,                // LONG NNNN, in the word after the opcode
+    Keyword(&'static str), // Written as is: I, [I], DT, K, ...
+}
+
+pub struct Opcode{
+
+    pub pattern: u16,
+    pub mask: u16, // Bits fixed by the pattern, the rest hold the operands
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    pub decode: fn(u16, u16) -> Instruction, // Opcode and the word after it
+}
+
+impl Opcode{
+
+    pub fn matches(&self, opcode: u16) -> bool{
+
+        opcode & self.mask == self.pattern
+    }
+
+    pub fn length(&self) -> u16{
+
+        if self.operands.contains(&Operand::Long) { 4 } else { 2 }
+    }
+}
+
+use Operand::{Addr, Byte, Keyword, Long, Nibble, Planes, Range, Vx, Vy};
+
+const I: Operand = Keyword("I");
+const AT_I: Operand = Keyword("[I]");
+
+// Patterns don't overlap, so the order only matters for the assembler, which takes the
+// first entry whose operands fit
+pub const OPCODES: &[Opcode] = &[
+
+    Opcode{ pattern: 0x00E0, mask: 0xFFFF, mnemonic: "CLS", operands: &[], decode: |_, _| Instruction::ClearDisplay },
+    Opcode{ pattern: 0x00EE, mask: 0xFFFF, mnemonic: "RET", operands: &[], decode: |_, _| Instruction::ReturnFromSubroutine },
+    Opcode{ pattern: 0x00C0, mask: 0xFFF0, mnemonic: "SCD", operands: &[Nibble], decode: |op, _| Instruction::ScrollDown(Cpu::get_nibble(op, 3)) },
+    Opcode{ pattern: 0x00D0, mask: 0xFFF0, mnemonic: "SCU", operands: &[Nibble], decode: |op, _| Instruction::ScrollUp(Cpu::get_nibble(op, 3)) },
+    Opcode{ pattern: 0x00FB, mask: 0xFFFF, mnemonic: "SCR", operands: &[], decode: |_, _| Instruction::ScrollRight },
+    Opcode{ pattern: 0x00FC, mask: 0xFFFF, mnemonic: "SCL", operands: &[], decode: |_, _| Instruction::ScrollLeft },
+    Opcode{ pattern: 0x00FD, mask: 0xFFFF, mnemonic: "EXIT", operands: &[], decode: |_, _| Instruction::Exit },
+    Opcode{ pattern: 0x00FE, mask: 0xFFFF, mnemonic: "LOW", operands: &[], decode: |_, _| Instruction::LowResolution },
+    Opcode{ pattern: 0x00FF, mask: 0xFFFF, mnemonic: "HIGH", operands: &[], decode: |_, _| Instruction::HighResolution },
+
+    Opcode{ pattern: 0x1000, mask: 0xF000, mnemonic: "JP", operands: &[Addr], decode: |op, _| Instruction::JumpToAddress(Cpu::get_nnn(op)) },
+    Opcode{ pattern: 0x2000, mask: 0xF000, mnemonic: "CALL", operands: &[Addr], decode: |op, _| Instruction::CallSubroutine(Cpu::get_nnn(op)) },
+    Opcode{ pattern: 0x3000, mask: 0xF000, mnemonic: "SE", operands: &[Vx, Byte], decode: |op, _| Instruction::SkipIfVxEqualsByte(Cpu::get_x(op), Cpu::get_nn(op)) },
+    Opcode{ pattern: 0x4000, mask: 0xF000, mnemonic: "SNE", operands: &[Vx, Byte], decode: |op, _| Instruction::SkipIfVxNotEqualsByte(Cpu::get_x(op), Cpu::get_nn(op)) },
+    Opcode{ pattern: 0x5000, mask: 0xF00F, mnemonic: "SE", operands: &[Vx, Vy], decode: |op, _| Instruction::SkipIfVxEqualsVy(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x5002, mask: 0xF00F, mnemonic: "LD", operands: &[AT_I, Range], decode: |op, _| Instruction::StoreRegistersVxThroughVyInMemory(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x5003, mask: 0xF00F, mnemonic: "LD", operands: &[Range, AT_I], decode: |op, _| Instruction::ReadRegistersVxThroughVyFromMemory(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x6000, mask: 0xF000, mnemonic: "LD", operands: &[Vx, Byte], decode: |op, _| Instruction::SetVxToByte(Cpu::get_x(op), Cpu::get_nn(op)) },
+    Opcode{ pattern: 0x7000, mask: 0xF000, mnemonic: "ADD", operands: &[Vx, Byte], decode: |op, _| Instruction::AddByteToVx(Cpu::get_x(op), Cpu::get_nn(op)) },
+
+    Opcode{ pattern: 0x8000, mask: 0xF00F, mnemonic: "LD", operands: &[Vx, Vy], decode: |op, _| Instruction::SetVxToVy(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8001, mask: 0xF00F, mnemonic: "OR", operands: &[Vx, Vy], decode: |op, _| Instruction::SetVxToVxOrVy(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8002, mask: 0xF00F, mnemonic: "AND", operands: &[Vx, Vy], decode: |op, _| Instruction::SetVxToVxAndVy(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8003, mask: 0xF00F, mnemonic: "XOR", operands: &[Vx, Vy], decode: |op, _| Instruction::SetVxToVxXorVy(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8004, mask: 0xF00F, mnemonic: "ADD", operands: &[Vx, Vy], decode: |op, _| Instruction::AddVyToVxWithCarry(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8005, mask: 0xF00F, mnemonic: "SUB", operands: &[Vx, Vy], decode: |op, _| Instruction::SubtractVyFromVxWithBorrow(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8006, mask: 0xF00F, mnemonic: "SHR", operands: &[Vx, Vy], decode: |op, _| Instruction::ShiftVxRightByOne(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x8007, mask: 0xF00F, mnemonic: "SUBN", operands: &[Vx, Vy], decode: |op, _| Instruction::SetVxToVyMinusVx(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x800E, mask: 0xF00F, mnemonic: "SHL", operands: &[Vx, Vy], decode: |op, _| Instruction::ShiftVxLeftByOne(Cpu::get_x(op), Cpu::get_y(op)) },
+    Opcode{ pattern: 0x9000, mask: 0xF00F, mnemonic: "SNE", operands: &[Vx, Vy], decode: |op, _| Instruction::SkipIfVxNotEqualsVy(Cpu::get_x(op), Cpu::get_y(op)) },
+
+    Opcode{ pattern: 0xA000, mask: 0xF000, mnemonic: "LD", operands: &[I, Addr], decode: |op, _| Instruction::SetIToAddress(Cpu::get_nnn(op)) },
+    Opcode{ pattern: 0xB000, mask: 0xF000, mnemonic: "JP", operands: &[Keyword("V0"), Addr], decode: |op, _| Instruction::JumpToV0PlusAddress(Cpu::get_nnn(op)) },
+    Opcode{ pattern: 0xC000, mask: 0xF000, mnemonic: "RND", operands: &[Vx, Byte], decode: |op, _| Instruction::SetVxToRandomAndByte(Cpu::get_x(op), Cpu::get_nn(op)) },
+    Opcode{ pattern: 0xD000, mask: 0xF000, mnemonic: "DRW", operands: &[Vx, Vy, Nibble], decode: |op, _| Instruction::DrawSprite(Cpu::get_x(op), Cpu::get_y(op), Cpu::get_nibble(op, 3)) },
+    Opcode{ pattern: 0xE09E, mask: 0xF0FF, mnemonic: "SKP", operands: &[Vx], decode: |op, _| Instruction::SkipIfKeyInVxPressed(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xE0A1, mask: 0xF0FF, mnemonic: "SKNP", operands: &[Vx], decode: |op, _| Instruction::SkipIfKeyInVxNotPressed(Cpu::get_x(op)) },
+
+    Opcode{ pattern: 0xF000, mask: 0xFFFF, mnemonic: "LD", operands: &[I, Long], decode: |_, next| Instruction::SetIToLongAddress(next) },
+    Opcode{ pattern: 0xF001, mask: 0xF0FF, mnemonic: "PLANE", operands: &[Planes], decode: |op, _| Instruction::SelectPlanes(Cpu::get_nibble(op, 1)) },
+    Opcode{ pattern: 0xF002, mask: 0xFFFF, mnemonic: "AUDIO", operands: &[], decode: |_, _| Instruction::LoadAudioPattern },
+    Opcode{ pattern: 0xF007, mask: 0xF0FF, mnemonic: "LD", operands: &[Vx, Keyword("DT")], decode: |op, _| Instruction::SetVxToDelayTimer(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF00A, mask: 0xF0FF, mnemonic: "LD", operands: &[Vx, Keyword("K")], decode: |op, _| Instruction::WaitForKeyPressAndStoreInVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF015, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("DT"), Vx], decode: |op, _| Instruction::SetDelayTimerToVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF018, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("ST"), Vx], decode: |op, _| Instruction::SetSoundTimerToVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF01E, mask: 0xF0FF, mnemonic: "ADD", operands: &[I, Vx], decode: |op, _| Instruction::AddVxToI(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF029, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("F"), Vx], decode: |op, _| Instruction::SetIToSpriteAddressForDigitVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF030, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("HF"), Vx], decode: |op, _| Instruction::SetIToBigSpriteAddressForDigitVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF033, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("B"), Vx], decode: |op, _| Instruction::StoreBcdOfVxAtI(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF03A, mask: 0xF0FF, mnemonic: "PITCH", operands: &[Vx], decode: |op, _| Instruction::SetPitchToVx(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF055, mask: 0xF0FF, mnemonic: "LD", operands: &[AT_I, Vx], decode: |op, _| Instruction::StoreRegistersV0ThroughVxInMemory(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF065, mask: 0xF0FF, mnemonic: "LD", operands: &[Vx, AT_I], decode: |op, _| Instruction::ReadRegistersV0ThroughVxFromMemory(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF075, mask: 0xF0FF, mnemonic: "LD", operands: &[Keyword("R"), Vx], decode: |op, _| Instruction::StoreRegistersV0ThroughVxInFlags(Cpu::get_x(op)) },
+    Opcode{ pattern: 0xF085, mask: 0xF0FF, mnemonic: "LD", operands: &[Vx, Keyword("R")], decode: |op, _| Instruction::ReadRegistersV0ThroughVxFromFlags(Cpu::get_x(op)) },
+];
+
+/// `V0`-`VF` in either case, as the assembler, the Octo compiler and the debugger write them.
+pub fn parse_register(text: &str) -> Option<usize>{
+
+    let digit = text.strip_prefix('V').or(text.strip_prefix('v'))?;
+    if digit.len() != 1{
+
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+/// Decimal, or hex with `0x`, as the debugger and its conditions take numbers.
+pub fn parse_number(text: &str) -> Result<usize, String>{
+
+    let parsed = match text.strip_prefix("0x").or(text.strip_prefix("0X")){
+
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a number", text))
+}
+
+pub fn lookup(opcode: u16) -> Option<&'static Opcode>{
+
+    OPCODES.iter().find(|entry| entry.matches(opcode))
+}
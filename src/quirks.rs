@@ -0,0 +1,208 @@
+use std::fmt;
+
+/// What FX55/FX65 leave in I after copying the registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore{
+
+    Unchanged,           // SUPER-CHIP 1.1
+    IncrementByX,        // CHIP-48 as documented: I += X
+    IncrementByXPlusOne, // COSMAC VIP: I += X + 1
+}
+
+/// The ambiguous opcodes, and how this machine should interpret them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks{
+
+    pub shift: bool,            // 8XY6/8XYE shift Vx in place and ignore Vy
+    pub load_store: LoadStore,  // What FX55/FX65 do to I
+    pub vf_reset: bool,         // 8XY1/8XY2/8XY3 reset VF to 0
+    pub jump: bool,             // BNNN is BXNN: jump to XNN + Vx instead of NNN + V0
+    pub clip: bool,             // Sprites are clipped at the screen edge instead of wrapping
+    pub display_wait: bool,     // DXYN waits for the next 60 Hz tick before execution continues
+}
+
+pub const PRESETS: [&str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+pub const QUIRK_NAMES: [&str; 6] = ["shift", "load-store", "vf-reset", "jump", "clip", "display-wait"];
+
+impl Default for Quirks{
+
+    fn default() -> Self{
+
+        Self::modern()
+    }
+}
+
+impl Quirks{
+
+    pub fn cosmac_vip() -> Quirks{
+
+        Quirks{ shift: false, load_store: LoadStore::IncrementByXPlusOne, vf_reset: true, jump: false, clip: true, display_wait: true }
+    }
+
+    pub fn chip48() -> Quirks{
+
+        Quirks{ shift: true, load_store: LoadStore::IncrementByX, vf_reset: false, jump: true, clip: true, display_wait: false }
+    }
+
+    pub fn superchip() -> Quirks{
+
+        Quirks{ shift: true, load_store: LoadStore::Unchanged, vf_reset: false, jump: true, clip: true, display_wait: false }
+    }
+
+    pub fn xochip() -> Quirks{
+
+        Quirks{ shift: false, load_store: LoadStore::IncrementByXPlusOne, vf_reset: false, jump: false, clip: false, display_wait: false }
+    }
+
+    // How RustChip has always behaved, kept as the default so existing ROMs don't change
+    pub fn modern() -> Quirks{
+
+        Quirks{ shift: false, load_store: LoadStore::Unchanged, vf_reset: false, jump: false, clip: false, display_wait: false }
+    }
+
+    pub fn preset(name: &str) -> Option<Quirks>{
+
+        match name.to_ascii_lowercase().as_str(){
+
+            "vip" | "cosmac-vip" | "chip8" | "chip-8" => Some(Self::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Self::superchip()),
+            "xochip" | "xo-chip" => Some(Self::xochip()),
+            "modern" => Some(Self::modern()),
+            _ => None,
+        }
+    }
+
+    // Applies a single override such as ("clip", "off") or ("load-store", "x+1")
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), QuirkError>{
+
+        if !QUIRK_NAMES.contains(&name){
+
+            return Err(QuirkError::UnknownQuirk(name.to_string()));
+        }
+        let invalid = || QuirkError::InvalidValue{ quirk: name.to_string(), value: value.to_string() };
+
+        if name == "load-store"{
+
+            self.load_store = match value{
+
+                "unchanged" | "off" | "false" | "0" => LoadStore::Unchanged,
+                "x" => LoadStore::IncrementByX,
+                "x+1" | "on" | "true" | "1" => LoadStore::IncrementByXPlusOne,
+                _ => return Err(invalid()),
+            };
+            return Ok(());
+        }
+
+        let flag = match value{
+
+            "on" | "true" | "1" => true,
+            "off" | "false" | "0" => false,
+            _ => return Err(invalid()),
+        };
+        match name{
+
+            "shift" => self.shift = flag,
+            "vf-reset" => self.vf_reset = flag,
+            "jump" => self.jump = flag,
+            "clip" => self.clip = flag,
+            "display-wait" => self.display_wait = flag,
+            _ => return Err(QuirkError::UnknownQuirk(name.to_string())),
+        }
+        Ok(())
+    }
+
+    // The value `set` takes for a quirk's current setting
+    pub fn get(&self, name: &str) -> Option<&'static str>{
+
+        let flag = |on: bool| if on { "on" } else { "off" };
+        match name{
+
+            "shift" => Some(flag(self.shift)),
+            "load-store" => Some(match self.load_store{
+
+                LoadStore::Unchanged => "unchanged",
+                LoadStore::IncrementByX => "x",
+                LoadStore::IncrementByXPlusOne => "x+1",
+            }),
+            "vf-reset" => Some(flag(self.vf_reset)),
+            "jump" => Some(flag(self.jump)),
+            "clip" => Some(flag(self.clip)),
+            "display-wait" => Some(flag(self.display_wait)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuirkError{
+
+    UnknownQuirk(String),
+    InvalidValue{ quirk: String, value: String },
+}
+
+impl fmt::Display for QuirkError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            QuirkError::UnknownQuirk(name) => write!(f, "unknown quirk '{}' (expected one of {})", name, QUIRK_NAMES.join(", ")),
+            QuirkError::InvalidValue{ quirk, value } => write!(f, "invalid value '{}' for quirk '{}'", value, quirk),
+        }
+    }
+}
+
+impl std::error::Error for QuirkError{}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    #[test]
+    fn set_and_get_every_quirk(){
+
+        let mut quirks = Quirks::modern();
+        for name in QUIRK_NAMES{
+
+            let value = if name == "load-store" { "x" } else { "on" };
+            quirks.set(name, value).unwrap();
+            assert_eq!(quirks.get(name), Some(value));
+        }
+        assert_eq!(quirks, Quirks{ shift: true, load_store: LoadStore::IncrementByX, vf_reset: true, jump: true, clip: true, display_wait: true });
+    }
+
+    #[test]
+    fn every_quirk_takes_the_same_flag_spellings(){
+
+        for name in QUIRK_NAMES{
+
+            let mut quirks = Quirks::modern();
+            for value in ["on", "true", "1"]{
+
+                quirks.set(name, value).unwrap();
+                let on = quirks.get(name);
+                quirks.set(name, "off").unwrap();
+                assert_ne!(quirks.get(name), on, "{}={}", name, value);
+            }
+            for value in ["off", "false", "0"]{
+
+                quirks.set(name, "on").unwrap();
+                let on = quirks.get(name);
+                quirks.set(name, value).unwrap();
+                assert_ne!(quirks.get(name), on, "{}={}", name, value);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_quirk_is_reported_before_its_value(){
+
+        let mut quirks = Quirks::modern();
+        assert_eq!(quirks.set("bogus", "x"), Err(QuirkError::UnknownQuirk("bogus".to_string())));
+        assert_eq!(quirks.set("clip", "x"), Err(QuirkError::InvalidValue{ quirk: "clip".to_string(), value: "x".to_string() }));
+        assert_eq!(quirks, Quirks::modern());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+
+// 30 seconds of history at 60 frames per second
+pub const DEFAULT_REWIND_FRAMES: usize = 30 * 60;
+
+/// Ring buffer of per-frame machine snapshots for stepping backwards in time.
+///
+/// Only the newest state is kept whole. Every older frame is stored as the XOR of
+/// itself with the frame after it, run-length encoded, so frames that barely
+/// change (most of RAM, most of the screen) cost a few bytes each.
+pub struct RewindBuffer{
+
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>, // Oldest first
+    capacity: usize,
+}
+
+impl RewindBuffer{
+
+    pub fn new(capacity: usize) -> RewindBuffer{
+
+        RewindBuffer{ latest: None, deltas: VecDeque::new(), capacity }
+    }
+
+    // Number of frames that can still be stepped back
+    pub fn len(&self) -> usize{
+
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self){
+
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn record(&mut self, machine: &Chip8){
+
+        let state = machine.save_state();
+        if let Some(latest) = self.latest.take(){
+
+            if latest.len() == state.len(){
+
+                self.deltas.push_back(encode_delta(&latest, &state));
+                if self.deltas.len() > self.capacity{
+
+                    self.deltas.pop_front();
+                }
+            }else{
+
+                // Can't happen for one machine, but a layout change would break the chain
+                self.deltas.clear();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Restores the frame before the most recently recorded one. Returns false when out of history.
+    pub fn step_back(&mut self, machine: &mut Chip8) -> bool{
+
+        let (Some(latest), Some(delta)) = (self.latest.as_mut(), self.deltas.pop_back()) else{
+
+            return false;
+        };
+        if apply_delta(latest, &delta).is_none(){
+
+            // Only a bug could get here, but the chain is broken from this frame back
+            self.clear();
+            return false;
+        }
+        // The history has already moved back a frame, so it can't be kept if the machine didn't
+        if machine.load_state(latest).is_err(){
+
+            self.clear();
+            return false;
+        }
+        true
+    }
+}
+
+// Encodes `old ^ new` as pairs of (zero run, literal run) lengths, each followed by the literals
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8>{
+
+    let diff: Vec<u8> = old.iter().zip(new).map(|(a, b)| a ^ b).collect();
+    let mut encoded = Vec::new();
+    let mut index = 0;
+
+    while index < diff.len(){
+
+        let zeros = diff[index..].iter().take_while(|&&byte| byte == 0).count();
+        index += zeros;
+        let literals = diff[index..].iter().take_while(|&&byte| byte != 0).count();
+        put_varint(&mut encoded, zeros);
+        put_varint(&mut encoded, literals);
+        encoded.extend_from_slice(&diff[index..index + literals]);
+        index += literals;
+    }
+    encoded
+}
+
+// XOR is its own inverse, so applying the delta to the newer state yields the older one.
+// None if the delta runs past either buffer, in which case `state` is partly changed.
+fn apply_delta(state: &mut [u8], delta: &[u8]) -> Option<()>{
+
+    let mut position = 0usize;
+    let mut cursor = 0;
+
+    while cursor < delta.len(){
+
+        position = position.checked_add(get_varint(delta, &mut cursor)?)?;
+        let literals = get_varint(delta, &mut cursor)?;
+        let bytes = state.get_mut(position..position.checked_add(literals)?)?;
+        let changes = delta.get(cursor..cursor + literals)?;
+        for (byte, &change) in bytes.iter_mut().zip(changes){
+
+            *byte ^= change;
+        }
+        position += literals;
+        cursor += literals;
+    }
+    Some(())
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: usize){
+
+    while value >= 0x80{
+
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// None if the varint is cut off or too long for a usize
+fn get_varint(bytes: &[u8], cursor: &mut usize) -> Option<usize>{
+
+    let mut value = 0;
+    let mut shift = 0;
+    loop{
+
+        let byte = *bytes.get(*cursor)?;
+        *cursor += 1;
+        if shift >= usize::BITS{
+
+            return None;
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0{
+
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+
+    // Counts in V0, draws it, rolls a random number, and goes round again
+    const PROGRAM: [u16; 5] = [0x7001, 0xF029, 0xD115, 0xC2FF, 0x1200];
+
+    fn machine() -> Chip8{
+
+        let mut machine = Chip8::from_opcodes(&PROGRAM);
+        machine.cpu_mut().set_seed(1);
+        machine
+    }
+
+    // Records `frames` frames after the starting one and returns every state along the way
+    fn record(machine: &mut Chip8, history: &mut RewindBuffer, frames: usize) -> Vec<Vec<u8>>{
+
+        let mut states = vec![machine.save_state()];
+        history.record(machine);
+        for _ in 0..frames{
+
+            machine.run_frame().unwrap();
+            history.record(machine);
+            states.push(machine.save_state());
+        }
+        states
+    }
+
+    #[test]
+    fn steps_back_to_identical_states(){
+
+        let mut machine = machine();
+        let mut history = RewindBuffer::new(DEFAULT_REWIND_FRAMES);
+        let states = record(&mut machine, &mut history, 20);
+        assert_eq!(history.len(), 20);
+
+        for expected in states.iter().rev().skip(1){
+
+            assert!(history.step_back(&mut machine));
+            assert_eq!(&machine.save_state(), expected);
+        }
+        assert!(history.is_empty());
+        assert!(!history.step_back(&mut machine));
+    }
+
+    #[test]
+    fn keeps_only_the_newest_frames_when_full(){
+
+        let mut machine = machine();
+        let mut history = RewindBuffer::new(5);
+        let states = record(&mut machine, &mut history, 12);
+        assert_eq!(history.len(), 5);
+
+        for expected in states[7..12].iter().rev(){
+
+            assert!(history.step_back(&mut machine));
+            assert_eq!(&machine.save_state(), expected);
+        }
+        assert!(!history.step_back(&mut machine));
+        assert_eq!(machine.save_state(), states[7]);
+    }
+
+    #[test]
+    fn recording_after_rewinding_continues_from_there(){
+
+        let mut machine = machine();
+        let mut history = RewindBuffer::new(DEFAULT_REWIND_FRAMES);
+        let states = record(&mut machine, &mut history, 6);
+        for _ in 0..3{
+
+            history.step_back(&mut machine);
+        }
+        machine.run_frame().unwrap();
+        history.record(&machine);
+        assert!(history.step_back(&mut machine));
+        assert_eq!(machine.save_state(), states[3]);
+    }
+
+    #[test]
+    fn a_failed_load_clears_the_history(){
+
+        let mut machine = machine();
+        let mut history = RewindBuffer::new(DEFAULT_REWIND_FRAMES);
+        record(&mut machine, &mut history, 4);
+
+        // A different ROM refuses the state, and the history can't stay a frame ahead of it
+        let mut other = Chip8::from_opcodes(&[0x1200]);
+        let before = other.save_state();
+        assert!(!history.step_back(&mut other));
+        assert_eq!(other.save_state(), before);
+        assert!(history.is_empty());
+        assert!(!history.step_back(&mut machine));
+    }
+
+    #[test]
+    fn delta_round_trip(){
+
+        // Runs longer than 127 bytes need multi-byte varints
+        let old: Vec<u8> = (0..1000).map(|index| (index % 7) as u8).collect();
+        let mut new = old.clone();
+        new[0] ^= 0xFF;
+        new[300] = 0xAA;
+        new[301] = 0xBB;
+        new[999] ^= 1;
+        let delta = encode_delta(&old, &new);
+        assert!(delta.len() < 20);
+
+        let mut state = new.clone();
+        assert_eq!(apply_delta(&mut state, &delta), Some(()));
+        assert_eq!(state, old);
+
+        // An unchanged frame is a single zero run
+        let unchanged = encode_delta(&old, &old);
+        assert_eq!(unchanged, vec![0xE8, 0x07, 0x00]);
+        assert_eq!(apply_delta(&mut state, &unchanged), Some(()));
+        assert_eq!(state, old);
+    }
+
+    #[test]
+    fn malformed_deltas_are_rejected(){
+
+        let mut state = vec![0; 16];
+        assert_eq!(apply_delta(&mut state, &[0x80]), None);         // Cut off varint
+        assert_eq!(apply_delta(&mut state, &[20, 1, 0xFF]), None);  // Past the end of the state
+        assert_eq!(apply_delta(&mut state, &[0, 4, 1, 2]), None);   // Fewer literals than promised
+        assert_eq!(apply_delta(&mut state, &[0xFF; 12]), None);     // Varint too long
+        let mut cursor = 0;
+        assert_eq!(get_varint(&[0xAC, 0x02], &mut cursor), Some(300));
+        assert_eq!(cursor, 2);
+    }
+}
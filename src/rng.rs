@@ -0,0 +1,41 @@
+//! The random number generator behind CXNN.
+//!
+//! It's xorshift64*, small enough to go in a save state and seeded explicitly, so the
+//! same seed and inputs always give the same run.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng{
+
+    state: u64, // Never zero, or xorshift would only ever return zero
+}
+
+impl Rng{
+
+    pub fn new(seed: u64) -> Rng{
+
+        // SplitMix64 spreads nearby seeds (0, 1, 2...) over the whole state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng{ state: z.max(1) }
+    }
+
+    pub fn next_byte(&mut self) -> u8{
+
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    pub(crate) fn state(&self) -> u64{
+
+        self.state
+    }
+
+    pub(crate) fn from_state(state: u64) -> Option<Rng>{
+
+        (state != 0).then_some(Rng{ state })
+    }
+}
@@ -0,0 +1,256 @@
+use std::{fmt, path::{Path, PathBuf}};
+
+// Layout: magic, version, ROM hash, frame counter, cycles into the frame, then the CPU (including memory),
+// display and keypad in that order. Bump STATE_VERSION whenever the layout changes.
+pub const STATE_MAGIC: [u8; 4] = *b"RCST";
+pub const STATE_VERSION: u16 = 3;
+pub const SLOT_COUNT: u8 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError{
+
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch{ expected: u64, found: u64 },
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError{
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+
+        match self{
+
+            StateError::BadMagic => write!(f, "not a RustChip save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported (expected {})", version, STATE_VERSION),
+            StateError::RomMismatch{ expected, found } => write!(f, "save state belongs to a different ROM (hash {:016X}, loaded ROM is {:016X})", found, expected),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt(what) => write!(f, "save state is corrupt: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StateError{}
+
+// Save states for `rom.ch8` live next to it as `rom.ch8.state0` .. `rom.ch8.state9`
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf{
+
+    let mut file_name = rom_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".state{}", slot));
+    rom_path.with_file_name(file_name)
+}
+
+/// Little-endian byte sink the machine components serialize themselves into.
+#[derive(Default)]
+pub struct StateWriter{
+
+    bytes: Vec<u8>,
+}
+
+impl StateWriter{
+
+    pub fn new() -> StateWriter{
+
+        StateWriter::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8>{
+
+        self.bytes
+    }
+
+    pub fn put_u8(&mut self, value: u8){
+
+        self.bytes.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool){
+
+        self.bytes.push(value as u8);
+    }
+
+    pub fn put_u16(&mut self, value: u16){
+
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32){
+
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64){
+
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]){
+
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// Cursor over a serialized state; every read fails cleanly on truncated input.
+pub struct StateReader<'a>{
+
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a>{
+
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a>{
+
+        StateReader{ bytes }
+    }
+
+    pub fn is_empty(&self) -> bool{
+
+        self.bytes.is_empty()
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError>{
+
+        if self.bytes.len() < len{
+
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, StateError>{
+
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, StateError>{
+
+        match self.get_u8()?{
+
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt("invalid boolean")),
+        }
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, StateError>{
+
+        Ok(u16::from_le_bytes(self.get_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, StateError>{
+
+        Ok(u32::from_le_bytes(self.get_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, StateError>{
+
+        Ok(u64::from_le_bytes(self.get_bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn get_array<const N: usize>(&mut self) -> Result<[u8; N], StateError>{
+
+        Ok(self.get_bytes(N)?.try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+
+    use super::*;
+    use crate::chip8::Chip8;
+
+    // Draws a font digit, starts both timers, rolls a random number and stores V0-V2 at 0x300
+    const PROGRAM: [u16; 10] = [0x600F, 0xF029, 0xD005, 0x6140, 0xF115, 0xF118, 0xC2FF, 0xA300, 0xF255, 0x1212];
+
+    fn running_machine() -> Chip8{
+
+        let mut machine = Chip8::from_opcodes(&PROGRAM);
+        machine.cpu_mut().set_seed(42);
+        for _ in 0..3{
+
+            machine.run_frame().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn save_and_load_round_trip(){
+
+        let mut original = running_machine();
+        let state = original.save_state();
+
+        let mut restored = Chip8::from_opcodes(&PROGRAM);
+        restored.cpu_mut().set_seed(7);
+        restored.load_state(&state).unwrap();
+
+        for index in 0..16{
+
+            assert_eq!(restored.cpu().get_register(index), original.cpu().get_register(index));
+        }
+        assert_eq!((restored.cpu().get_pc(), restored.cpu().get_i()), (original.cpu().get_pc(), original.cpu().get_i()));
+        for address in 0x300..0x303{
+
+            assert_eq!(restored.cpu().read_byte_from_mem(address), original.cpu().read_byte_from_mem(address));
+        }
+        assert_eq!(restored.display().get_buffer(), original.display().get_buffer());
+        assert_eq!(restored.display().get_pixel(15, 15), 1);
+        assert_eq!(restored.cpu().get_delay_timer(), original.cpu().get_delay_timer());
+        assert_eq!(restored.cpu().get_sound_timer(), original.cpu().get_sound_timer());
+        assert!(restored.cpu().get_delay_timer() < 0x40);
+        assert_eq!(restored.frame(), original.frame());
+        assert_eq!(restored.save_state(), state);
+
+        // Both generators carry on with the same numbers
+        for machine in [&mut original, &mut restored]{
+
+            machine.cpu_mut().set_pc(0x20C);
+            machine.step().unwrap();
+        }
+        assert_eq!(restored.cpu().get_register(2), original.cpu().get_register(2));
+    }
+
+    // Each failed load must leave the machine exactly as it was
+    fn assert_rejected(machine: &mut Chip8, state: &[u8], expected: StateError){
+
+        let before = machine.save_state();
+        assert_eq!(machine.load_state(state), Err(expected));
+        assert_eq!(machine.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_a_wrong_version(){
+
+        let mut state = running_machine().save_state();
+        state[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_rejected(&mut Chip8::from_opcodes(&PROGRAM), &state, StateError::UnsupportedVersion(STATE_VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_a_different_rom(){
+
+        let state = running_machine().save_state();
+        let mut other_program = PROGRAM;
+        other_program[0] = 0x600E;
+        let mut other = Chip8::from_opcodes(&other_program);
+        let expected = StateError::RomMismatch{ expected: other.rom_hash(), found: running_machine().rom_hash() };
+        assert_rejected(&mut other, &state, expected);
+    }
+
+    #[test]
+    fn rejects_a_truncated_or_padded_file(){
+
+        let state = running_machine().save_state();
+        let mut target = running_machine();
+        target.run_frame().unwrap();
+        assert_rejected(&mut target, &state[..state.len() - 1], StateError::Truncated);
+        assert_rejected(&mut target, &state[..3], StateError::Truncated);
+        assert_rejected(&mut target, b"NOPE and more", StateError::BadMagic);
+
+        let mut padded = state.clone();
+        padded.push(0);
+        assert_rejected(&mut target, &padded, StateError::Corrupt("trailing data"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Framebuffer dumps at the display's current resolution, one image pixel per CHIP-8 pixel.
+
+use std::io::Write;
+
+use crate::display::Display;
+
+/// Plain (P1) PBM, a row per line so dumps diff well. Any lit plane counts as black.
+pub fn pbm(display: &Display) -> String{
+
+    let mut text = format!("P1\n{} {}\n", display.width(), display.height());
+    for row in display.get_buffer(){
+
+        text.extend(row[..display.width()].iter().map(|&pixel| if pixel != 0 { '1' } else { '0' }));
+        text.push('\n');
+    }
+    text
+}
+
+/// `.` for unlit pixels, `#` for plane one and the hex colour index for the other XO-CHIP planes.
+pub fn ascii(display: &Display) -> String{
+
+    let mut text = String::new();
+    for row in display.get_buffer(){
+
+        text.extend(row[..display.width()].iter().map(|&pixel| match pixel{
+
+            0 => '.',
+            1 => '#',
+            _ => char::from_digit(pixel as u32, 16).unwrap_or('?'),
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+/// Indexed PNG, with `palette` giving the colour of each pixel value.
+pub fn write_png(display: &Display, palette: &[[u8; 4]; 16], writer: impl Write) -> Result<(), png::EncodingError>{
+
+    let mut encoder = png::Encoder::new(writer, display.width() as u32, display.height() as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.iter().flat_map(|colour| colour[..3].to_vec()).collect::<Vec<u8>>());
+
+    let pixels: Vec<u8> = display.get_buffer().iter().flat_map(|row| row[..display.width()].to_vec()).collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()
+}
@@ -0,0 +1,20 @@
+//! Fixtures shared by the unit tests.
+
+use crate::chip8::Chip8;
+
+/// Opcodes as ROM bytes, big-endian as CHIP-8 stores them.
+pub fn rom(opcodes: &[u16]) -> Vec<u8>{
+
+    opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+}
+
+impl Chip8{
+
+    /// A fresh machine with `opcodes` loaded at 0x200.
+    pub fn from_opcodes(opcodes: &[u16]) -> Chip8{
+
+        let mut machine = Chip8::new();
+        machine.load_rom(&rom(opcodes)).unwrap();
+        machine
+    }
+}
@@ -0,0 +1,102 @@
+//! Runs the test ROMs in the repository root for a fixed number of frames, or through an
+//! input movie, and compares the screen with the PBM checked in under `tests/golden`. After an intended change, update the
+//! images with `BLESS=1 cargo test --test golden` and review them in the diff.
+
+use std::{env, fs, path::{Path, PathBuf}};
+
+use rustchip::{Chip8, Quirks, StepOutcome, movie::{Movie, Playback}, screenshot};
+
+const CYCLES_PER_FRAME: u32 = 20;
+
+fn root() -> &'static Path{
+
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn check(rom: &str, frames: u32, quirks: Quirks){
+
+    let mut machine = Chip8::with_quirks(quirks);
+    machine.set_cycles_per_frame(CYCLES_PER_FRAME);
+    machine.cpu_mut().set_seed(0);
+    machine.load_rom(&fs::read(root().join(rom)).unwrap()).unwrap();
+    for _ in 0..frames{
+
+        if machine.run_frame().unwrap() == StepOutcome::Exited{
+
+            break;
+        }
+    }
+    compare(&Path::new(rom).with_extension("pbm"), &machine);
+}
+
+// Plays `tests/golden/<name>.movie` into its ROM and compares the screen with `<name>.pbm`
+fn check_movie(rom: &str, name: &str){
+
+    let movie = Movie::parse(&fs::read_to_string(root().join("tests/golden").join(name).with_extension("movie")).unwrap()).unwrap();
+    let mut machine = Chip8::new();
+    machine.load_rom(&fs::read(root().join(rom)).unwrap()).unwrap();
+    movie.start(&mut machine).unwrap();
+
+    let frames = movie.frames;
+    let mut playback = Playback::new(movie);
+    for _ in 0..frames{
+
+        playback.apply(&mut machine);
+        if machine.run_frame().unwrap() == StepOutcome::Exited{
+
+            break;
+        }
+    }
+    compare(&Path::new(name).with_extension("pbm"), &machine);
+}
+
+fn compare(name: &Path, machine: &Chip8){
+
+    let actual = screenshot::pbm(machine.display());
+    let expected_path = root().join("tests/golden").join(name);
+    if env::var_os("BLESS").is_some(){
+
+        fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&expected_path)
+        .unwrap_or_else(|e| panic!("no golden image {} ({}), create it with BLESS=1", expected_path.display(), e));
+    if actual != expected{
+
+        let actual_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+        fs::write(&actual_path, &actual).unwrap();
+        panic!("{} doesn't match {}\nthe screen was saved to {}, run with BLESS=1 if the change is intended\n\n{}",
+            name.display(), expected_path.display(), actual_path.display(), screenshot::ascii(machine.display()));
+    }
+}
+
+#[test]
+fn ibm_logo(){
+
+    check("ibm_logo.ch8", 30, Quirks::default());
+}
+
+#[test]
+fn chip8_logo(){
+
+    check("chip8_logo.ch8", 60, Quirks::default());
+}
+
+#[test]
+fn corax_plus(){
+
+    check("corax_plus.ch8", 60, Quirks::default());
+}
+
+#[test]
+fn flags(){
+
+    check("flags.ch8", 60, Quirks::default());
+}
+
+#[test]
+fn pong_movie(){
+
+    check_movie("PONG.ch8", "pong");
+}